@@ -17,7 +17,7 @@ sed -i '' '/^\[build\]/,/^\[/s/^target = .*/# target disabled for tests/' .cargo
 
 # Run tests
 echo "Running tests..."
-cargo test --lib history_lib --release "$@"
+cargo test --lib history --release "$@"
 TEST_RESULT=$?
 
 # Restore the original config
@@ -1,6 +1,16 @@
+use crate::audit::Source;
+use crate::calibration::{Job, Sampler};
+use crate::events::Event;
 use crate::gui::HistoryType;
+use crate::history::{FlowStats, Status};
+use crate::totals::Totals;
+use bitflags::bitflags;
 use time::PrimitiveDateTime;
 
+/// Firmware version, shown on the Version screen and in the Modbus
+/// device identification
+pub const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[derive(Debug, Copy, Clone)]
 pub enum Actions {
     Label,
@@ -21,6 +31,8 @@ pub enum AppRequest {
     LcdLed(bool),
     SetDateTime(PrimitiveDateTime),
     SetHistory(HistoryType, u32),
+    /// Load event `index` places back from the newest into `App::event_state`
+    SetEvent(u32),
     DeepSleep,
     SetCommType(u8),
     SetAddress(u8),
@@ -29,15 +41,61 @@ pub enum AppRequest {
     ExitShell,
     SystemReset,
     EnterCalibration,
+    /// Start the resettable total over from the current total
+    ResetTotals,
+    /// Measure and save the zero offsets, as `zero` on the shell
+    AutoZero,
+    SaveOptions,
+    /// Options were written: bring the load profile to its interval and depth
+    ProfileOptions,
+}
+
+bitflags! {
+    /// Meter status, read over Modbus as discrete inputs (bit n = input n)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct MeterStatus: u16 {
+        /// Last TDC measurement timed out, overflowed or could not be read
+        const TDC_ERROR = 1 << 0;
+        const REVERSE_FLOW = 1 << 1;
+        /// Not measured yet, always clear
+        const LOW_BATTERY = 1 << 2;
+        /// Options came from the other page or from factory defaults at boot
+        const OPTIONS_FALLBACK = 1 << 3;
+        /// Auto-zero or auto-calibration measuring
+        const CALIBRATING = 1 << 4;
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct HistoryState {
     pub history_type: HistoryType,
     pub flow: Option<f32>,
+    /// Status of the record in `flow`
+    pub status: Status,
+    /// Min/max flow of the period, for rings that keep them
+    pub stats: Option<FlowStats>,
     pub datetime: u32,
 }
 
+/// Auto-zero or auto-calibration waiting for measurements
+#[derive(Debug)]
+pub struct Calibrating {
+    pub job: Job,
+    /// Who asked, for the audit log and the reply
+    pub source: Source,
+    pub sampler: Sampler,
+}
+
+/// Event shown on the event log screen
+#[derive(Debug, Default)]
+pub struct EventState {
+    /// 0 = newest
+    pub index: u32,
+    /// Events held by the log
+    pub count: u32,
+    pub event: Option<Event>,
+}
+
 pub struct App {
     pub text: &'static str,
     pub num: u64,
@@ -45,10 +103,29 @@ pub struct App {
     pub label_value: alloc::string::String,
     pub datetime: PrimitiveDateTime,
     pub flow: f32,
+    /// Channel the next TDC measurement belongs to
+    pub channel: usize,
+    /// Latest delta TOF per channel (ns)
+    pub dtof: [i32; 2],
+    /// Latest uncalibrated flow per channel (m³/h)
+    pub raw_flow: [f32; 2],
+    pub calibrating: Option<Calibrating>,
+    /// Flow accumulated since commissioning
+    pub total_flow: f32,
+    /// `total_flow` at the last totals reset
+    pub rest_flow: f32,
     pub hour_flow: f32,
     pub day_flow: f32,
     pub month_flow: f32,
+    /// Flow accumulated in the current load-profile interval
+    pub profile_flow: f32,
+    /// Flow extremes in the current hour and day
+    pub hour_stats: FlowStats,
+    pub day_stats: FlowStats,
     pub history_state: HistoryState,
+    pub event_state: EventState,
+    /// Latched status bits; `status()` adds the ones read off the flow
+    pub alarms: MeterStatus,
 }
 
 impl App {
@@ -60,20 +137,93 @@ impl App {
             label_value: alloc::string::String::from("123456"),
             datetime: time::macros::datetime!(2023-01-01 00:00:00),
             flow: 0.0,
+            channel: 0,
+            dtof: [0; 2],
+            raw_flow: [0.0; 2],
+            calibrating: None,
+            total_flow: 0.0,
+            rest_flow: 0.0,
             hour_flow: 0.0,
             day_flow: 0.0,
             month_flow: 0.0,
+            profile_flow: 0.0,
+            hour_stats: FlowStats::EMPTY,
+            day_stats: FlowStats::EMPTY,
             history_state: HistoryState {
                 history_type: HistoryType::Hour,
                 flow: Some(0.0),
+                status: Status::Valid,
+                stats: None,
                 datetime: 0,
             },
+            event_state: EventState::default(),
+            alarms: MeterStatus::empty(),
         }
     }
 
     pub fn handle_event(&mut self, _action: Option<Actions>) -> Option<AppRequest> {
         None
     }
+
+    /// Add the current flow to the running totals
+    pub fn accumulate(&mut self) {
+        self.total_flow += self.flow;
+        self.hour_flow += self.flow;
+        self.day_flow += self.flow;
+        self.month_flow += self.flow;
+        self.profile_flow += self.flow;
+    }
+
+    /// Take a new measurement of `channel`. True once a pending
+    /// calibration has all its samples.
+    pub fn record_measurement(&mut self, channel: usize, dtof: i32, raw_flow: f32) -> bool {
+        if channel < 2 {
+            self.dtof[channel] = dtof;
+            self.raw_flow[channel] = raw_flow;
+        }
+        self.calibrating
+            .as_mut()
+            .is_some_and(|calibrating| calibrating.sampler.push(channel, dtof, raw_flow))
+    }
+
+    /// Start the resettable total over from the current total
+    pub fn reset_totals(&mut self) {
+        self.rest_flow = self.total_flow;
+    }
+
+    /// Flow accumulated since the last totals reset
+    pub fn rest_total(&self) -> f32 {
+        self.total_flow - self.rest_flow
+    }
+
+    /// Running totals for a checkpoint
+    pub fn totals(&self) -> Totals {
+        Totals {
+            total: self.total_flow,
+            hour: self.hour_flow,
+            day: self.day_flow,
+            month: self.month_flow,
+            profile: self.profile_flow,
+            rest: self.rest_flow,
+        }
+    }
+
+    pub fn status(&self) -> MeterStatus {
+        let mut status = self.alarms;
+        status.set(MeterStatus::REVERSE_FLOW, self.flow < 0.0);
+        status.set(MeterStatus::CALIBRATING, self.calibrating.is_some());
+        status
+    }
+
+    /// Continue from the totals of a checkpoint
+    pub fn restore_totals(&mut self, totals: &Totals) {
+        self.total_flow = totals.total;
+        self.hour_flow = totals.hour;
+        self.day_flow = totals.day;
+        self.month_flow = totals.month;
+        self.profile_flow = totals.profile;
+        self.rest_flow = totals.rest;
+    }
 }
 
 impl Default for App {
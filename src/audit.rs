@@ -0,0 +1,470 @@
+#![allow(dead_code)]
+
+//! Metrological audit trail
+//!
+//! Every change to a calibration or metrology field of `Options` (sensor type,
+//! zero, V and K) is stored with the old and new value, where it came from and
+//! when. The log is append-only and never wraps: once it is full, changes to
+//! these fields are refused. The audit counter is the number of changes
+//! recorded; each record carries its own counter value and CRC, so `new()`
+//! finds the end of the log by scanning for the first record that does not
+//! continue the sequence. A record torn by a power cut is overwritten by the
+//! next change.
+
+use crate::history::Error;
+use crate::options::Options;
+use embedded_storage::Storage;
+
+type Result<T> = core::result::Result<T, Error>;
+
+/// Audited `Options` field, stored as a `u8` (0 = none)
+#[cfg_attr(not(test), derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditField {
+    SensorType,
+    Zero1,
+    Zero2,
+    V11,
+    V12,
+    V13,
+    V21,
+    V22,
+    V23,
+    K11,
+    K12,
+    K13,
+    K21,
+    K22,
+    K23,
+}
+
+impl AuditField {
+    pub const ALL: [AuditField; 15] = [
+        AuditField::SensorType,
+        AuditField::Zero1,
+        AuditField::Zero2,
+        AuditField::V11,
+        AuditField::V12,
+        AuditField::V13,
+        AuditField::V21,
+        AuditField::V22,
+        AuditField::V23,
+        AuditField::K11,
+        AuditField::K12,
+        AuditField::K13,
+        AuditField::K21,
+        AuditField::K22,
+        AuditField::K23,
+    ];
+
+    pub fn to_u8(self) -> u8 {
+        self as u8 + 1
+    }
+
+    pub fn from_u8(code: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(code).checked_sub(1)?).copied()
+    }
+
+    /// Short name for the shell listing
+    pub fn name(self) -> &'static str {
+        match self {
+            AuditField::SensorType => "sensor_type",
+            AuditField::Zero1 => "zero1",
+            AuditField::Zero2 => "zero2",
+            AuditField::V11 => "v11",
+            AuditField::V12 => "v12",
+            AuditField::V13 => "v13",
+            AuditField::V21 => "v21",
+            AuditField::V22 => "v22",
+            AuditField::V23 => "v23",
+            AuditField::K11 => "k11",
+            AuditField::K12 => "k12",
+            AuditField::K13 => "k13",
+            AuditField::K21 => "k21",
+            AuditField::K22 => "k22",
+            AuditField::K23 => "k23",
+        }
+    }
+
+    /// Raw value of the field in `options`
+    pub fn value(self, options: &Options) -> u32 {
+        match self {
+            AuditField::SensorType => options.sensor_type() as u32,
+            AuditField::Zero1 => options.zero1(),
+            AuditField::Zero2 => options.zero2(),
+            AuditField::V11 => options.v11(),
+            AuditField::V12 => options.v12(),
+            AuditField::V13 => options.v13(),
+            AuditField::V21 => options.v21(),
+            AuditField::V22 => options.v22(),
+            AuditField::V23 => options.v23(),
+            AuditField::K11 => options.k11(),
+            AuditField::K12 => options.k12(),
+            AuditField::K13 => options.k13(),
+            AuditField::K21 => options.k21(),
+            AuditField::K22 => options.k22(),
+            AuditField::K23 => options.k23(),
+        }
+    }
+
+    /// Fields that differ between `old` and `new`
+    pub fn changed<'a>(old: &'a Options, new: &'a Options) -> impl Iterator<Item = Self> + 'a {
+        Self::ALL
+            .into_iter()
+            .filter(move |field| field.value(old) != field.value(new))
+    }
+}
+
+/// Where a change came from, stored as a `u8` (0 = none)
+#[cfg_attr(not(test), derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Modbus = 1,
+    Shell = 2,
+    Ui = 3,
+}
+
+impl Source {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Source::Modbus),
+            2 => Some(Source::Shell),
+            3 => Some(Source::Ui),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Modbus => "modbus",
+            Source::Shell => "shell",
+            Source::Ui => "ui",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Audit counter after this change, 1 for the first one
+    pub counter: u32,
+    /// Unix time from the RTC
+    pub time: u32,
+    pub field: AuditField,
+    pub source: Source,
+    pub old: u32,
+    pub new: u32,
+}
+
+/// Counter u32, time u32, field u8, source u8, old u32, new u32, CRC u16
+const RECORD_SIZE: usize = 20;
+const RECORD_CRC: usize = RECORD_SIZE - 2;
+
+/// Append-only log of `SIZE` changes at `OFFSET` from the statistics page
+pub struct AuditLog<const OFFSET: usize, const SIZE: u32> {
+    /// Changes recorded so far, also the next free slot
+    count: u32,
+}
+
+impl<const OFFSET: usize, const SIZE: u32> AuditLog<OFFSET, SIZE> {
+    const OFFSET_OF_STAT_PAGE: usize = 4096;
+    const OFFSET: usize = Self::OFFSET_OF_STAT_PAGE + OFFSET;
+    pub const SIZE_ON_FLASH: usize = SIZE as usize * RECORD_SIZE;
+
+    /// Open the log, continuing after the last record of the sequence
+    pub fn new<S: Storage>(storage: &mut S) -> Result<Self> {
+        let mut log = Self::new_empty();
+        while log.count < SIZE {
+            match log.read_slot(storage, log.count)? {
+                Some(record) if record.counter == log.count + 1 => log.count += 1,
+                _ => break,
+            }
+        }
+        Ok(log)
+    }
+
+    pub const fn new_empty() -> Self {
+        Self { count: 0 }
+    }
+
+    /// A log that refuses every change, for when the stored one can't be read
+    pub const fn new_full() -> Self {
+        Self { count: SIZE }
+    }
+
+    /// Audit counter: changes recorded since the log was created
+    pub fn counter(&self) -> u32 {
+        self.count
+    }
+
+    /// Changes that can still be recorded
+    pub fn remaining(&self) -> u32 {
+        SIZE - self.count
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count >= SIZE
+    }
+
+    /// Record every audited field that differs between `old` and `new`.
+    /// Nothing is written unless all of them fit (`Error::Full`).
+    pub fn record<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        time: u32,
+        source: Source,
+        old: &Options,
+        new: &Options,
+    ) -> Result<()> {
+        if AuditField::changed(old, new).count() as u32 > self.remaining() {
+            return Err(Error::Full);
+        }
+        for field in AuditField::changed(old, new) {
+            let record = AuditRecord {
+                counter: self.count + 1,
+                time,
+                field,
+                source,
+                old: field.value(old),
+                new: field.value(new),
+            };
+            self.write_slot(storage, self.count, &record)?;
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    /// Change `index` places back from the newest (0 = newest)
+    pub fn get<S: Storage>(&self, storage: &mut S, index: u32) -> Result<Option<AuditRecord>> {
+        if index >= self.count {
+            return Ok(None);
+        }
+        self.read_slot(storage, self.count - 1 - index)
+    }
+
+    fn write_slot<S: Storage>(
+        &self,
+        storage: &mut S,
+        slot: u32,
+        record: &AuditRecord,
+    ) -> Result<()> {
+        let mut bytes = [0_u8; RECORD_SIZE];
+        bytes[..4].copy_from_slice(&record.counter.to_le_bytes());
+        bytes[4..8].copy_from_slice(&record.time.to_le_bytes());
+        bytes[8] = record.field.to_u8();
+        bytes[9] = record.source.to_u8();
+        bytes[10..14].copy_from_slice(&record.old.to_le_bytes());
+        bytes[14..18].copy_from_slice(&record.new.to_le_bytes());
+        let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&bytes[..RECORD_CRC]);
+        bytes[RECORD_CRC..].copy_from_slice(&crc.to_le_bytes());
+        storage
+            .write(Self::slot_offset(slot), &bytes)
+            .map_err(|_| Error::Storage)
+    }
+
+    fn read_slot<S: Storage>(&self, storage: &mut S, slot: u32) -> Result<Option<AuditRecord>> {
+        let mut bytes = [0_u8; RECORD_SIZE];
+        storage
+            .read(Self::slot_offset(slot), &mut bytes)
+            .map_err(|_| Error::Storage)?;
+        let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&bytes[..RECORD_CRC]);
+        if crc != u16::from_le_bytes([bytes[RECORD_CRC], bytes[RECORD_CRC + 1]]) {
+            return Ok(None);
+        }
+        let word = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        let (Some(field), Some(source)) =
+            (AuditField::from_u8(bytes[8]), Source::from_u8(bytes[9]))
+        else {
+            return Ok(None);
+        };
+        Ok(Some(AuditRecord {
+            counter: word(0),
+            time: word(4),
+            field,
+            source,
+            old: word(10),
+            new: word(14),
+        }))
+    }
+
+    fn slot_offset(slot: u32) -> u32 {
+        (Self::OFFSET + slot as usize * RECORD_SIZE) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_storage::MemStorage;
+
+    type Log = AuditLog<0, 4>;
+
+    const TIME: u32 = 1_700_000_000;
+
+    fn with_zero1(zero1: u32) -> Options {
+        let mut options = Options::default();
+        options.set_zero1(zero1);
+        options
+    }
+
+    #[test]
+    fn test_empty_log() {
+        let mut storage = MemStorage::new();
+        let log = Log::new(&mut storage).unwrap();
+        assert_eq!(log.counter(), 0);
+        assert_eq!(log.remaining(), 4);
+        assert_eq!(log.get(&mut storage, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_records_only_audited_changes() {
+        let mut storage = MemStorage::new();
+        let mut log = Log::new(&mut storage).unwrap();
+        let old = Options::default();
+        let mut new = old;
+        new.set_slave_address(7);
+        log.record(&mut storage, TIME, Source::Modbus, &old, &new)
+            .unwrap();
+        assert_eq!(log.counter(), 0);
+
+        new.set_sensor_type(3);
+        new.set_k12(0x3F80_0000);
+        log.record(&mut storage, TIME, Source::Shell, &old, &new)
+            .unwrap();
+        assert_eq!(log.counter(), 2);
+        assert_eq!(
+            log.get(&mut storage, 0).unwrap(),
+            Some(AuditRecord {
+                counter: 2,
+                time: TIME,
+                field: AuditField::K12,
+                source: Source::Shell,
+                old: 0,
+                new: 0x3F80_0000,
+            })
+        );
+        let first = log.get(&mut storage, 1).unwrap().unwrap();
+        assert_eq!((first.counter, first.field), (1, AuditField::SensorType));
+        assert_eq!((first.old, first.new), (0, 3));
+    }
+
+    #[test]
+    fn test_counter_survives_reopen() {
+        let mut storage = MemStorage::new();
+        let mut log = Log::new(&mut storage).unwrap();
+        for zero1 in 1..4 {
+            let old = with_zero1(zero1 - 1);
+            log.record(
+                &mut storage,
+                TIME + zero1,
+                Source::Ui,
+                &old,
+                &with_zero1(zero1),
+            )
+            .unwrap();
+        }
+        let log = Log::new(&mut storage).unwrap();
+        assert_eq!(log.counter(), 3);
+        let newest = log.get(&mut storage, 0).unwrap().unwrap();
+        assert_eq!((newest.counter, newest.time, newest.new), (3, TIME + 3, 3));
+    }
+
+    #[test]
+    fn test_refuses_changes_when_full() {
+        let mut storage = MemStorage::new();
+        let mut log = Log::new(&mut storage).unwrap();
+        for zero1 in 1..4 {
+            let old = with_zero1(zero1 - 1);
+            log.record(&mut storage, TIME, Source::Modbus, &old, &with_zero1(zero1))
+                .unwrap();
+        }
+
+        // Two changes don't fit in the last slot: nothing is written
+        let old = with_zero1(3);
+        let mut new = with_zero1(4);
+        new.set_zero2(1);
+        assert!(matches!(
+            log.record(&mut storage, TIME, Source::Modbus, &old, &new),
+            Err(Error::Full)
+        ));
+        assert_eq!(log.counter(), 3);
+
+        log.record(&mut storage, TIME, Source::Modbus, &old, &with_zero1(4))
+            .unwrap();
+        assert!(log.is_full());
+        assert!(matches!(
+            log.record(&mut storage, TIME, Source::Modbus, &old, &with_zero1(5)),
+            Err(Error::Full)
+        ));
+
+        // Unaudited changes still go through
+        let mut other = old;
+        other.set_comm_type(2);
+        log.record(&mut storage, TIME, Source::Modbus, &old, &other)
+            .unwrap();
+
+        let log = Log::new(&mut storage).unwrap();
+        assert!(log.is_full());
+        assert!(Log::new_full().is_full());
+    }
+
+    #[test]
+    fn test_power_cut_during_record() {
+        let mut image = MemStorage::new();
+        let mut log = Log::new(&mut image).unwrap();
+        log.record(
+            &mut image,
+            TIME,
+            Source::Modbus,
+            &with_zero1(0),
+            &with_zero1(1),
+        )
+        .unwrap();
+        for cut in 0..RECORD_SIZE {
+            let mut storage = image.clone();
+            let mut log = Log::new(&mut storage).unwrap();
+            storage.written = 0;
+            storage.power_cut_after = Some(cut);
+            assert!(log
+                .record(
+                    &mut storage,
+                    TIME,
+                    Source::Modbus,
+                    &with_zero1(1),
+                    &with_zero1(2)
+                )
+                .is_err());
+            storage.power_cut_after = None;
+
+            // The torn record is not counted and the next change replaces it
+            let mut log = Log::new(&mut storage).unwrap();
+            assert_eq!(log.counter(), 1, "cut at {}", cut);
+            log.record(
+                &mut storage,
+                TIME,
+                Source::Modbus,
+                &with_zero1(1),
+                &with_zero1(3),
+            )
+            .unwrap();
+            let log = Log::new(&mut storage).unwrap();
+            assert_eq!(log.counter(), 2);
+            assert_eq!(log.get(&mut storage, 0).unwrap().unwrap().new, 3);
+        }
+    }
+
+    #[test]
+    fn test_field_codes() {
+        assert_eq!(AuditField::from_u8(0), None);
+        for field in AuditField::ALL {
+            assert_eq!(AuditField::from_u8(field.to_u8()), Some(field));
+        }
+        assert_eq!(AuditField::from_u8(16), None);
+        assert_eq!(Source::from_u8(Source::Ui.to_u8()), Some(Source::Ui));
+    }
+}
@@ -11,6 +11,21 @@
 
 #![allow(dead_code)]
 
+use crate::options::Options;
+use heapless::Vec;
+
+/// Largest zero offset accepted (nanoseconds)
+pub const DTOF0_MAX: f32 = 1000.0;
+/// Largest calibration flow accepted (m³/h)
+pub const V_MAX: f32 = 1000.0;
+/// Accepted range of the correction ratio
+pub const K_MIN: f32 = 0.5;
+pub const K_MAX: f32 = 2.0;
+
+/// A calibration value is outside the accepted range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRange;
+
 /// Single calibration data point
 #[cfg_attr(not(test), derive(defmt::Format))]
 #[derive(Debug, Clone, Copy, Default)]
@@ -31,6 +46,30 @@ pub struct CalibTable {
     pub data: [CalibData; 3],
 }
 
+impl CalibTable {
+    /// All values finite and in range, and the calibrated points in
+    /// ascending flow order. A point that is all zeros has not been
+    /// calibrated yet and is skipped.
+    pub fn is_valid(&self) -> bool {
+        if !self.dtof0.is_finite() || self.dtof0.abs() > DTOF0_MAX {
+            return false;
+        }
+        let mut last_v = 0.0;
+        for point in &self.data {
+            if point.v == 0.0 && point.k == 0.0 {
+                continue;
+            }
+            // Written so that NaN fails
+            let v_in_range = point.v > last_v && point.v <= V_MAX;
+            if !v_in_range || !(K_MIN..=K_MAX).contains(&point.k) {
+                return false;
+            }
+            last_v = point.v;
+        }
+        true
+    }
+}
+
 /// Meter configuration limits
 #[cfg_attr(not(test), derive(defmt::Format))]
 #[derive(Debug, Clone, Copy)]
@@ -141,58 +180,151 @@ impl Calculator {
     }
 }
 
-/// Auto-zero calibration: measure 10 samples with no flow to determine dTOF0
-/// Returns the zero offset for both channels
-pub fn auto_zero(measure_fn: impl Fn() -> (i32, i32), // (delta_ch0, delta_ch1) in ns
-) -> [f32; 2] {
-    let mut delta: [i32; 2] = [0, 0];
+/// Measurements averaged by auto-zero and auto-calibration
+pub const SAMPLES: usize = 10;
+
+/// Fewer than `SAMPLES` fresh measurements, or a channel read all zeros
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoSignal;
+
+/// What the collected samples are for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Job {
+    /// Zero offsets of both channels
+    Zero,
+    /// Point `coef_no` (1-3) at reference flow `vet` (m³/h)
+    Point(u8, f32),
+}
+
+/// Collects `SAMPLES` successive measurements of both channels. A sample
+/// is taken once each channel has been measured again since the last one,
+/// so no measurement is counted twice.
+#[derive(Debug, Default)]
+pub struct Sampler {
+    /// Latest (dtof, raw flow) per channel not yet in a sample
+    pending: [Option<(i32, f32)>; 2],
+    dtof: Vec<[i32; 2], SAMPLES>,
+    raw_flow: Vec<[f32; 2], SAMPLES>,
+}
 
-    for _ in 0..10 {
-        let (d0, d1) = measure_fn();
-        delta[0] += d0;
-        delta[1] += d1;
+impl Sampler {
+    /// Add a new measurement of `channel`. True once `SAMPLES` samples are in.
+    pub fn push(&mut self, channel: usize, dtof: i32, raw_flow: f32) -> bool {
+        if let Some(slot) = self.pending.get_mut(channel) {
+            *slot = Some((dtof, raw_flow));
+        }
+        if let [Some((dtof0, flow0)), Some((dtof1, flow1))] = self.pending {
+            self.pending = [None; 2];
+            self.dtof.push([dtof0, dtof1]).ok();
+            self.raw_flow.push([flow0, flow1]).ok();
+        }
+        self.is_full()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.dtof.is_full()
     }
 
-    [delta[0] as f32 / 10_000.0, delta[1] as f32 / 10_000.0]
+    pub fn zero(&self) -> Result<[f32; 2], NoSignal> {
+        auto_zero(&self.dtof)
+    }
+
+    pub fn point(&self, coef_no: u8, vet: f32) -> Result<[CalibData; 2], NoSignal> {
+        auto_calibrate(coef_no, vet, &self.raw_flow)
+    }
+}
+
+/// Auto-zero calibration: average `SAMPLES` delta TOFs (ns) taken with no
+/// flow to determine dTOF0 of both channels
+pub fn auto_zero(samples: &[[i32; 2]]) -> Result<[f32; 2], NoSignal> {
+    if samples.len() < SAMPLES {
+        return Err(NoSignal);
+    }
+    let mut delta: [i64; 2] = [0, 0];
+    let mut signal = [false; 2];
+
+    for sample in samples {
+        for channel in 0..2 {
+            delta[channel] += i64::from(sample[channel]);
+            signal[channel] |= sample[channel] != 0;
+        }
+    }
+    if signal.contains(&false) {
+        return Err(NoSignal);
+    }
+
+    let scale = samples.len() as f32 * 1000.0;
+    Ok([delta[0] as f32 / scale, delta[1] as f32 / scale])
 }
 
 /// Auto-calibration for a specific coefficient (1-3)
-/// Takes 10 measurements with known reference flow (vet in m³/h)
-/// Returns updated CalibData for the given coefficient index
+/// Averages `SAMPLES` raw flows (m³/h) taken at a known reference flow
+/// (vet in m³/h). Returns updated CalibData for the given coefficient index
 pub fn auto_calibrate(
-    _coef_no: u8,                      // 1, 2, or 3 (reserved for logging)
-    vet: f32,                          // reference flow m³/h
-    measure_fn: impl Fn() -> [f32; 2], // [vm_raw_ch0, vm_raw_ch1]
-) -> [CalibData; 2] {
+    _coef_no: u8, // 1, 2, or 3 (reserved for logging)
+    vet: f32,     // reference flow m³/h
+    samples: &[[f32; 2]],
+) -> Result<[CalibData; 2], NoSignal> {
+    if samples.len() < SAMPLES {
+        return Err(NoSignal);
+    }
     let mut vm_raw: [f32; 2] = [0.0, 0.0];
 
-    for _ in 0..10 {
-        let m = measure_fn();
-        vm_raw[0] += m[0];
-        vm_raw[1] += m[1];
+    for sample in samples {
+        vm_raw[0] += sample[0];
+        vm_raw[1] += sample[1];
     }
 
-    vm_raw[0] /= 10.0;
-    vm_raw[1] /= 10.0;
+    vm_raw[0] /= samples.len() as f32;
+    vm_raw[1] /= samples.len() as f32;
+    if vm_raw.iter().any(|vm| vm.abs() <= f32::EPSILON) {
+        return Err(NoSignal);
+    }
 
-    [
+    Ok([
         CalibData {
             v: vm_raw[0],
-            k: if vm_raw[0].abs() > f32::EPSILON {
-                vet / vm_raw[0]
-            } else {
-                1.0
-            },
+            k: vet / vm_raw[0],
         },
         CalibData {
             v: vm_raw[1],
-            k: if vm_raw[1].abs() > f32::EPSILON {
-                vet / vm_raw[1]
-            } else {
-                1.0
-            },
+            k: vet / vm_raw[1],
         },
-    ]
+    ])
+}
+
+/// Store `auto_zero` results as the zero offsets of both channels.
+/// On error `options` is left unchanged.
+pub fn store_zero(options: &mut Options, zero: [f32; 2]) -> Result<(), OutOfRange> {
+    let mut updated = *options;
+    for (channel, dtof0) in zero.into_iter().enumerate() {
+        let mut table = updated.calib_table(channel)?;
+        table.dtof0 = dtof0;
+        updated.set_calib_table(channel, &table)?;
+    }
+    *options = updated;
+    Ok(())
+}
+
+/// Store `auto_calibrate` results as point `coef_no` (1-3) of both channels.
+/// On error `options` is left unchanged.
+pub fn store_point(
+    options: &mut Options,
+    coef_no: u8,
+    data: [CalibData; 2],
+) -> Result<(), OutOfRange> {
+    let index = match coef_no {
+        1..=3 => coef_no as usize - 1,
+        _ => return Err(OutOfRange),
+    };
+    let mut updated = *options;
+    for (channel, point) in data.into_iter().enumerate() {
+        let mut table = updated.calib_table(channel)?;
+        table.data[index] = point;
+        updated.set_calib_table(channel, &table)?;
+    }
+    *options = updated;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -300,22 +432,58 @@ mod tests {
 
     #[test]
     fn test_auto_zero() {
-        // Each call returns (100, 200), 10 calls summed = (1000, 2000)
-        // 1000 / 10000 = 0.1, 2000 / 10000 = 0.2
-        let result = auto_zero(|| (100, 200));
+        // Average (100, 200) / 1000 = (0.1, 0.2)
+        let result = auto_zero(&[[100, 200]; SAMPLES]).unwrap();
         assert!((result[0] - 0.1).abs() < 0.01);
         assert!((result[1] - 0.2).abs() < 0.01);
     }
 
     #[test]
     fn test_auto_calibrate() {
-        let result = auto_calibrate(1, 150.0, || [100.0, 120.0]);
+        let result = auto_calibrate(1, 150.0, &[[100.0, 120.0]; SAMPLES]).unwrap();
         assert!((result[0].v - 100.0).abs() < 0.001);
         assert!((result[0].k - 1.5).abs() < 0.01); // 150/100
         assert!((result[1].v - 120.0).abs() < 0.001);
         assert!((result[1].k - 1.25).abs() < 0.01); // 150/120
     }
 
+    #[test]
+    fn test_no_signal_is_refused() {
+        assert_eq!(auto_zero(&[[100, 200]; SAMPLES - 1]), Err(NoSignal));
+        assert_eq!(auto_zero(&[[0, 0]; SAMPLES]), Err(NoSignal));
+        // One dead channel is enough
+        assert_eq!(auto_zero(&[[100, 0]; SAMPLES]), Err(NoSignal));
+        assert!(matches!(
+            auto_calibrate(1, 150.0, &[[0.0, 120.0]; SAMPLES]),
+            Err(NoSignal)
+        ));
+        assert!(matches!(
+            auto_calibrate(1, 150.0, &[[100.0, 120.0]; 1]),
+            Err(NoSignal)
+        ));
+    }
+
+    #[test]
+    fn test_sampler_takes_fresh_measurements() {
+        let mut sampler = Sampler::default();
+        // Channel 1 never measured again: its value is not reused
+        sampler.push(1, 200, 120.0);
+        for _ in 0..2 * SAMPLES {
+            assert!(!sampler.push(0, 100, 100.0));
+        }
+        assert_eq!(sampler.zero(), Err(NoSignal));
+
+        for n in 1..SAMPLES {
+            assert!(!sampler.push(0, 100 + n as i32, 100.0));
+            assert_eq!(sampler.push(1, 200, 120.0), n == SAMPLES - 1);
+        }
+        let zero = sampler.zero().unwrap();
+        assert!((zero[0] - 0.1045).abs() < 0.001);
+        assert!((zero[1] - 0.2).abs() < 0.001);
+        let point = sampler.point(1, 150.0).unwrap();
+        assert!((point[1].k - 1.25).abs() < 0.01);
+    }
+
     #[test]
     fn test_get_raw_volume() {
         let calc = Calculator::new(default_config());
@@ -336,4 +504,65 @@ mod tests {
         let result = calc.get_raw_volume(&table, 100100.0, 100000.0);
         assert!(result.abs() < 0.01);
     }
+
+    #[test]
+    fn test_table_validation() {
+        assert!(default_table().is_valid());
+        // Blank options: nothing calibrated yet
+        assert!(CalibTable::default().is_valid());
+
+        let mut table = default_table();
+        table.data[2] = CalibData::default();
+        assert!(table.is_valid());
+
+        for (dtof0, v, k) in [
+            (f32::NAN, 100.0, 1.0),
+            (DTOF0_MAX + 1.0, 100.0, 1.0),
+            (0.0, f32::INFINITY, 1.0),
+            (0.0, -100.0, 1.0),
+            (0.0, V_MAX + 1.0, 1.0),
+            (0.0, 100.0, K_MIN - 0.1),
+            (0.0, 100.0, K_MAX + 0.1),
+            (0.0, 100.0, f32::NAN),
+            // Out of order with point 2 at 300
+            (0.0, 400.0, 1.0),
+        ] {
+            let mut table = default_table();
+            table.dtof0 = dtof0;
+            table.data[0] = CalibData { v, k };
+            assert!(!table.is_valid(), "{} {} {}", dtof0, v, k);
+        }
+    }
+
+    #[test]
+    fn test_store_auto_zero() {
+        let mut options = Options::default();
+        store_zero(&mut options, auto_zero(&[[100, 200]; SAMPLES]).unwrap()).unwrap();
+        assert!((options.calib_table(0).unwrap().dtof0 - 0.1).abs() < 0.001);
+        assert!((options.calib_table(1).unwrap().dtof0 - 0.2).abs() < 0.001);
+
+        // Both channels or neither
+        let before = options.into_bytes();
+        assert_eq!(store_zero(&mut options, [0.5, f32::NAN]), Err(OutOfRange));
+        assert_eq!(options.into_bytes(), before);
+    }
+
+    #[test]
+    fn test_store_auto_calibrate() {
+        let mut options = Options::default();
+        let data = auto_calibrate(2, 150.0, &[[100.0, 120.0]; SAMPLES]).unwrap();
+        store_point(&mut options, 2, data).unwrap();
+        let table = options.calib_table(0).unwrap();
+        assert!((table.data[1].v - 100.0).abs() < 0.001);
+        assert!((table.data[1].k - 1.5).abs() < 0.01);
+        assert_eq!(table.data[0].v, 0.0);
+        assert!((options.calib_table(1).unwrap().data[1].k - 1.25).abs() < 0.01);
+
+        // Point 1 above point 2 is refused
+        let before = options.into_bytes();
+        let data = auto_calibrate(1, 150.0, &[[110.0, 110.0]; SAMPLES]).unwrap();
+        assert_eq!(store_point(&mut options, 1, data), Err(OutOfRange));
+        assert_eq!(store_point(&mut options, 4, data), Err(OutOfRange));
+        assert_eq!(options.into_bytes(), before);
+    }
 }
@@ -0,0 +1,380 @@
+//! Options as `name=value` text, for backing up and cloning meter setups
+//!
+//! `config dump` prints one line per `Options` field followed by
+//! `crc=XXXX`, the CRC-16/CCITT-FALSE of the field lines, each taken with a
+//! `\n` ending whatever line ending the terminal used. `config load` takes the
+//! same lines back through `ConfigLoad`.
+//!
+//! Every field is listed once in `FIELDS`; the page CRC is the only one left
+//! out. A test checks that the table covers every bit of `Options`, so a new
+//! field cannot be forgotten.
+
+#![allow(dead_code)]
+
+use crate::options::{Options, FACTORY_DEFAULTS};
+use core::fmt::Write;
+use crc16::{State, CCITT_FALSE};
+use heapless::String;
+
+/// A dump line, long enough for any f32 written out in full
+pub type Line = String<80>;
+
+/// How a field is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Decimal integer
+    Uint,
+    /// f32 stored as its bits
+    Float,
+    /// Register image, `0x` and hex digits
+    Hex,
+}
+
+pub struct Field {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub bits: u32,
+    get: fn(&Options) -> u128,
+    set: fn(&mut Options, u128),
+}
+
+macro_rules! field {
+    ($name:ident, $kind:ident, $bits:expr) => {
+        paste::paste! {
+            Field {
+                name: stringify!($name),
+                kind: Kind::$kind,
+                bits: $bits,
+                get: |options| options.$name() as u128,
+                set: |options, value| options.[<set_ $name>](value as _),
+            }
+        }
+    };
+}
+
+/// Every `Options` field but the page CRC, in layout order
+pub const FIELDS: [Field; 30] = [
+    field!(serial_number, Uint, 32),
+    field!(sensor_type, Uint, 8),
+    field!(tdc1000_regs, Hex, 80),
+    field!(tdc7200_regs, Hex, 80),
+    field!(zero1, Float, 32),
+    field!(zero2, Float, 32),
+    field!(v11, Float, 32),
+    field!(v12, Float, 32),
+    field!(v13, Float, 32),
+    field!(v21, Float, 32),
+    field!(v22, Float, 32),
+    field!(v23, Float, 32),
+    field!(k11, Float, 32),
+    field!(k12, Float, 32),
+    field!(k13, Float, 32),
+    field!(k21, Float, 32),
+    field!(k22, Float, 32),
+    field!(k23, Float, 32),
+    field!(uptime, Uint, 32),
+    field!(total, Uint, 32),
+    field!(hour_total, Uint, 32),
+    field!(day_total, Uint, 32),
+    field!(month_total, Uint, 32),
+    field!(rest, Uint, 32),
+    field!(enable_negative, Uint, 8),
+    field!(slave_address, Uint, 8),
+    field!(comm_type, Uint, 8),
+    field!(modbus_mode, Uint, 8),
+    field!(profile_interval, Uint, 8),
+    field!(profile_depth, Uint, 16),
+];
+
+/// Name of the closing line
+const CRC: &str = "crc";
+
+/// Why `config load` refused the text
+#[cfg_attr(not(test), derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// Not a `name=value` line
+    Syntax,
+    UnknownField,
+    Duplicate,
+    /// Value does not parse or does not fit the field
+    Value,
+    /// `crc=` came before every field was given
+    Missing,
+    Crc,
+    /// Calibration or sensor type out of range
+    Invalid,
+}
+
+impl ConfigError {
+    pub fn message(self) -> &'static str {
+        match self {
+            ConfigError::Syntax => "expected name=value",
+            ConfigError::UnknownField => "unknown field",
+            ConfigError::Duplicate => "field given twice",
+            ConfigError::Value => "bad value",
+            ConfigError::Missing => "fields missing",
+            ConfigError::Crc => "CRC mismatch",
+            ConfigError::Invalid => "config out of range",
+        }
+    }
+}
+
+/// `config dump` lines: the fields, then `crc=XXXX`
+pub struct Dump {
+    options: Options,
+    index: usize,
+    crc: State<CCITT_FALSE>,
+}
+
+pub fn dump(options: &Options) -> Dump {
+    Dump {
+        options: *options,
+        index: 0,
+        crc: State::new(),
+    }
+}
+
+impl Iterator for Dump {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Line> {
+        let mut line = Line::new();
+        match FIELDS.get(self.index) {
+            Some(field) => {
+                write_value(&mut line, field, (field.get)(&self.options));
+                self.crc.update(line.as_bytes());
+                self.crc.update(b"\n");
+            }
+            None if self.index == FIELDS.len() => {
+                write!(line, "{}={:04X}", CRC, self.crc.get()).ok();
+            }
+            None => return None,
+        }
+        self.index += 1;
+        line.push_str("\r\n").ok();
+        Some(line)
+    }
+}
+
+fn write_value(line: &mut Line, field: &Field, value: u128) {
+    match field.kind {
+        Kind::Uint => write!(line, "{}={}", field.name, value),
+        Kind::Float => write!(line, "{}={}", field.name, f32::from_bits(value as u32)),
+        Kind::Hex => write!(
+            line,
+            "{}=0x{:02$X}",
+            field.name,
+            value,
+            field.bits as usize / 4
+        ),
+    }
+    .ok();
+}
+
+fn parse_value(field: &Field, text: &str) -> Option<u128> {
+    let value = match field.kind {
+        Kind::Uint => text.parse::<u128>().ok()?,
+        Kind::Float => text.parse::<f32>().ok()?.to_bits() as u128,
+        Kind::Hex => u128::from_str_radix(text.strip_prefix("0x")?, 16).ok()?,
+    };
+    (value >> field.bits == 0).then_some(value)
+}
+
+/// `config load` in progress: feed it the dump lines one by one
+pub struct ConfigLoad {
+    options: Options,
+    /// Bit n set once `FIELDS[n]` was given
+    seen: u64,
+    crc: State<CCITT_FALSE>,
+}
+
+// One bit of `ConfigLoad::seen` per field, with room for the all-seen mask
+const _: () = assert!(FIELDS.len() < u64::BITS as usize);
+
+impl ConfigLoad {
+    /// Start a load over `current`. The serial number is checked against the
+    /// CRC but not taken, so a cloned setup keeps the meter's own.
+    pub fn new(current: &Options) -> Self {
+        Self {
+            options: *current,
+            seen: 0,
+            crc: State::new(),
+        }
+    }
+
+    /// Take one line. Returns the new options once the `crc=` line checks out.
+    pub fn feed(&mut self, line: &[u8]) -> Result<Option<Options>, ConfigError> {
+        let line = core::str::from_utf8(line)
+            .map_err(|_| ConfigError::Syntax)?
+            .trim_end();
+        let (name, value) = line.split_once('=').ok_or(ConfigError::Syntax)?;
+
+        if name == CRC {
+            let crc = u16::from_str_radix(value, 16).map_err(|_| ConfigError::Value)?;
+            if self.seen != (1_u64 << FIELDS.len()) - 1 {
+                return Err(ConfigError::Missing);
+            }
+            if crc != self.crc.get() {
+                return Err(ConfigError::Crc);
+            }
+            return self.validate().map(Some);
+        }
+
+        let index = FIELDS
+            .iter()
+            .position(|field| field.name == name)
+            .ok_or(ConfigError::UnknownField)?;
+        if self.seen & (1 << index) != 0 {
+            return Err(ConfigError::Duplicate);
+        }
+        let field = &FIELDS[index];
+        let value = parse_value(field, value).ok_or(ConfigError::Value)?;
+        if field.name != "serial_number" {
+            (field.set)(&mut self.options, value);
+        }
+        self.seen |= 1 << index;
+        self.crc.update(line.as_bytes());
+        self.crc.update(b"\n");
+        Ok(None)
+    }
+
+    fn validate(&self) -> Result<Options, ConfigError> {
+        let calibrated = (0..2).all(|channel| {
+            self.options
+                .calib_table(channel)
+                .is_ok_and(|table| table.is_valid())
+        });
+        if !calibrated || self.options.sensor_type() as usize >= FACTORY_DEFAULTS.len() {
+            return Err(ConfigError::Invalid);
+        }
+        Ok(self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::{CalibData, CalibTable};
+
+    fn configured() -> Options {
+        let mut options = Options::factory(2);
+        options.set_serial_number(1234);
+        options.set_slave_address(17);
+        options.set_profile_depth(960);
+        options
+            .set_calib_table(
+                1,
+                &CalibTable {
+                    dtof0: -0.125,
+                    data: [
+                        CalibData { v: 1.5, k: 1.02 },
+                        CalibData { v: 8.0, k: 0.98 },
+                        CalibData { v: 40.0, k: 1.01 },
+                    ],
+                },
+            )
+            .unwrap();
+        options
+    }
+
+    fn load(target: &Options, lines: &[Line]) -> Result<Option<Options>, ConfigError> {
+        let mut load = ConfigLoad::new(target);
+        let mut result = Ok(None);
+        for line in lines {
+            result = load.feed(line.as_bytes());
+            if !matches!(result, Ok(None)) {
+                break;
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_table_covers_every_field() {
+        let bits: u32 = FIELDS.iter().map(|field| field.bits).sum();
+        // Plus the 16-bit page CRC
+        assert_eq!(bits + 16, core::mem::size_of::<Options>() as u32 * 8);
+
+        // Each entry reaches its own bits
+        for (index, field) in FIELDS.iter().enumerate() {
+            let mut options = Options::new();
+            let max = u128::MAX >> (128 - field.bits);
+            (field.set)(&mut options, max);
+            assert_eq!((field.get)(&options), max, "{}", field.name);
+            for (other, other_field) in FIELDS.iter().enumerate() {
+                if other != index {
+                    assert_eq!((other_field.get)(&options), 0, "{}", other_field.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dump() {
+        let lines: std::vec::Vec<Line> = dump(&configured()).collect();
+        assert_eq!(lines.len(), FIELDS.len() + 1);
+        assert_eq!(lines[0].as_str(), "serial_number=1234\r\n");
+        assert_eq!(lines[1].as_str(), "sensor_type=2\r\n");
+        assert_eq!(lines[2].as_str(), "tdc1000_regs=0x001A0000001F0B00407F\r\n");
+        assert!(lines.contains(&Line::try_from("zero2=-0.125\r\n").unwrap()));
+        assert!(lines.contains(&Line::try_from("k21=1.02\r\n").unwrap()));
+        assert!(lines.contains(&Line::try_from("slave_address=17\r\n").unwrap()));
+        assert!(lines[FIELDS.len()].starts_with("crc="));
+    }
+
+    #[test]
+    fn test_dump_load_round_trip() {
+        let source = configured();
+        let lines: std::vec::Vec<Line> = dump(&source).collect();
+
+        // Onto another meter: everything but the serial number is taken
+        let mut target = Options::factory(0);
+        target.set_serial_number(99);
+        let loaded = load(&target, &lines).unwrap().unwrap();
+        assert_eq!(loaded.serial_number(), 99);
+        let mut expected = source;
+        expected.set_serial_number(99);
+        assert_eq!(loaded.into_bytes()[2..], expected.into_bytes()[2..]);
+
+        // Line endings do not matter
+        let mut load = ConfigLoad::new(&target);
+        for line in &lines[..FIELDS.len()] {
+            let line = line.trim_end();
+            assert!(matches!(load.feed(line.as_bytes()), Ok(None)));
+        }
+        assert!(load.feed(lines[FIELDS.len()].as_bytes()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_load_refuses_bad_text() {
+        let target = Options::factory(0);
+        let lines: std::vec::Vec<Line> = dump(&configured()).collect();
+        let with = |index: usize, text: &str| {
+            let mut lines = lines.clone();
+            lines[index] = Line::try_from(text).unwrap();
+            load(&target, &lines)
+        };
+
+        assert_eq!(with(1, "sensor_type=3").err(), Some(ConfigError::Crc));
+        assert_eq!(with(1, "sensor_type").err(), Some(ConfigError::Syntax));
+        assert_eq!(with(1, "sensor=2").err(), Some(ConfigError::UnknownField));
+        assert_eq!(
+            with(1, "serial_number=1234").err(),
+            Some(ConfigError::Duplicate)
+        );
+        assert_eq!(with(1, "sensor_type=256").err(), Some(ConfigError::Value));
+        assert_eq!(with(2, "tdc1000_regs=1A").err(), Some(ConfigError::Value));
+        assert_eq!(with(6, "v11=fast").err(), Some(ConfigError::Value));
+
+        let mut short = lines.clone();
+        short.remove(3);
+        assert_eq!(load(&target, &short).err(), Some(ConfigError::Missing));
+
+        // Values that parse but fail validation, with a matching CRC
+        let mut bad = configured();
+        bad.set_k21(5.0_f32.to_bits());
+        let lines: std::vec::Vec<Line> = dump(&bad).collect();
+        assert_eq!(load(&target, &lines).err(), Some(ConfigError::Invalid));
+    }
+}
@@ -0,0 +1,314 @@
+#![allow(dead_code)]
+
+//! Event / alarm log
+//!
+//! Append-only ring of events (TDC errors, options CRC failures, clock
+//! changes, config writes, resets) kept in EEPROM next to the history rings.
+//! Every record carries a sequence number and its own CRC, so the log needs
+//! no header: `new()` scans the records and continues after the highest
+//! sequence number. A write cut by a power loss leaves a record with a bad
+//! CRC, which reads as missing.
+
+use crate::history::Error;
+use embedded_storage::Storage;
+
+type Result<T> = core::result::Result<T, Error>;
+
+/// What happened. Stored as a `u16`; codes from newer firmware read as `Unknown`.
+#[cfg_attr(not(test), derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventCode {
+    /// Power-up, param = reset flags (RCC_CSR >> 24)
+    PowerUp,
+    /// Reset by the independent watchdog
+    WatchdogReset,
+    /// An options page failed its CRC or has a newer layout and was
+    /// rewritten from the other, param 0 primary, 1 secondary
+    OptionsPageCrc,
+    /// No valid options page, defaults loaded
+    OptionsLoadFailed,
+    /// History ring could not be opened, param from `history_id`
+    HistoryInit,
+    /// History record could not be stored, param from `history_id`
+    HistoryWrite,
+    /// RTC set, param = time before the change
+    ClockChanged,
+    /// Options written over Modbus, param = first register
+    ConfigWrite,
+    TdcTimeout,
+    TdcOverflow,
+    /// SPI error talking to the TDC1000/TDC7200
+    TdcBus,
+    /// History ring emptied because its interval changed, param from `history_id`
+    HistoryCleared,
+    /// Auto-zero or auto-calibration not saved, param 0 zero, 1-3 point
+    CalibrationFailed,
+    Unknown(u16),
+}
+
+impl EventCode {
+    pub fn to_u16(self) -> u16 {
+        match self {
+            EventCode::PowerUp => 1,
+            EventCode::WatchdogReset => 2,
+            EventCode::OptionsPageCrc => 3,
+            EventCode::OptionsLoadFailed => 4,
+            EventCode::HistoryInit => 5,
+            EventCode::HistoryWrite => 6,
+            EventCode::ClockChanged => 7,
+            EventCode::ConfigWrite => 8,
+            EventCode::TdcTimeout => 9,
+            EventCode::TdcOverflow => 10,
+            EventCode::TdcBus => 11,
+            EventCode::HistoryCleared => 12,
+            EventCode::CalibrationFailed => 13,
+            EventCode::Unknown(code) => code,
+        }
+    }
+
+    /// Short name for the shell listing
+    pub fn name(self) -> &'static str {
+        match self {
+            EventCode::PowerUp => "power_up",
+            EventCode::WatchdogReset => "watchdog_reset",
+            EventCode::OptionsPageCrc => "options_crc",
+            EventCode::OptionsLoadFailed => "options_load",
+            EventCode::HistoryInit => "history_init",
+            EventCode::HistoryWrite => "history_write",
+            EventCode::ClockChanged => "clock_changed",
+            EventCode::ConfigWrite => "config_write",
+            EventCode::TdcTimeout => "tdc_timeout",
+            EventCode::TdcOverflow => "tdc_overflow",
+            EventCode::TdcBus => "tdc_bus",
+            EventCode::HistoryCleared => "history_cleared",
+            EventCode::CalibrationFailed => "calibration_failed",
+            EventCode::Unknown(_) => "unknown",
+        }
+    }
+
+    pub fn from_u16(code: u16) -> Self {
+        match code {
+            1 => EventCode::PowerUp,
+            2 => EventCode::WatchdogReset,
+            3 => EventCode::OptionsPageCrc,
+            4 => EventCode::OptionsLoadFailed,
+            5 => EventCode::HistoryInit,
+            6 => EventCode::HistoryWrite,
+            7 => EventCode::ClockChanged,
+            8 => EventCode::ConfigWrite,
+            9 => EventCode::TdcTimeout,
+            10 => EventCode::TdcOverflow,
+            11 => EventCode::TdcBus,
+            12 => EventCode::HistoryCleared,
+            13 => EventCode::CalibrationFailed,
+            code => EventCode::Unknown(code),
+        }
+    }
+}
+
+/// Param of `HistoryInit` / `HistoryWrite`
+pub mod history_id {
+    pub const HOUR: u32 = 0;
+    pub const DAY: u32 = 1;
+    pub const MONTH: u32 = 2;
+    pub const PROFILE: u32 = 3;
+    pub const HOUR_STATS: u32 = 4;
+    pub const DAY_STATS: u32 = 5;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// Unix time from the RTC
+    pub time: u32,
+    pub code: EventCode,
+    pub param: u32,
+}
+
+/// Sequence u32, time u32, code u16, param u32, CRC u16
+const RECORD_SIZE: usize = 16;
+const RECORD_CRC: usize = RECORD_SIZE - 2;
+
+/// Ring of `SIZE` events at `OFFSET` from the statistics page
+pub struct EventLog<const OFFSET: usize, const SIZE: u32> {
+    /// Sequence number of the next event, i.e. events logged so far
+    next: u32,
+}
+
+impl<const OFFSET: usize, const SIZE: u32> EventLog<OFFSET, SIZE> {
+    const OFFSET_OF_STAT_PAGE: usize = 4096;
+    const OFFSET: usize = Self::OFFSET_OF_STAT_PAGE + OFFSET;
+    pub const SIZE_ON_FLASH: usize = SIZE as usize * RECORD_SIZE;
+
+    /// Open the log, continuing after the newest valid record
+    pub fn new<S: Storage>(storage: &mut S) -> Result<Self> {
+        let mut log = Self::new_empty();
+        for slot in 0..SIZE {
+            if let Some((sequence, _)) = log.read_slot(storage, slot)? {
+                if sequence >= log.next {
+                    log.next = sequence + 1;
+                }
+            }
+        }
+        Ok(log)
+    }
+
+    pub const fn new_empty() -> Self {
+        Self { next: 0 }
+    }
+
+    /// Events logged since the log was created, including overwritten ones
+    pub fn sequence(&self) -> u32 {
+        self.next
+    }
+
+    /// Events still held by the ring
+    pub fn len(&self) -> u32 {
+        self.next.min(SIZE)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next == 0
+    }
+
+    /// Store an event, overwriting the oldest one when the ring is full
+    pub fn append<S: Storage>(&mut self, storage: &mut S, event: Event) -> Result<()> {
+        let mut record = [0_u8; RECORD_SIZE];
+        record[..4].copy_from_slice(&self.next.to_le_bytes());
+        record[4..8].copy_from_slice(&event.time.to_le_bytes());
+        record[8..10].copy_from_slice(&event.code.to_u16().to_le_bytes());
+        record[10..14].copy_from_slice(&event.param.to_le_bytes());
+        let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&record[..RECORD_CRC]);
+        record[RECORD_CRC..].copy_from_slice(&crc.to_le_bytes());
+        storage
+            .write(Self::slot_offset(self.next % SIZE), &record)
+            .map_err(|_| Error::Storage)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Event `index` places back from the newest (0 = newest).
+    /// `None` past the oldest event, or for a record lost to a power cut.
+    pub fn get<S: Storage>(&self, storage: &mut S, index: u32) -> Result<Option<Event>> {
+        if index >= self.len() {
+            return Ok(None);
+        }
+        let sequence = self.next - 1 - index;
+        Ok(self
+            .read_slot(storage, sequence % SIZE)?
+            .filter(|(stored, _)| *stored == sequence)
+            .map(|(_, event)| event))
+    }
+
+    fn read_slot<S: Storage>(&self, storage: &mut S, slot: u32) -> Result<Option<(u32, Event)>> {
+        let mut record = [0_u8; RECORD_SIZE];
+        storage
+            .read(Self::slot_offset(slot), &mut record)
+            .map_err(|_| Error::Storage)?;
+        let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&record[..RECORD_CRC]);
+        if crc != u16::from_le_bytes([record[RECORD_CRC], record[RECORD_CRC + 1]]) {
+            return Ok(None);
+        }
+        let word = |at: usize| [record[at], record[at + 1], record[at + 2], record[at + 3]];
+        Ok(Some((
+            u32::from_le_bytes(word(0)),
+            Event {
+                time: u32::from_le_bytes(word(4)),
+                code: EventCode::from_u16(u16::from_le_bytes([record[8], record[9]])),
+                param: u32::from_le_bytes(word(10)),
+            },
+        )))
+    }
+
+    fn slot_offset(slot: u32) -> u32 {
+        (Self::OFFSET + slot as usize * RECORD_SIZE) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_storage::MemStorage;
+
+    type Log = EventLog<0, 8>;
+
+    fn event(n: u32) -> Event {
+        Event {
+            time: 1_700_000_000 + n * 60,
+            code: EventCode::TdcTimeout,
+            param: n,
+        }
+    }
+
+    fn params(log: &Log, storage: &mut MemStorage) -> std::vec::Vec<u32> {
+        (0..log.len())
+            .filter_map(|i| log.get(storage, i).unwrap().map(|e| e.param))
+            .collect()
+    }
+
+    #[test]
+    fn test_empty_log() {
+        let mut storage = MemStorage::new();
+        let log = Log::new(&mut storage).unwrap();
+        assert!(log.is_empty());
+        assert_eq!(log.get(&mut storage, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_append_and_reopen() {
+        let mut storage = MemStorage::new();
+        let mut log = Log::new(&mut storage).unwrap();
+        for n in 0..3 {
+            log.append(&mut storage, event(n)).unwrap();
+        }
+        let log = Log::new(&mut storage).unwrap();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.get(&mut storage, 0).unwrap(), Some(event(2)));
+        assert_eq!(params(&log, &mut storage), [2, 1, 0]);
+    }
+
+    #[test]
+    fn test_wraps_keeping_newest() {
+        let mut storage = MemStorage::new();
+        let mut log = Log::new(&mut storage).unwrap();
+        for n in 0..11 {
+            log.append(&mut storage, event(n)).unwrap();
+        }
+        let log = Log::new(&mut storage).unwrap();
+        assert_eq!(log.sequence(), 11);
+        assert_eq!(log.len(), 8);
+        assert_eq!(params(&log, &mut storage), [10, 9, 8, 7, 6, 5, 4, 3]);
+        assert_eq!(log.get(&mut storage, 8).unwrap(), None);
+    }
+
+    #[test]
+    fn test_power_cut_during_append() {
+        let mut image = MemStorage::new();
+        let mut log = Log::new(&mut image).unwrap();
+        for n in 0..9 {
+            log.append(&mut image, event(n)).unwrap();
+        }
+        for cut in 0..RECORD_SIZE {
+            let mut storage = image.clone();
+            let mut log = Log::new(&mut storage).unwrap();
+            storage.written = 0;
+            storage.power_cut_after = Some(cut);
+            assert!(log.append(&mut storage, event(9)).is_err());
+            storage.power_cut_after = None;
+
+            // The torn record is dropped, older events stay readable
+            let mut log = Log::new(&mut storage).unwrap();
+            let kept = params(&log, &mut storage);
+            assert_eq!(kept[..7], [8, 7, 6, 5, 4, 3, 2], "cut at {}", cut);
+            log.append(&mut storage, event(10)).unwrap();
+            assert_eq!(log.get(&mut storage, 0).unwrap(), Some(event(10)));
+        }
+    }
+
+    #[test]
+    fn test_event_codes() {
+        for code in 0..16 {
+            assert_eq!(EventCode::from_u16(code).to_u16(), code);
+        }
+        assert_eq!(EventCode::from_u16(0x1234), EventCode::Unknown(0x1234));
+    }
+}
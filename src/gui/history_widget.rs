@@ -1,5 +1,6 @@
 use crate::gui::date_time_widget::DateTimeItems;
 use crate::gui::{CharacterDisplay, Edit, Label, UiEvent, Widget};
+use crate::history::Status;
 
 #[cfg_attr(not(test), derive(defmt::Format))]
 #[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
@@ -8,6 +9,8 @@ pub enum HistoryType {
     Hour,
     Day,
     Month,
+    /// Load profile (interval set in `Options`)
+    Profile,
 }
 use crate::alloc::string::ToString;
 use crate::Actions;
@@ -193,7 +196,7 @@ impl<K: HistoryKind> HistoryWidget<K> {
     pub fn next_item(&mut self) -> bool {
         match self.items {
             DateTimeItems::None => match K::history_type() {
-                HistoryType::Hour => {
+                HistoryType::Hour | HistoryType::Profile => {
                     self.time.blink_mask(0x18);
                     self.time.set_editable(true);
                     self.items = DateTimeItems::Hours;
@@ -274,7 +277,8 @@ impl<K: HistoryKind> Widget<&App, Actions> for HistoryWidget<K> {
         defmt::debug!("HistoryWidget date.editable={}, date.invalidate={}, time.editable={}, time.invalidate={}",
             self.date.editable, self.date.invalidate, self.time.editable, self.time.invalidate);
 
-        if let Some(flow) = state.history_state.flow {
+        let metered = state.history_state.status != Status::GapFilled;
+        if let Some(flow) = state.history_state.flow.filter(|_| metered) {
             let mut value_str = alloc::string::String::new();
             write!(value_str, "{:>8}", alloc::format!("{}", flow)).ok();
             if self.value.state != value_str {
@@ -282,6 +286,15 @@ impl<K: HistoryKind> Widget<&App, Actions> for HistoryWidget<K> {
             }
             #[cfg(not(test))]
             defmt::debug!("HistoryWidget flow value: {}", flow);
+        } else if state.history_state.flow.is_some() {
+            // Period the meter was not running: not the same as zero flow
+            let mut value_str = alloc::string::String::new();
+            write!(value_str, "{:>8}", "--").ok();
+            if self.value.state != value_str {
+                self.value.update(&value_str);
+            }
+            #[cfg(not(test))]
+            defmt::debug!("HistoryWidget flow value: gap");
         } else if self.value.state != "None" {
             let mut value_str = alloc::string::String::new();
             write!(value_str, "{:>8}", alloc::format!("None")).ok();
@@ -1,15 +1,21 @@
 #![allow(dead_code)]
 
+use core::marker::PhantomData;
 use embedded_storage::Storage;
 use modular_bitfield::prelude::*;
+use time::{Date, Month, OffsetDateTime};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Error {
     NoRecords,
     Unitialized,
     Unimplented,
     Storage,
     WrongCrc,
+    /// The record is for a period at or before the newest one
+    ClockBehind,
+    /// An append-only log has no room left
+    Full,
 }
 
 type Result<T> = core::result::Result<T, Error>;
@@ -23,194 +29,2280 @@ pub struct ServiceData {
     crc: u16,
 }
 
-pub struct RingStorage<const OFFSET: usize, const SIZE: i32, const ELEMENT_SIZE: i32> {
+/// Encoding of one history record on EEPROM.
+///
+/// The ring stores a format tag in the word following `ServiceData`, so a ring
+/// written with one encoding can be recognised after a firmware upgrade.
+pub trait Record: Copy + Default {
+    /// Format tag stored next to `ServiceData`
+    const FORMAT: u16;
+    /// Bytes per record
+    const SIZE: usize;
+    /// Value no measurement produces, stored by `GapFill::Marker`
+    const MARKER: Self;
+
+    fn encode(&self, buf: &mut [u8]);
+    fn decode(buf: &[u8]) -> Self;
+    fn from_f32(value: f32) -> Self;
+    fn to_f32(&self) -> f32;
+}
+
+/// Legacy encoding: value truncated to whole units.
+/// Rings written before format tags were introduced are read as `i32`.
+impl Record for i32 {
+    const FORMAT: u16 = 0x0000;
+    const SIZE: usize = 4;
+    const MARKER: Self = i32::MIN;
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[..4].copy_from_slice(&self.to_le_bytes());
+    }
+    fn decode(buf: &[u8]) -> Self {
+        i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+    }
+    fn from_f32(value: f32) -> Self {
+        value as i32
+    }
+    fn to_f32(&self) -> f32 {
+        *self as f32
+    }
+}
+
+impl Record for f32 {
+    const FORMAT: u16 = 0x0001;
+    const SIZE: usize = 4;
+    const MARKER: Self = f32::NAN;
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[..4].copy_from_slice(&self.to_le_bytes());
+    }
+    fn decode(buf: &[u8]) -> Self {
+        f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+    }
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+    fn to_f32(&self) -> f32 {
+        *self
+    }
+}
+
+/// Fixed-point record: value * 10^DECIMALS stored as `u64`.
+/// Negative values saturate to zero.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Scaled<const DECIMALS: u8>(pub u64);
+
+impl<const DECIMALS: u8> Scaled<DECIMALS> {
+    pub const SCALE: u64 = 10_u64.pow(DECIMALS as u32);
+}
+
+impl<const DECIMALS: u8> Record for Scaled<DECIMALS> {
+    const FORMAT: u16 = 0x0200 | DECIMALS as u16;
+    const SIZE: usize = 8;
+    const MARKER: Self = Self(u64::MAX);
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[..8].copy_from_slice(&self.0.to_le_bytes());
+    }
+    fn decode(buf: &[u8]) -> Self {
+        let mut bytes = [0_u8; 8];
+        bytes.copy_from_slice(&buf[..8]);
+        Self(u64::from_le_bytes(bytes))
+    }
+    fn from_f32(value: f32) -> Self {
+        if value > 0.0 {
+            Self((value as f64 * Self::SCALE as f64 + 0.5) as u64)
+        } else {
+            Self(0)
+        }
+    }
+    fn to_f32(&self) -> f32 {
+        (self.0 as f64 / Self::SCALE as f64) as f32
+    }
+}
+
+/// Minimum and peak instantaneous flow within one period,
+/// with the Unix time each was measured.
+/// `to_f32` reports the peak.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlowStats {
+    pub min: f32,
+    pub min_at: u32,
+    pub max: f32,
+    pub max_at: u32,
+}
+
+impl FlowStats {
+    /// No samples yet: the first `record` sets both extremes
+    pub const EMPTY: Self = Self {
+        min: f32::INFINITY,
+        min_at: 0,
+        max: f32::NEG_INFINITY,
+        max_at: 0,
+    };
+
+    /// Fold one instantaneous flow sample in. NaN samples are ignored.
+    pub fn record(&mut self, flow: f32, time: u32) {
+        if flow < self.min {
+            self.min = flow;
+            self.min_at = time;
+        }
+        if flow > self.max {
+            self.max = flow;
+            self.max_at = time;
+        }
+    }
+
+    /// No sample was recorded yet
+    pub fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+}
+
+impl Default for FlowStats {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl Record for FlowStats {
+    const FORMAT: u16 = 0x0300;
+    const SIZE: usize = 16;
+    const MARKER: Self = Self {
+        min: f32::NAN,
+        min_at: u32::MAX,
+        max: f32::NAN,
+        max_at: u32::MAX,
+    };
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[..4].copy_from_slice(&self.min.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.min_at.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.max.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.max_at.to_le_bytes());
+    }
+    fn decode(buf: &[u8]) -> Self {
+        let word = |at: usize| [buf[at], buf[at + 1], buf[at + 2], buf[at + 3]];
+        Self {
+            min: f32::from_le_bytes(word(0)),
+            min_at: u32::from_le_bytes(word(4)),
+            max: f32::from_le_bytes(word(8)),
+            max_at: u32::from_le_bytes(word(12)),
+        }
+    }
+    fn from_f32(value: f32) -> Self {
+        Self {
+            min: value,
+            min_at: 0,
+            max: value,
+            max_at: 0,
+        }
+    }
+    fn to_f32(&self) -> f32 {
+        self.max
+    }
+}
+
+/// How timestamps map to ring periods.
+///
+/// Times are Unix timestamps read from the RTC, which runs on UTC.
+pub trait Bucketing: Default + Copy {
+    /// Start of the period containing `time`
+    fn start(&self, time: u32) -> u32;
+    /// Start of the period `periods` away from the period starting at `start`
+    fn advance(&self, start: u32, periods: i32) -> u32;
+    /// Whole periods from the period starting at `from` to the one starting at `to`
+    fn periods(&self, from: u32, to: u32) -> i32;
+
+    /// Set at run time, so the ring keeps it in a word after its header slots
+    const STORED: bool = false;
+    /// Word stored for a `STORED` bucketing
+    fn to_word(&self) -> u32 {
+        0
+    }
+    /// Bucketing stored as `word`, `None` if the word holds none
+    fn from_word(_word: u32) -> Option<Self> {
+        None
+    }
+}
+
+/// Fixed stride of `SECONDS`, times aligned to the minute.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Fixed<const SECONDS: i32>;
+
+impl<const SECONDS: i32> Bucketing for Fixed<SECONDS> {
+    fn start(&self, time: u32) -> u32 {
+        time - time % 60
+    }
+    fn advance(&self, start: u32, periods: i32) -> u32 {
+        start.wrapping_add((periods as u32).wrapping_mul(SECONDS as u32))
+    }
+    fn periods(&self, from: u32, to: u32) -> i32 {
+        (to.wrapping_sub(from) as i32) / SECONDS
+    }
+}
+
+/// Calendar hours
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Hourly;
+
+impl Bucketing for Hourly {
+    fn start(&self, time: u32) -> u32 {
+        time - time % 3600
+    }
+    fn advance(&self, start: u32, periods: i32) -> u32 {
+        (start as i64 + periods as i64 * 3600).clamp(0, u32::MAX as i64) as u32
+    }
+    fn periods(&self, from: u32, to: u32) -> i32 {
+        ((to as i64 - from as i64) / 3600) as i32
+    }
+}
+
+/// Calendar days
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Daily;
+
+impl Bucketing for Daily {
+    fn start(&self, time: u32) -> u32 {
+        time - time % 86400
+    }
+    fn advance(&self, start: u32, periods: i32) -> u32 {
+        (start as i64 + periods as i64 * 86400).clamp(0, u32::MAX as i64) as u32
+    }
+    fn periods(&self, from: u32, to: u32) -> i32 {
+        ((to as i64 - from as i64) / 86400) as i32
+    }
+}
+
+/// Interval set at run time, e.g. from `Options`.
+///
+/// Periods are aligned to whole intervals since the Unix epoch, so an interval
+/// that divides an hour starts on the hour. Changing the interval of a ring
+/// that holds records misplaces them; `set_bucketing()` clears the ring. The
+/// ring stores the interval, so a change made while the meter was off is
+/// caught by `set_bucketing()` at start-up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    seconds: u32,
+}
+
+impl Interval {
+    pub const fn minutes(minutes: u32) -> Self {
+        Self {
+            seconds: if minutes == 0 { 60 } else { minutes * 60 },
+        }
+    }
+
+    pub fn seconds(&self) -> u32 {
+        self.seconds
+    }
+}
+
+impl Default for Interval {
+    fn default() -> Self {
+        Self::minutes(15)
+    }
+}
+
+impl Bucketing for Interval {
+    fn start(&self, time: u32) -> u32 {
+        time - time % self.seconds
+    }
+    fn advance(&self, start: u32, periods: i32) -> u32 {
+        (start as i64 + periods as i64 * self.seconds as i64).clamp(0, u32::MAX as i64) as u32
+    }
+    fn periods(&self, from: u32, to: u32) -> i32 {
+        ((to as i64 - from as i64) / self.seconds as i64) as i32
+    }
+
+    const STORED: bool = true;
+    fn to_word(&self) -> u32 {
+        self.seconds
+    }
+    fn from_word(word: u32) -> Option<Self> {
+        // Erased EEPROM reads as u32::MAX, no whole number of minutes
+        (word != 0 && word.is_multiple_of(60)).then_some(Self { seconds: word })
+    }
+}
+
+/// Calendar months: 28 to 31 days, leap years included
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Monthly;
+
+impl Monthly {
+    /// Months since January 1970
+    fn month_number(time: u32) -> i32 {
+        OffsetDateTime::from_unix_timestamp(time as i64).map_or(0, |dt| {
+            (dt.year() - 1970) * 12 + u8::from(dt.month()) as i32 - 1
+        })
+    }
+    /// Midnight of the first day of the month `number` months after January 1970
+    fn first_day(number: i32) -> u32 {
+        let number = number.max(0);
+        Month::try_from((number % 12 + 1) as u8)
+            .ok()
+            .and_then(|month| Date::from_calendar_date(1970 + number / 12, month, 1).ok())
+            .map_or(u32::MAX, |date| {
+                date.midnight()
+                    .assume_utc()
+                    .unix_timestamp()
+                    .clamp(0, u32::MAX as i64) as u32
+            })
+    }
+}
+
+impl Bucketing for Monthly {
+    fn start(&self, time: u32) -> u32 {
+        Self::first_day(Self::month_number(time))
+    }
+    fn advance(&self, start: u32, periods: i32) -> u32 {
+        Self::first_day(Self::month_number(start) + periods)
+    }
+    fn periods(&self, from: u32, to: u32) -> i32 {
+        Self::month_number(to) - Self::month_number(from)
+    }
+}
+
+/// How a stored record came about
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Measured over the whole period
+    #[default]
+    Valid,
+    /// Inserted for a period the meter was not running
+    GapFilled,
+    /// Not measured directly, e.g. worked out from neighbouring periods
+    Estimated,
+    /// Recorded around an RTC adjustment
+    ClockAdjusted,
+}
+
+impl Status {
+    fn to_u8(self) -> u8 {
+        match self {
+            Status::Valid => 0,
+            Status::GapFilled => 1,
+            Status::Estimated => 2,
+            Status::ClockAdjusted => 3,
+        }
+    }
+    /// Erased bytes (rings written before statuses existed) read as valid.
+    /// `STATUS_MISSING` is handled by the ring before this is reached.
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Status::GapFilled,
+            2 => Status::Estimated,
+            3 => Status::ClockAdjusted,
+            _ => Status::Valid,
+        }
+    }
+}
+
+/// Status byte of a period skipped by `GapFill::Skip`: no record at all
+const STATUS_MISSING: u8 = 0x7F;
+
+/// What `RingStorage::add` stores for periods missed while the meter was not
+/// running (powered off, or nothing logged for a while).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GapFill {
+    /// Zero value marked `Status::GapFilled`
+    #[default]
+    Zero,
+    /// `Record::MARKER` marked `Status::GapFilled`, so raw dumps show the gap too
+    Marker,
+    /// Nothing: the periods read as not recorded
+    Skip,
+}
+
+/// Records written per EEPROM write while filling a gap
+const FILL_CHUNK: usize = 32;
+
+/// Stored value together with its status
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry<R> {
+    pub value: R,
+    pub status: Status,
+}
+
+impl<R> Entry<R> {
+    pub fn valid(value: R) -> Self {
+        Self {
+            value,
+            status: Status::Valid,
+        }
+    }
+}
+
+/// Search direction for `RingStorage::find_nearest`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Before,
+    After,
+}
+
+/// Erased word: ring written by firmware without format tags (i32 records)
+const FORMAT_UNTAGGED: u16 = 0xFFFF;
+/// Set while legacy i32 records are converted in place; low bits count converted chunks
+const FORMAT_MIGRATING: u16 = 0x8000;
+/// Records converted per EEPROM write during migration
+const MIGRATE_CHUNK: usize = 64;
+
+/// Header slots `ServiceData` rotates through, one per update
+pub const HEADER_SLOTS: usize = 8;
+
+/// Largest `Record::SIZE` a ring can store
+pub const MAX_RECORD_SIZE: usize = 16;
+
+// Header slot: generation counter, `ServiceData` and the record and status
+// stored by the same update. The slot is written first and is the commit point; the record
+// slot is written after it and restored from here by `new()` if a reset cut it.
+const SLOT_DATA: usize = size_of::<u32>();
+const SLOT_FORMAT: usize = SLOT_DATA + size_of::<ServiceData>();
+const SLOT_INDEX: usize = SLOT_FORMAT + size_of::<u16>();
+const SLOT_RECORD: usize = SLOT_INDEX + size_of::<u32>();
+/// Largest header slot, for a record of `MAX_RECORD_SIZE` bytes
+const MAX_SLOT_SIZE: usize = SLOT_RECORD + MAX_RECORD_SIZE + size_of::<u8>() + size_of::<u16>();
+/// Slot index of an update that only rewrites `ServiceData`
+const SLOT_NO_RECORD: u32 = u32::MAX;
+
+pub struct RingStorage<
+    const OFFSET: usize,
+    const SIZE: i32,
+    const ELEMENT_SIZE: i32,
+    R: Record = i32,
+    B: Bucketing = Fixed<ELEMENT_SIZE>,
+> {
     pub data: ServiceData,
+    /// Format tag of the records on EEPROM
+    format: u16,
+    /// Header updates so far; the newest header slot carries it
+    generation: u32,
+    gap_fill: GapFill,
+    /// The clock was changed since the newest record
+    clock_adjusted: bool,
+    bucketing: B,
+    /// Records kept in the window, at most `SIZE`
+    depth: u32,
+    _record: PhantomData<R>,
 }
-impl<const OFFSET: usize, const SIZE: i32, const ELEMENT_SIZE: i32>
-    RingStorage<OFFSET, SIZE, ELEMENT_SIZE>
+impl<const OFFSET: usize, const SIZE: i32, const ELEMENT_SIZE: i32, R: Record, B: Bucketing>
+    RingStorage<OFFSET, SIZE, ELEMENT_SIZE, R, B>
 {
     const OFFSET_OF_STAT_PAGE: usize = 4096;
     const OFFSET: usize = Self::OFFSET_OF_STAT_PAGE + OFFSET;
-    pub const SIZE_ON_FLASH: usize =
-        size_of::<u32>() + SIZE as usize + size_of::<ServiceData>() + size_of::<u16>();
+    const OFFSET_OF_FORMAT: usize = Self::OFFSET + size_of::<ServiceData>();
+    /// One status byte per record slot
+    const OFFSET_OF_STATUS: usize =
+        Self::OFFSET_OF_FORMAT + size_of::<u16>() + SIZE as usize * R::SIZE;
+    const OFFSET_OF_SLOTS: usize = Self::OFFSET_OF_STATUS + SIZE as usize;
+    /// `Bucketing::to_word()` of a `STORED` bucketing
+    const OFFSET_OF_BUCKETING: usize = Self::OFFSET_OF_SLOTS + HEADER_SLOTS * Self::SLOT_SIZE;
+    pub const SIZE_ON_FLASH: usize = size_of::<ServiceData>()
+        + size_of::<u16>()
+        + SIZE as usize * (R::SIZE + size_of::<u8>())
+        + HEADER_SLOTS * Self::SLOT_SIZE
+        + if B::STORED { size_of::<u32>() } else { 0 };
+
+    /// Record bytes in a header slot. At least 8, the size before larger
+    /// records existed, so rings of small records keep their slot layout.
+    const SLOT_RECORD_SIZE: usize = {
+        assert!(R::SIZE <= MAX_RECORD_SIZE);
+        if R::SIZE > 8 {
+            R::SIZE
+        } else {
+            8
+        }
+    };
+    const SLOT_STATUS: usize = SLOT_RECORD + Self::SLOT_RECORD_SIZE;
+    const SLOT_CRC: usize = Self::SLOT_STATUS + size_of::<u8>();
+    const SLOT_SIZE: usize = Self::SLOT_CRC + size_of::<u16>();
+
+    pub fn new_empty() -> Self {
+        Self {
+            data: ServiceData::default(),
+            format: R::FORMAT,
+            generation: 0,
+            gap_fill: GapFill::default(),
+            clock_adjusted: false,
+            bucketing: B::default(),
+            depth: SIZE as u32,
+            _record: PhantomData,
+        }
+    }
 
     pub fn new<S: Storage>(storage: &mut S) -> Result<Self> {
+        let mut tag = [0_u8; size_of::<u16>()];
+        storage
+            .read(Self::OFFSET_OF_FORMAT as u32, &mut tag)
+            .map_err(|_| Error::Storage)?;
+        let mut format = u16::from_le_bytes(tag);
+        if format == FORMAT_UNTAGGED {
+            format = i32::FORMAT;
+        }
+
+        let (data, generation) = match Self::newest_slot(storage, format)? {
+            Some(newest) => newest,
+            // Nothing rotated yet: header written by firmware without slots
+            None => match Self::read_service_data(storage, Self::OFFSET)? {
+                Some(data) => (data, 0),
+                None => {
+                    let mut ring = Self::new_empty();
+                    ring.load_bucketing(storage)?;
+                    return Ok(ring);
+                }
+            },
+        };
+
+        let mut ring = Self {
+            data,
+            format,
+            generation,
+            gap_fill: GapFill::default(),
+            clock_adjusted: false,
+            bucketing: B::default(),
+            depth: SIZE as u32,
+            _record: PhantomData,
+        };
+        if ring.format != R::FORMAT {
+            ring.migrate(storage)?;
+        }
+        ring.load_bucketing(storage)?;
+        Ok(ring)
+    }
+
+    /// Read a `STORED` bucketing back. A ring written before it was stored
+    /// is taken to use `B::default()`, which is stored from then on.
+    fn load_bucketing<S: Storage>(&mut self, storage: &mut S) -> Result<()> {
+        if !B::STORED {
+            return Ok(());
+        }
+        let mut word = [0_u8; size_of::<u32>()];
+        storage
+            .read(Self::OFFSET_OF_BUCKETING as u32, &mut word)
+            .map_err(|_| Error::Storage)?;
+        match B::from_word(u32::from_le_bytes(word)) {
+            Some(bucketing) => {
+                self.bucketing = bucketing;
+                Ok(())
+            }
+            None => self.write_bucketing(storage),
+        }
+    }
+
+    fn write_bucketing<S: Storage>(&mut self, storage: &mut S) -> Result<()> {
+        if !B::STORED {
+            return Ok(());
+        }
+        storage
+            .write(
+                Self::OFFSET_OF_BUCKETING as u32,
+                &self.bucketing.to_word().to_le_bytes(),
+            )
+            .map_err(|_| Error::Storage)
+    }
+
+    fn read_service_data<S: Storage>(
+        storage: &mut S,
+        offset: usize,
+    ) -> Result<Option<ServiceData>> {
         let mut buf = [0_u8; size_of::<ServiceData>()];
         storage
-            .read(Self::OFFSET as u32, &mut buf)
+            .read(offset as u32, &mut buf)
             .map_err(|_| Error::Storage)?;
         let crc =
             crc16::State::<crc16::CCITT_FALSE>::calculate(&buf[..size_of::<ServiceData>() - 2]);
         let data = ServiceData { bytes: buf };
-        if crc != data.crc() {
-            return Ok(Self {
-                data: ServiceData::default(),
-            });
+        Ok((crc == data.crc()).then_some(data))
+    }
+
+    fn slot_offset(position: usize) -> u32 {
+        (Self::OFFSET_OF_SLOTS + position * Self::SLOT_SIZE) as u32
+    }
+
+    /// Find the valid header slot with the highest generation.
+    ///
+    /// A slot torn by a reset fails its CRC, so the previous generation wins and
+    /// the update it belonged to never happened. If the newest slot stores a
+    /// record, that record and its status are written again in case the reset
+    /// hit their slots.
+    fn newest_slot<S: Storage>(storage: &mut S, format: u16) -> Result<Option<(ServiceData, u32)>> {
+        let mut newest: Option<(u32, [u8; MAX_SLOT_SIZE])> = None;
+        for position in 0..HEADER_SLOTS {
+            let mut slot = [0_u8; MAX_SLOT_SIZE];
+            storage
+                .read(Self::slot_offset(position), &mut slot[..Self::SLOT_SIZE])
+                .map_err(|_| Error::Storage)?;
+            let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&slot[..Self::SLOT_CRC]);
+            if crc != u16::from_le_bytes([slot[Self::SLOT_CRC], slot[Self::SLOT_CRC + 1]]) {
+                continue;
+            }
+            let generation = u32::from_le_bytes([slot[0], slot[1], slot[2], slot[3]]);
+            if newest.is_none_or(|(newest, _)| generation > newest) {
+                newest = Some((generation, slot));
+            }
         }
-        Ok(Self { data })
+        let Some((generation, slot)) = newest else {
+            return Ok(None);
+        };
+
+        let mut buf = [0_u8; size_of::<ServiceData>()];
+        buf.copy_from_slice(&slot[SLOT_DATA..SLOT_FORMAT]);
+        let data = ServiceData { bytes: buf };
+        let index = u32::from_le_bytes([
+            slot[SLOT_INDEX],
+            slot[SLOT_INDEX + 1],
+            slot[SLOT_INDEX + 2],
+            slot[SLOT_INDEX + 3],
+        ]);
+        // Records written in an older format have been converted since
+        let same_format = format == u16::from_le_bytes([slot[SLOT_FORMAT], slot[SLOT_FORMAT + 1]]);
+        if same_format && index < SIZE as u32 {
+            let mut ring = Self::new_empty();
+            let entry = Entry {
+                value: R::decode(&slot[SLOT_RECORD..Self::SLOT_STATUS]),
+                status: Status::from_u8(slot[Self::SLOT_STATUS]),
+            };
+            let mut stored = [0_u8; MAX_RECORD_SIZE];
+            storage
+                .read(ring.offset(index as usize), &mut stored[..R::SIZE])
+                .map_err(|_| Error::Storage)?;
+            let mut status = [0_u8];
+            storage
+                .read(Self::status_offset(index as usize), &mut status)
+                .map_err(|_| Error::Storage)?;
+            if stored[..R::SIZE] != slot[SLOT_RECORD..SLOT_RECORD + R::SIZE]
+                || status[0] != slot[Self::SLOT_STATUS]
+            {
+                defmt::warn!("History update interrupted, restoring record {}", index);
+                ring.write_record(storage, index as usize, entry)?;
+            }
+        }
+        Ok(Some((data, generation)))
+    }
+
+    /// Bring records written in another format to `R`.
+    ///
+    /// Legacy i32 rings with the same record size are converted in place, one
+    /// chunk per write, with progress kept in the format tag so a reset resumes
+    /// where it stopped. Anything else cannot be kept at the same slot positions,
+    /// so the ring starts empty.
+    fn migrate<S: Storage>(&mut self, storage: &mut S) -> Result<()> {
+        let legacy = self.format == i32::FORMAT || self.format & FORMAT_MIGRATING != 0;
+        if !legacy || R::SIZE != i32::SIZE {
+            defmt::warn!(
+                "History format {:x} cannot be read as {:x}, starting empty",
+                self.format,
+                R::FORMAT
+            );
+            self.data = ServiceData::default();
+            self.commit(storage, None)?;
+            return self.write_format(storage);
+        }
+
+        let chunks = (SIZE as usize).div_ceil(MIGRATE_CHUNK);
+        let done = if self.format & FORMAT_MIGRATING != 0 {
+            (self.format & !FORMAT_MIGRATING) as usize
+        } else {
+            0
+        };
+        let mut buf = [0_u8; MIGRATE_CHUNK * 4];
+        for chunk in done..chunks {
+            let first = chunk * MIGRATE_CHUNK;
+            let count = MIGRATE_CHUNK.min(SIZE as usize - first);
+            let bytes = &mut buf[..count * 4];
+            storage
+                .read(self.offset(first), bytes)
+                .map_err(|_| Error::Storage)?;
+            for slot in bytes.chunks_mut(4) {
+                R::from_f32(i32::decode(slot) as f32).encode(slot);
+            }
+            storage
+                .write(self.offset(first), bytes)
+                .map_err(|_| Error::Storage)?;
+            // Whatever preceded the status area is not a status
+            let statuses = [Status::Valid.to_u8(); MIGRATE_CHUNK];
+            storage
+                .write(Self::status_offset(first), &statuses[..count])
+                .map_err(|_| Error::Storage)?;
+            self.format = FORMAT_MIGRATING | (chunk + 1) as u16;
+            storage
+                .write(Self::OFFSET_OF_FORMAT as u32, &self.format.to_le_bytes())
+                .map_err(|_| Error::Storage)?;
+        }
+        self.format = R::FORMAT;
+        self.write_format(storage)
     }
+
+    fn write_format<S: Storage>(&mut self, storage: &mut S) -> Result<()> {
+        self.format = R::FORMAT;
+        storage
+            .write(Self::OFFSET_OF_FORMAT as u32, &R::FORMAT.to_le_bytes())
+            .map_err(|_| Error::Storage)
+    }
+
+    /// Note that the RTC was set to `time`.
+    ///
+    /// Stored records are never moved or dropped. If the clock went back to
+    /// or before the newest period, that record is marked `ClockAdjusted`;
+    /// `add()` then refuses periods that are already stored with
+    /// `Error::ClockBehind` until the clock passes the newest record. The
+    /// next record stored after a clock change is marked `ClockAdjusted`.
+    pub fn clock_changed<S: Storage>(&mut self, storage: &mut S, time: u32) -> Result<()> {
+        if self.empty() {
+            return Ok(());
+        }
+        self.clock_adjusted = true;
+        if self.bucketing.start(time) > self.data.time_of_last() {
+            return Ok(());
+        }
+        let newest = self.data.size() - 1;
+        match self.read_record(storage, newest)? {
+            Some(entry) if entry.status == Status::Valid => {
+                let slot = self.slot(newest);
+                let entry = Entry {
+                    status: Status::ClockAdjusted,
+                    ..entry
+                };
+                self.commit(storage, Some((slot, entry)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Use `bucketing` instead of `B::default()` to map times to periods.
+    /// Nothing is stored or cleared; a ring opened with `new()` takes a new
+    /// bucketing through `set_bucketing()`.
+    pub fn with_bucketing(mut self, bucketing: B) -> Self {
+        self.bucketing = bucketing;
+        self
+    }
+
+    pub fn bucketing(&self) -> B {
+        self.bucketing
+    }
+
+    /// Keep at most `depth` records (1 to `SIZE`) in the window.
+    ///
+    /// Records beyond the depth drop out of the window when the ring is next
+    /// updated; they are not erased, but are not read back either.
+    pub fn with_depth(mut self, depth: u32) -> Self {
+        self.set_depth(depth);
+        self
+    }
+
+    /// Change the depth at run time, as `with_depth` does
+    pub fn set_depth(&mut self, depth: u32) {
+        self.depth = depth.clamp(1, SIZE as u32);
+        if self.data.size() > self.depth {
+            self.data.set_size(self.depth);
+        }
+    }
+
+    /// Change the bucketing, at start-up or at run time. Records stored under
+    /// the old periods would be misplaced, so a change clears the ring first.
+    /// Returns whether it did.
+    pub fn set_bucketing<S: Storage>(&mut self, storage: &mut S, bucketing: B) -> Result<bool>
+    where
+        B: PartialEq,
+    {
+        if bucketing == self.bucketing {
+            return Ok(false);
+        }
+        // A reset in between leaves an empty ring, cleared again next time
+        self.clear(storage)?;
+        self.bucketing = bucketing;
+        self.write_bucketing(storage)?;
+        Ok(true)
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Drop every record, e.g. before changing the interval
+    pub fn clear<S: Storage>(&mut self, storage: &mut S) -> Result<()> {
+        if self.empty() {
+            return Ok(());
+        }
+        self.data.set_size(0);
+        self.commit(storage, None)
+    }
+
+    /// Use `policy` for periods missed before the next record
+    pub fn with_gap_fill(mut self, policy: GapFill) -> Self {
+        self.gap_fill = policy;
+        self
+    }
+
+    pub fn gap_fill(&self) -> GapFill {
+        self.gap_fill
+    }
+
+    /// Format tag of the records on EEPROM
+    pub fn format(&self) -> u16 {
+        self.format
+    }
+
     fn empty(&mut self) -> bool {
         self.data.size() == 0
     }
-    fn size(&mut self) -> u32 {
+    pub fn size(&mut self) -> u32 {
         self.data.size()
     }
-    fn offset(&mut self, index: usize) -> u32 {
+    pub(crate) fn offset(&self, index: usize) -> u32 {
         let mut offset = Self::OFFSET + size_of::<ServiceData>() + size_of::<u16>(); // first element offset
-        offset += size_of::<u32>() * index;
+        offset += R::SIZE * index;
         offset as u32
     }
-    pub fn find<S: Storage>(&mut self, storage: &mut S, time: u32) -> Result<Option<i32>> {
-        if self.data.size() == 0 {
+    /// EEPROM slot of the record at `index`, counting from the oldest one.
+    /// `offset_of_last` is the slot after the newest record.
+    fn slot(&self, index: u32) -> usize {
+        let oldest = self.data.offset_of_last() as i64 - self.data.size() as i64;
+        (oldest + index as i64).rem_euclid(SIZE as i64) as usize
+    }
+    fn status_offset(slot: usize) -> u32 {
+        (Self::OFFSET_OF_STATUS + slot) as u32
+    }
+    /// Record at `index`, `None` for a period skipped by `GapFill::Skip`
+    fn read_record<S: Storage>(&self, storage: &mut S, index: u32) -> Result<Option<Entry<R>>> {
+        let slot = self.slot(index);
+        let mut status = [0_u8];
+        storage
+            .read(Self::status_offset(slot), &mut status)
+            .map_err(|_| Error::Storage)?;
+        if status[0] == STATUS_MISSING {
             return Ok(None);
         }
-        let time = time - time % 60;
-        let mut index = self.data.offset_of_last() as usize;
-        for _ in 0..self.data.size() {
-            let offset = self.offset(index);
-            let mut buf = [0_u8; size_of::<i32>()];
-            storage.read(offset, &mut buf).map_err(|_| Error::Storage)?;
-            let value = i32::from_le_bytes(buf);
+        let mut buf = [0_u8; MAX_RECORD_SIZE];
+        storage
+            .read(self.offset(slot), &mut buf[..R::SIZE])
+            .map_err(|_| Error::Storage)?;
+        Ok(Some(Entry {
+            value: R::decode(&buf),
+            status: Status::from_u8(status[0]),
+        }))
+    }
+    /// Number of stored records older than `time`
+    fn count_before(&mut self, time: u32) -> u32 {
+        if self.data.size() == 0 {
+            return 0;
+        }
+        let first = self.first_stored_timestamp();
+        if time <= first {
+            return 0;
+        }
+        if time > self.data.time_of_last() {
+            return self.data.size();
+        }
+        let periods = self.bucketing.periods(first, self.bucketing.start(time)) as u32;
+        if self.bucketing.advance(first, periods as i32) < time {
+            periods + 1
+        } else {
+            periods
+        }
+    }
 
-            let expected_time = self.data.time_of_last()
-                - (self.data.size() - 1 - index as u32) * ELEMENT_SIZE as u32;
-            if expected_time == time {
-                return Ok(Some(value));
-            }
+    pub fn find<S: Storage>(&mut self, storage: &mut S, time: u32) -> Result<Option<Entry<R>>> {
+        match self.index_of(time) {
+            Some(index) => self.read_record(storage, index),
+            None => Ok(None),
+        }
+    }
 
-            if index == 0 {
-                index = SIZE as usize - 1;
-            } else {
-                index -= 1;
+    /// Closest record strictly before or after `time`, for stepping through
+    /// the history one record at a time.
+    pub fn find_nearest<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        time: u32,
+        direction: Direction,
+    ) -> Result<Option<(u32, Entry<R>)>> {
+        let mut index = match direction {
+            Direction::Before => match self.count_before(time) {
+                0 => return Ok(None),
+                count => count - 1,
+            },
+            Direction::After => self.count_before(time.saturating_add(1)),
+        };
+        while let Some(timestamp) = self.timestamp_at(index) {
+            if let Some(entry) = self.read_record(storage, index)? {
+                return Ok(Some((timestamp, entry)));
+            }
+            // Skipped period, keep going
+            match direction {
+                Direction::Before if index == 0 => break,
+                Direction::Before => index -= 1,
+                Direction::After => index += 1,
             }
         }
-
         Ok(None)
     }
-    fn last_value<S: Storage>(&mut self, storage: &mut S) -> Result<Option<i32>> {
+
+    /// Records with `from <= timestamp < to`, oldest first.
+    pub fn iter_range<'a, S: Storage>(
+        &'a mut self,
+        storage: &'a mut S,
+        from: u32,
+        to: u32,
+    ) -> Records<'a, S, OFFSET, SIZE, ELEMENT_SIZE, R, B> {
+        let next = self.count_before(from);
+        let end = self.count_before(to).max(next);
+        Records {
+            ring: self,
+            storage,
+            next,
+            end,
+        }
+    }
+
+    fn last_value<S: Storage>(&mut self, storage: &mut S) -> Result<Option<Entry<R>>> {
         if self.data.size() > 0 {
             return Ok(self.find(storage, self.data.time_of_last()).unwrap());
         }
         Ok(None)
     }
 
-    fn advance_offset_by_one(&mut self) {
+    pub(crate) fn advance_offset_by_one(&mut self) {
         let offset_of_last = self.data.offset_of_last() + 1;
         self.data.set_offset_of_last(offset_of_last);
         if self.data.offset_of_last() == SIZE as u32 {
             self.data.set_offset_of_last(0);
         }
     }
-    fn write_value<S: Storage>(&mut self, storage: &mut S, val: i32, time: u32) -> Result<()> {
-        if self.data.size() < SIZE as u32 {
+    fn write_record<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        index: usize,
+        entry: Entry<R>,
+    ) -> Result<()> {
+        let mut buf = [0_u8; MAX_RECORD_SIZE];
+        entry.value.encode(&mut buf);
+        storage
+            .write(self.offset(index), &buf[..R::SIZE])
+            .map_err(|_| Error::Storage)?;
+        storage
+            .write(Self::status_offset(index), &[entry.status.to_u8()])
+            .map_err(|_| Error::Storage)
+    }
+    /// Store `entry` for `time` in the next slot.
+    fn push<S: Storage>(&mut self, storage: &mut S, entry: Entry<R>, time: u32) -> Result<()> {
+        let index = self.data.offset_of_last() as usize;
+        if self.data.size() < self.depth {
             let tmp = self.data.size() + 1;
             self.data.set_size(tmp);
         }
         self.data.set_time_of_last(time);
+        self.advance_offset_by_one();
+        self.commit(storage, Some((index, entry)))
+    }
+
+    /// Fill `count` missed periods after the newest record.
+    ///
+    /// The filled slots are written in bulk and only become part of the ring
+    /// with the next commit. Records they overwrite are dropped from the ring
+    /// by a header-only commit first, so a reset never shows them with the
+    /// wrong values.
+    fn fill_gap<S: Storage>(&mut self, storage: &mut S, count: u32) -> Result<()> {
+        let overwritten = (self.data.size() + count).saturating_sub(SIZE as u32);
+        if overwritten > 0 {
+            let size = self.data.size() - overwritten;
+            self.data.set_size(size);
+            self.commit(storage, None)?;
+        }
+
+        let (value, status) = match self.gap_fill {
+            GapFill::Zero => (Some(R::default()), Status::GapFilled.to_u8()),
+            GapFill::Marker => (Some(R::MARKER), Status::GapFilled.to_u8()),
+            GapFill::Skip => (None, STATUS_MISSING),
+        };
+        let mut records = [0_u8; FILL_CHUNK * MAX_RECORD_SIZE];
+        if let Some(value) = value {
+            for slot in records.chunks_mut(R::SIZE) {
+                value.encode(slot);
+            }
+        }
+        let statuses = [status; FILL_CHUNK];
+        let mut slot = self.data.offset_of_last() as usize;
+        let mut left = count as usize;
+        while left > 0 {
+            // Contiguous run up to the end of the record area
+            let run = left.min(FILL_CHUNK).min(SIZE as usize - slot);
+            if value.is_some() {
+                storage
+                    .write(self.offset(slot), &records[..run * R::SIZE])
+                    .map_err(|_| Error::Storage)?;
+            }
+            storage
+                .write(Self::status_offset(slot), &statuses[..run])
+                .map_err(|_| Error::Storage)?;
+            slot = (slot + run) % SIZE as usize;
+            left -= run;
+        }
+
+        let size = (self.data.size() + count).min(self.depth);
+        self.data.set_size(size);
+        self.data.set_offset_of_last(slot as u32);
+        let last = self
+            .bucketing
+            .advance(self.data.time_of_last(), count as i32);
+        self.data.set_time_of_last(last);
+        Ok(())
+    }
+
+    /// Write `self.data` and optionally one record so that a reset at any
+    /// point leaves either the previous or the new state after `new()`:
+    /// the next header slot first, then the record slot.
+    fn commit<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        record: Option<(usize, Entry<R>)>,
+    ) -> Result<()> {
+        self.seal();
+        let generation = self.generation + 1;
+        let mut slot = [0xFF_u8; MAX_SLOT_SIZE];
+        slot[..SLOT_DATA].copy_from_slice(&generation.to_le_bytes());
+        slot[SLOT_DATA..SLOT_FORMAT].copy_from_slice(&self.data.into_bytes());
+        slot[SLOT_FORMAT..SLOT_INDEX].copy_from_slice(&self.format.to_le_bytes());
+        let index = record.map_or(SLOT_NO_RECORD, |(index, _)| index as u32);
+        slot[SLOT_INDEX..SLOT_RECORD].copy_from_slice(&index.to_le_bytes());
+        if let Some((_, entry)) = record {
+            entry
+                .value
+                .encode(&mut slot[SLOT_RECORD..Self::SLOT_STATUS]);
+            slot[Self::SLOT_STATUS] = entry.status.to_u8();
+        }
+        let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&slot[..Self::SLOT_CRC]);
+        slot[Self::SLOT_CRC..Self::SLOT_SIZE].copy_from_slice(&crc.to_le_bytes());
         storage
             .write(
-                self.offset(self.data.offset_of_last() as usize),
-                &val.to_le_bytes(),
+                Self::slot_offset(generation as usize % HEADER_SLOTS),
+                &slot[..Self::SLOT_SIZE],
             )
             .map_err(|_| Error::Storage)?;
+        self.generation = generation;
+
+        if let Some((index, entry)) = record {
+            self.write_record(storage, index, entry)?;
+        }
         Ok(())
     }
-    fn write_service_data<S: Storage>(&mut self, storage: &mut S) -> Result<()> {
-        self.advance_offset_by_one();
-        let mut buff = self.data.into_bytes();
+    fn seal(&mut self) {
+        let buff = self.data.into_bytes();
         self.data
             .set_crc(crc16::State::<crc16::CCITT_FALSE>::calculate(
                 &buff[..size_of::<ServiceData>() - 2],
             ));
-        buff = self.data.into_bytes();
-        storage
-            .write(Self::OFFSET as u32, &buff)
-            .map_err(|_| Error::Storage)?;
-        Ok(())
     }
+
+    /// Header updates since the ring was created.
+    ///
+    /// Each header slot sees about `write_count() / HEADER_SLOTS` of them, which
+    /// can be compared against the EEPROM endurance rating.
+    pub fn write_count(&self) -> u32 {
+        self.generation
+    }
+
     pub fn last_stored_timestamp(&mut self) -> u32 {
         self.data.time_of_last()
     }
 
     pub fn first_stored_timestamp(&mut self) -> u32 {
         if self.data.size() > 0 {
-            return self.data.time_of_last() - ELEMENT_SIZE as u32 * (self.data.size() - 1);
+            return self
+                .bucketing
+                .advance(self.data.time_of_last(), 1 - self.data.size() as i32);
         }
         self.data.time_of_last()
     }
 
-    /// Maximum number of gap-fill entries per add() call.
-    /// Prevents excessive EEPROM writes when device was offline for a long time.
-    const MAX_GAP_FILL: i32 = 24;
+    /// Timestamp of the record at `index`, counting from the oldest one.
+    pub fn timestamp_at(&mut self, index: u32) -> Option<u32> {
+        if index >= self.data.size() {
+            return None;
+        }
+        let first = self.first_stored_timestamp();
+        Some(self.bucketing.advance(first, index as i32))
+    }
 
-    pub fn add<S: Storage>(&mut self, storage: &mut S, val: i32, time: u32) -> Result<()> {
-        let mut time = time;
-        time -= time % 60;
+    /// Index of the record stored for `time` (0 = oldest), if any.
+    pub fn index_of(&mut self, time: u32) -> Option<u32> {
+        if self.data.size() == 0 {
+            return None;
+        }
+        let time = self.bucketing.start(time);
+        let first = self.first_stored_timestamp();
+        if time < first || time > self.data.time_of_last() {
+            return None;
+        }
+        let index = self.bucketing.periods(first, time) as u32;
+        (self.timestamp_at(index) == Some(time)).then_some(index)
+    }
+
+    pub fn add<S: Storage>(&mut self, storage: &mut S, val: R, time: u32) -> Result<()> {
+        self.add_with_status(storage, val, Status::Valid, time)
+    }
+
+    /// Store `val` for the period containing `time`, marked with `status`.
+    /// Missed periods before it are filled according to the ring's `GapFill`.
+    pub fn add_with_status<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        val: R,
+        status: Status,
+        time: u32,
+    ) -> Result<()> {
+        let status = match status {
+            Status::Valid if self.clock_adjusted => Status::ClockAdjusted,
+            status => status,
+        };
+        let entry = Entry { value: val, status };
+        let time = self.bucketing.start(time);
         if self.empty() {
-            self.write_value(storage, val, time)?;
-            self.write_service_data(storage)?;
+            // First record of a fresh ring: tag it before any data lands
+            self.write_format(storage)?;
+            self.push(storage, entry, time)?;
         } else {
-            let mut delta = (time - self.data.time_of_last()) as i32;
-            if delta > 0 {
-                if delta / ELEMENT_SIZE >= SIZE {
-                    // Gap too large — reset buffer and start fresh
-                    self.data.set_size(0);
-                    self.data.set_offset_of_last(0);
-                    self.write_value(storage, val, time)?;
-                    self.write_service_data(storage)?;
-                } else if delta / ELEMENT_SIZE > Self::MAX_GAP_FILL {
-                    // Gap exceeds MAX_GAP_FILL — skip fill, just write current value
-                    // and update service data without filling gaps
-                    defmt::warn!(
-                        "Gap too large ({} periods), skipping gap fill",
-                        delta / ELEMENT_SIZE
-                    );
-                    self.write_value(storage, val, time)?;
-                    self.write_service_data(storage)?;
-                } else {
-                    // Fill gaps with zero values but correct timestamps
-                    while delta > ELEMENT_SIZE {
-                        let gap_time = self.data.time_of_last() + ELEMENT_SIZE as u32;
-                        self.write_value(storage, 0, gap_time)?;
-                        self.write_service_data(storage)?;
-                        delta -= ELEMENT_SIZE;
-                    }
-                    self.write_value(storage, val, time)?;
-                    self.write_service_data(storage)?;
-                    return Ok(());
+            let last = self.data.time_of_last();
+            if time <= last {
+                // The period is already stored. Keep it and let the caller
+                // carry the value over until the clock reaches a new period.
+                defmt::warn!("Record for {} at or before newest {}", time, last);
+                if time < last {
+                    self.clock_adjusted = true;
                 }
-            } else if delta.abs() / ELEMENT_SIZE >= self.data.size() as i32 {
+                return Err(Error::ClockBehind);
+            }
+            let periods = self.bucketing.periods(last, time);
+            if periods >= self.depth as i32 {
+                // Every stored period is older than the ring reaches back:
+                // start the window over from the slot after the newest record
+                defmt::warn!("Gap of {} periods, older records out of range", periods);
                 self.data.set_size(0);
-                self.data.set_offset_of_last(0);
-                self.write_value(storage, val, time)?;
-                self.write_service_data(storage)?;
-            } else {
-                // Handle negative delta (going back in time)
-                delta = delta.abs();
-                while delta >= ELEMENT_SIZE {
-                    storage
-                        .write(
-                            self.offset(self.data.offset_of_last() as usize),
-                            &0_i32.to_le_bytes(),
-                        )
-                        .map_err(|_| Error::Storage)?;
-                    if self.data.offset_of_last() == self.data.size() - 1 {
-                        let size = self.data.size() - 1;
-                        self.data.set_size(size);
-                    }
-                    // Handle underflow correctly
-                    if self.data.offset_of_last() == 0 {
-                        self.data.set_offset_of_last(SIZE as u32 - 1);
-                    } else {
-                        let tmp = self.data.offset_of_last() - 1;
-                        self.data.set_offset_of_last(tmp);
-                    }
-                    delta -= ELEMENT_SIZE;
-                }
-                self.write_value(storage, val, time)?;
-                self.write_service_data(storage)?;
+            } else if periods > 1 {
+                self.fill_gap(storage, periods as u32 - 1)?;
             }
+            self.push(storage, entry, time)?;
         }
+        self.clock_adjusted = false;
         Ok(())
     }
 }
+
+/// Iterator over `(timestamp, value)` pairs returned by `RingStorage::iter_range`
+pub struct Records<
+    'a,
+    S,
+    const OFFSET: usize,
+    const SIZE: i32,
+    const ELEMENT_SIZE: i32,
+    R: Record,
+    B: Bucketing,
+> {
+    ring: &'a mut RingStorage<OFFSET, SIZE, ELEMENT_SIZE, R, B>,
+    storage: &'a mut S,
+    next: u32,
+    end: u32,
+}
+
+impl<
+        S: Storage,
+        const OFFSET: usize,
+        const SIZE: i32,
+        const ELEMENT_SIZE: i32,
+        R: Record,
+        B: Bucketing,
+    > Iterator for Records<'_, S, OFFSET, SIZE, ELEMENT_SIZE, R, B>
+{
+    type Item = Result<(u32, Entry<R>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.end {
+            let index = self.next;
+            self.next += 1;
+            let timestamp = self.ring.timestamp_at(index)?;
+            match self.ring.read_record(self.storage, index) {
+                Ok(Some(entry)) => return Some(Ok((timestamp, entry))),
+                // Skipped period
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_storage::MemStorage;
+
+    const T0: u32 = 1_700_000_000 - 1_700_000_000 % 3600;
+
+    #[test]
+    fn test_f32_records_keep_fractions() {
+        let mut storage = MemStorage::new();
+        let mut ring = RingStorage::<0, 24, 3600, f32>::new(&mut storage).unwrap();
+        ring.add(&mut storage, 0.25, T0).unwrap();
+        ring.add(&mut storage, 0.75, T0 + 3600).unwrap();
+
+        let mut ring = RingStorage::<0, 24, 3600, f32>::new(&mut storage).unwrap();
+        assert_eq!(ring.format(), f32::FORMAT);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 3600)
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(0.75)
+        );
+    }
+
+    #[test]
+    fn test_scaled_records() {
+        let mut storage = MemStorage::new();
+        type Ring = RingStorage<0, 24, 3600, Scaled<3>>;
+        assert_eq!(
+            Ring::SIZE_ON_FLASH,
+            16 + 24 * (8 + 1) + HEADER_SLOTS * Ring::SLOT_SIZE
+        );
+
+        let mut ring = Ring::new(&mut storage).unwrap();
+        ring.add(&mut storage, Scaled::from_f32(0.123), T0).unwrap();
+        ring.add(&mut storage, Scaled::from_f32(-1.0), T0 + 3600)
+            .unwrap();
+
+        let mut ring = Ring::new(&mut storage).unwrap();
+        assert_eq!(ring.format(), 0x0203);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 3600)
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(Scaled(0))
+        );
+        let value = ring
+            .find(&mut storage, T0 + 3600)
+            .unwrap()
+            .map(|entry| entry.value);
+        assert_eq!(value.map(|v| v.to_f32()), Some(0.0));
+        assert_eq!(Scaled::<3>::from_f32(0.123), Scaled(123));
+        assert_eq!(Scaled::<3>(123).to_f32(), 0.123);
+    }
+
+    #[test]
+    fn test_flow_stats() {
+        let mut stats = FlowStats::EMPTY;
+        assert!(stats.is_empty());
+        stats.record(2.5, T0 + 60);
+        stats.record(f32::NAN, T0 + 120);
+        stats.record(7.0, T0 + 180);
+        stats.record(-0.5, T0 + 240);
+        stats.record(7.0, T0 + 300);
+        assert!(!stats.is_empty());
+        assert_eq!(
+            stats,
+            FlowStats {
+                min: -0.5,
+                min_at: T0 + 240,
+                max: 7.0,
+                max_at: T0 + 180,
+            }
+        );
+        assert_eq!(stats.to_f32(), 7.0);
+
+        let mut buf = [0_u8; FlowStats::SIZE];
+        stats.encode(&mut buf);
+        assert_eq!(FlowStats::decode(&buf), stats);
+        assert_eq!(&buf[4..8], &(T0 + 240).to_le_bytes());
+    }
+
+    #[test]
+    fn test_flow_stats_ring() {
+        type Ring = RingStorage<0, 24, 3600, FlowStats>;
+        assert_eq!(
+            Ring::SIZE_ON_FLASH,
+            16 + 24 * (16 + 1) + HEADER_SLOTS * Ring::SLOT_SIZE
+        );
+        let stats = |hour: u32| FlowStats {
+            min: hour as f32,
+            min_at: T0 + hour * 3600 + 60,
+            max: hour as f32 * 10.0,
+            max_at: T0 + hour * 3600 + 1200,
+        };
+
+        let mut image = MemStorage::new();
+        let mut ring = Ring::new(&mut image).unwrap();
+        for hour in 0..3 {
+            ring.add(&mut image, stats(hour), T0 + hour * 3600).unwrap();
+        }
+        let mut ring = Ring::new(&mut image).unwrap();
+        assert_eq!(ring.format(), FlowStats::FORMAT);
+        assert_eq!(
+            ring.find(&mut image, T0 + 3600)
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(stats(1))
+        );
+
+        // A cut anywhere in the 16-byte write leaves the old or the new record
+        let mut storage = image.clone();
+        storage.written = 0;
+        ring.add(&mut storage, stats(3), T0 + 3 * 3600).unwrap();
+        let total = storage.written;
+        for cut in 0..total {
+            let mut storage = image.clone();
+            let mut ring = Ring::new(&mut storage).unwrap();
+            storage.written = 0;
+            storage.power_cut_after = Some(cut);
+            assert!(ring.add(&mut storage, stats(3), T0 + 3 * 3600).is_err());
+            storage.power_cut_after = None;
+
+            let mut ring = Ring::new(&mut storage).unwrap();
+            match ring.last_stored_timestamp() {
+                last if last == T0 + 2 * 3600 => {}
+                last => {
+                    assert_eq!(last, T0 + 3 * 3600);
+                    assert_eq!(
+                        ring.find(&mut storage, last)
+                            .unwrap()
+                            .map(|entry| entry.value),
+                        Some(stats(3))
+                    );
+                }
+            }
+            assert_eq!(
+                ring.find(&mut storage, T0 + 2 * 3600)
+                    .unwrap()
+                    .map(|entry| entry.value),
+                Some(stats(2))
+            );
+        }
+    }
+
+    #[test]
+    fn test_untagged_i32_ring_is_migrated() {
+        let mut storage = MemStorage::new();
+        let mut legacy = RingStorage::<0, 24, 3600, i32>::new(&mut storage).unwrap();
+        legacy.add(&mut storage, 5, T0).unwrap();
+        legacy.add(&mut storage, 7, T0 + 3600).unwrap();
+        // Firmware before format tags never wrote the word after ServiceData
+        let tag = 4096 + size_of::<ServiceData>();
+        storage.data[tag..tag + 2].copy_from_slice(&[0xFF, 0xFF]);
+
+        let mut ring = RingStorage::<0, 24, 3600, f32>::new(&mut storage).unwrap();
+        assert_eq!(ring.format(), f32::FORMAT);
+        assert_eq!(ring.data.size(), 2);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 3600)
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(7.0)
+        );
+        assert_eq!(&storage.data[tag..tag + 2], &f32::FORMAT.to_le_bytes());
+
+        // Reopening does not convert twice
+        let mut ring = RingStorage::<0, 24, 3600, f32>::new(&mut storage).unwrap();
+        assert_eq!(
+            ring.find(&mut storage, T0 + 3600)
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(7.0)
+        );
+    }
+
+    #[test]
+    fn test_interrupted_migration_resumes() {
+        let mut storage = MemStorage::new();
+        type Legacy = RingStorage<0, 100, 60, i32>;
+        type Ring = RingStorage<0, 100, 60, f32>;
+        let mut legacy = Legacy::new(&mut storage).unwrap();
+        for i in 0..100 {
+            legacy.add(&mut storage, i, T0 + i as u32 * 60).unwrap();
+        }
+
+        // First chunk converted, then the power went away
+        let first = Legacy::OFFSET + 16;
+        for i in 0..MIGRATE_CHUNK {
+            let slot = first + i * 4;
+            let value = i32::decode(&storage.data[slot..slot + 4]);
+            (value as f32).encode(&mut storage.data[slot..slot + 4]);
+        }
+        let tag = Legacy::OFFSET_OF_FORMAT;
+        storage.data[tag..tag + 2].copy_from_slice(&(FORMAT_MIGRATING | 1).to_le_bytes());
+
+        let mut ring = Ring::new(&mut storage).unwrap();
+        assert_eq!(ring.format(), f32::FORMAT);
+        for i in [0_u32, 63, 64, 99] {
+            let value = ring
+                .find(&mut storage, T0 + i * 60)
+                .unwrap()
+                .map(|entry| entry.value);
+            assert_eq!(value, Some(i as f32));
+        }
+    }
+
+    #[test]
+    fn test_unknown_format_starts_empty() {
+        let mut storage = MemStorage::new();
+        let mut ring = RingStorage::<0, 24, 3600, f32>::new(&mut storage).unwrap();
+        ring.add(&mut storage, 1.5, T0).unwrap();
+
+        // Eight-byte records cannot reuse four-byte slots
+        let ring = RingStorage::<0, 24, 3600, Scaled<2>>::new(&mut storage).unwrap();
+        assert_eq!(ring.data.size(), 0);
+        assert_eq!(ring.format(), Scaled::<2>::FORMAT);
+    }
+
+    type CutRing = RingStorage<0, 4, 3600, f32>;
+
+    /// What `new()` recovers: `ServiceData` plus every record slot
+    fn recovered(storage: &MemStorage) -> (std::vec::Vec<u8>, std::vec::Vec<u8>) {
+        let mut storage = storage.clone();
+        storage.power_cut_after = None;
+        let ring = CutRing::new(&mut storage).unwrap();
+        let slots = CutRing::OFFSET_OF_FORMAT + 2..CutRing::OFFSET_OF_SLOTS;
+        (
+            ring.data.into_bytes().to_vec(),
+            storage.data[slots].to_vec(),
+        )
+    }
+
+    /// Cut the power after every byte `step` writes and check that reopening
+    /// the ring gives either the state before or the state after the step.
+    fn check_power_cuts(image: &MemStorage, step: impl Fn(&mut CutRing, &mut MemStorage)) {
+        let before = recovered(image);
+        let mut storage = image.clone();
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        storage.written = 0;
+        step(&mut ring, &mut storage);
+        let total = storage.written;
+        let after = recovered(&storage);
+        assert_ne!(before, after);
+
+        for cut in 0..total {
+            let mut storage = image.clone();
+            let mut ring = CutRing::new(&mut storage).unwrap();
+            storage.written = 0;
+            storage.power_cut_after = Some(cut);
+            step(&mut ring, &mut storage);
+            let state = recovered(&storage);
+            assert!(
+                state == before || state == after,
+                "inconsistent state after a cut at byte {} of {}",
+                cut,
+                total
+            );
+        }
+    }
+
+    fn ring_with(values: &[f32]) -> MemStorage {
+        let mut storage = MemStorage::new();
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        for (i, value) in values.iter().enumerate() {
+            ring.add(&mut storage, *value, T0 + i as u32 * 3600)
+                .unwrap();
+        }
+        storage
+    }
+
+    #[test]
+    fn test_power_cut_first_record() {
+        check_power_cuts(&ring_with(&[]), |ring, storage| {
+            ring.add(storage, 1.0, T0).ok();
+        });
+    }
+
+    #[test]
+    fn test_power_cut_append() {
+        check_power_cuts(&ring_with(&[1.0, 2.0]), |ring, storage| {
+            ring.add(storage, 3.0, T0 + 2 * 3600).ok();
+        });
+    }
+
+    #[test]
+    fn test_power_cut_wrapped_ring() {
+        check_power_cuts(&ring_with(&[1.0, 2.0, 3.0, 4.0, 5.0]), |ring, storage| {
+            ring.add(storage, 6.0, T0 + 5 * 3600).ok();
+        });
+    }
+
+    #[test]
+    fn test_power_cut_reset_after_long_gap() {
+        check_power_cuts(&ring_with(&[1.0, 2.0]), |ring, storage| {
+            ring.add(storage, 9.0, T0 + 100 * 3600).ok();
+        });
+    }
+
+    #[test]
+    fn test_power_cut_repeated() {
+        // Cut every update of a gap fill in turn, rebooting in between
+        let mut storage = ring_with(&[1.0]);
+        for cut in [3, 20, 31, 40] {
+            let mut ring = CutRing::new(&mut storage).unwrap();
+            storage.written = 0;
+            storage.power_cut_after = Some(cut);
+            ring.add(&mut storage, 4.0, T0 + 3 * 3600).ok();
+            storage.power_cut_after = None;
+        }
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        ring.add(&mut storage, 4.0, T0 + 3 * 3600).unwrap();
+
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        assert_eq!(ring.last_stored_timestamp(), T0 + 3 * 3600);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 3 * 3600)
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(4.0)
+        );
+    }
+
+    #[test]
+    fn test_interrupted_update_is_replayed() {
+        let mut storage = ring_with(&[1.0, 2.0]);
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        // Header slot reaches EEPROM, the record only half
+        storage.written = 0;
+        storage.power_cut_after = Some(CutRing::SLOT_SIZE + 2);
+        assert!(ring.add(&mut storage, 3.0, T0 + 2 * 3600).is_err());
+        storage.power_cut_after = None;
+
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        assert_eq!(ring.size(), 3);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 2 * 3600)
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(3.0)
+        );
+    }
+
+    #[test]
+    fn test_header_rotates_through_slots() {
+        let values: std::vec::Vec<f32> = (0..2 * HEADER_SLOTS).map(|i| i as f32).collect();
+        let mut storage = ring_with(&values);
+        let ring = CutRing::new(&mut storage).unwrap();
+        assert_eq!(ring.write_count(), 2 * HEADER_SLOTS as u32);
+
+        // Every slot is in use and the fixed header is never rewritten
+        for position in 0..HEADER_SLOTS {
+            let offset = CutRing::slot_offset(position) as usize;
+            assert_ne!(
+                storage.data[offset + CutRing::SLOT_CRC..offset + CutRing::SLOT_SIZE],
+                [0xFF, 0xFF]
+            );
+        }
+        let header = CutRing::OFFSET;
+        assert!(storage.data[header..header + size_of::<ServiceData>()]
+            .iter()
+            .all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn test_newest_slot_wins() {
+        let mut storage = ring_with(&[1.0, 2.0, 3.0]);
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        assert_eq!(ring.write_count(), 3);
+        assert_eq!(ring.last_stored_timestamp(), T0 + 2 * 3600);
+        assert_eq!(ring.size(), 3);
+
+        // A torn slot falls back to the generation before it
+        let offset = CutRing::slot_offset(3) as usize;
+        storage.data[offset + SLOT_DATA] ^= 0xFF;
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        assert_eq!(ring.write_count(), 2);
+        assert_eq!(ring.last_stored_timestamp(), T0 + 3600);
+        assert_eq!(ring.size(), 2);
+    }
+
+    #[test]
+    fn test_fixed_header_is_read_before_first_rotation() {
+        let mut storage = MemStorage::new();
+        let mut data = ServiceData::new();
+        data.set_size(1);
+        data.set_offset_of_last(1);
+        data.set_time_of_last(T0);
+        let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&data.into_bytes()[..12]);
+        data.set_crc(crc);
+        let header = CutRing::OFFSET;
+        storage.data[header..header + size_of::<ServiceData>()].copy_from_slice(&data.into_bytes());
+        2.5_f32.encode(&mut storage.data[header + 16..header + 20]);
+        storage.data[header + 14..header + 16].copy_from_slice(&f32::FORMAT.to_le_bytes());
+
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        assert_eq!(ring.write_count(), 0);
+        assert_eq!(
+            ring.find(&mut storage, T0)
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(2.5)
+        );
+
+        ring.add(&mut storage, 3.5, T0 + 3600).unwrap();
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        assert_eq!(ring.write_count(), 1);
+        assert_eq!(ring.size(), 2);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 3600)
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(3.5)
+        );
+    }
+
+    /// Midnight UTC of the given date
+    fn midnight(year: i32, month: u8, day: u8) -> u32 {
+        let month = Month::try_from(month).unwrap();
+        Date::from_calendar_date(year, month, day)
+            .unwrap()
+            .midnight()
+            .assume_utc()
+            .unix_timestamp() as u32
+    }
+
+    #[test]
+    fn test_monthly_bucketing() {
+        let jan = midnight(2024, 1, 1);
+        let feb = midnight(2024, 2, 1);
+        let mar = midnight(2024, 3, 1);
+        assert_eq!(Monthly.start(midnight(2024, 2, 29) + 3600), feb);
+        assert_eq!(Monthly.advance(jan, 1), feb);
+        assert_eq!(Monthly.advance(jan, 2), mar);
+        assert_eq!(Monthly.advance(mar, -14), midnight(2023, 1, 1));
+        assert_eq!(Monthly.periods(jan, midnight(2025, 3, 1)), 14);
+        assert_eq!(Monthly.periods(mar, jan), -2);
+        // 29 days in February 2024, 28 in 2023
+        assert_eq!(mar - feb, 29 * 86400);
+        assert_eq!(Monthly.advance(feb, -12), midnight(2023, 2, 1));
+        assert_eq!(
+            Monthly.advance(midnight(2023, 2, 1), 1) - midnight(2023, 2, 1),
+            28 * 86400
+        );
+    }
+
+    #[test]
+    fn test_hourly_and_daily_bucketing() {
+        let day = midnight(2024, 3, 31);
+        assert_eq!(Hourly.start(day + 5400), day + 3600);
+        assert_eq!(Hourly.periods(day, day + 86400), 24);
+        assert_eq!(Daily.start(day + 86399), day);
+        assert_eq!(Daily.advance(day, 1), midnight(2024, 4, 1));
+        assert_eq!(
+            Daily.periods(midnight(2024, 2, 28), midnight(2024, 3, 1)),
+            2
+        );
+    }
+
+    #[test]
+    fn test_month_ring_follows_calendar() {
+        type MonthRing = RingStorage<0, 24, { 3600 * 24 * 31 }, f32, Monthly>;
+        let mut storage = MemStorage::new();
+        let mut ring = MonthRing::new(&mut storage).unwrap();
+        // Logged a few seconds after midnight on the first of the month
+        ring.add(&mut storage, 10.0, midnight(2023, 12, 1) + 3)
+            .unwrap();
+        ring.add(&mut storage, 20.0, midnight(2024, 1, 1) + 3)
+            .unwrap();
+        // Offline through February and March
+        ring.add(&mut storage, 40.0, midnight(2024, 4, 1) + 3)
+            .unwrap();
+
+        assert_eq!(ring.size(), 5);
+        assert_eq!(ring.last_stored_timestamp(), midnight(2024, 4, 1));
+        assert_eq!(ring.first_stored_timestamp(), midnight(2023, 12, 1));
+        assert_eq!(ring.timestamp_at(2), Some(midnight(2024, 2, 1)));
+        assert_eq!(ring.timestamp_at(3), Some(midnight(2024, 3, 1)));
+        // Any time within a month selects that month
+        assert_eq!(ring.index_of(midnight(2024, 2, 29) + 7200), Some(2));
+        assert_eq!(
+            ring.find(&mut storage, midnight(2024, 3, 1))
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(0.0)
+        );
+        assert_eq!(
+            ring.find(&mut storage, midnight(2024, 4, 15))
+                .unwrap()
+                .map(|entry| entry.value),
+            Some(40.0)
+        );
+    }
+
+    fn collect(
+        ring: &mut CutRing,
+        storage: &mut MemStorage,
+        from: u32,
+        to: u32,
+    ) -> std::vec::Vec<(u32, f32)> {
+        ring.iter_range(storage, from, to)
+            .map(|record| record.map(|(time, entry)| (time, entry.value)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_find_in_wrapped_ring() {
+        // Six records through four slots: the two oldest are overwritten
+        let mut storage = ring_with(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        assert_eq!(
+            ring.find(&mut storage, T0 + 3600)
+                .unwrap()
+                .map(|entry| entry.value),
+            None
+        );
+        for i in 2..6 {
+            let time = T0 + i * 3600;
+            assert_eq!(
+                ring.find(&mut storage, time)
+                    .unwrap()
+                    .map(|entry| entry.value),
+                Some(i as f32 + 1.0)
+            );
+        }
+
+        // No scan: one read for the record, one for its status
+        storage.reads = 0;
+        ring.find(&mut storage, T0 + 2 * 3600).unwrap();
+        assert_eq!(storage.reads, 2);
+    }
+
+    #[test]
+    fn test_iter_range() {
+        let mut storage = ring_with(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        let all = collect(&mut ring, &mut storage, 0, u32::MAX);
+        let expected: std::vec::Vec<(u32, f32)> =
+            (2..6).map(|i| (T0 + i * 3600, i as f32 + 1.0)).collect();
+        assert_eq!(all, expected);
+
+        // Half-open range, bounds between records
+        let part = collect(&mut ring, &mut storage, T0 + 3 * 3600 - 1, T0 + 5 * 3600);
+        assert_eq!(part, &expected[1..3]);
+        assert!(collect(&mut ring, &mut storage, T0 + 5 * 3600 + 1, u32::MAX).is_empty());
+        assert!(collect(&mut ring, &mut storage, T0 + 5 * 3600, T0).is_empty());
+    }
+
+    #[test]
+    fn test_find_nearest() {
+        let mut storage = ring_with(&[1.0, 2.0, 3.0]);
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        let before = |ring: &mut CutRing, storage: &mut MemStorage, time| {
+            let nearest = ring.find_nearest(storage, time, Direction::Before).unwrap();
+            nearest.map(|(time, entry)| (time, entry.value))
+        };
+        let after = |ring: &mut CutRing, storage: &mut MemStorage, time| {
+            let nearest = ring.find_nearest(storage, time, Direction::After).unwrap();
+            nearest.map(|(time, entry)| (time, entry.value))
+        };
+
+        assert_eq!(before(&mut ring, &mut storage, T0), None);
+        assert_eq!(before(&mut ring, &mut storage, T0 + 1800), Some((T0, 1.0)));
+        assert_eq!(before(&mut ring, &mut storage, T0 + 3600), Some((T0, 1.0)));
+        assert_eq!(
+            before(&mut ring, &mut storage, u32::MAX),
+            Some((T0 + 7200, 3.0))
+        );
+        assert_eq!(after(&mut ring, &mut storage, 0), Some((T0, 1.0)));
+        assert_eq!(after(&mut ring, &mut storage, T0), Some((T0 + 3600, 2.0)));
+        assert_eq!(
+            after(&mut ring, &mut storage, T0 + 3601),
+            Some((T0 + 7200, 3.0))
+        );
+        assert_eq!(after(&mut ring, &mut storage, T0 + 7200), None);
+
+        // Step through every record
+        let mut time = 0;
+        let mut seen = std::vec::Vec::new();
+        while let Some((timestamp, value)) = after(&mut ring, &mut storage, time) {
+            seen.push(value);
+            time = timestamp;
+        }
+        assert_eq!(seen, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_record_status() {
+        let mut storage = ring_with(&[1.0]);
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        // Offline for two hours, then an estimated value
+        ring.add_with_status(&mut storage, 4.0, Status::Estimated, T0 + 3 * 3600)
+            .unwrap();
+
+        let mut ring = CutRing::new(&mut storage).unwrap();
+        let statuses: std::vec::Vec<Status> = ring
+            .iter_range(&mut storage, 0, u32::MAX)
+            .map(|record| record.unwrap().1.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                Status::Valid,
+                Status::GapFilled,
+                Status::GapFilled,
+                Status::Estimated
+            ]
+        );
+        let gap = ring.find(&mut storage, T0 + 3600).unwrap().unwrap();
+        assert_eq!(gap.value, 0.0);
+        assert_eq!(gap.status, Status::GapFilled);
+    }
+
+    #[test]
+    fn test_migrated_records_are_valid() {
+        let mut storage = MemStorage::new();
+        let mut legacy = RingStorage::<0, 24, 3600, i32>::new(&mut storage).unwrap();
+        legacy.add(&mut storage, 5, T0).unwrap();
+        legacy.add(&mut storage, 7, T0 + 3600).unwrap();
+        // Leftovers of an older layout where the status area is now
+        let status = RingStorage::<0, 24, 3600, f32>::OFFSET_OF_STATUS;
+        storage.data[status..status + 24].fill(0x02);
+        let tag = RingStorage::<0, 24, 3600, f32>::OFFSET_OF_FORMAT;
+        storage.data[tag..tag + 2].copy_from_slice(&[0xFF, 0xFF]);
+
+        let mut ring = RingStorage::<0, 24, 3600, f32>::new(&mut storage).unwrap();
+        let entry = ring.find(&mut storage, T0).unwrap();
+        assert_eq!(entry, Some(Entry::valid(5.0)));
+    }
+
+    type OutageRing = RingStorage<0, 48, 3600, f32, Hourly>;
+    const HOUR: u32 = 3600;
+
+    fn hourly(
+        storage: &mut MemStorage,
+        policy: GapFill,
+        hours: core::ops::Range<u32>,
+    ) -> OutageRing {
+        let mut ring = OutageRing::new(storage).unwrap().with_gap_fill(policy);
+        for hour in hours {
+            ring.add(storage, hour as f32 + 1.0, T0 + hour * HOUR)
+                .unwrap();
+        }
+        ring
+    }
+
+    fn all_records(
+        ring: &mut OutageRing,
+        storage: &mut MemStorage,
+    ) -> std::vec::Vec<(u32, Entry<f32>)> {
+        ring.iter_range(storage, 0, u32::MAX)
+            .map(|record| record.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_multi_day_outage_keeps_history() {
+        type WeekRing = RingStorage<0, 200, 3600, f32, Hourly>;
+        let mut storage = MemStorage::new();
+        let mut ring = WeekRing::new(&mut storage).unwrap();
+        for hour in 0..24 {
+            ring.add(&mut storage, hour as f32 + 1.0, T0 + hour * HOUR)
+                .unwrap();
+        }
+        let writes = ring.write_count();
+
+        // Three days without power
+        let back = T0 + 96 * HOUR;
+        ring.add(&mut storage, 99.0, back).unwrap();
+        // The gap costs no extra header updates
+        assert_eq!(ring.write_count(), writes + 1);
+
+        let mut ring = WeekRing::new(&mut storage).unwrap();
+        assert_eq!(ring.size(), 97);
+        assert_eq!(ring.first_stored_timestamp(), T0);
+        for hour in 0..24 {
+            let entry = ring.find(&mut storage, T0 + hour * HOUR).unwrap();
+            assert_eq!(entry, Some(Entry::valid(hour as f32 + 1.0)));
+        }
+        let records: std::vec::Vec<_> = ring
+            .iter_range(&mut storage, T0 + 24 * HOUR, back)
+            .map(|record| record.unwrap().1)
+            .collect();
+        assert_eq!(records.len(), 72);
+        assert!(records.iter().all(|entry| *entry
+            == Entry {
+                value: 0.0,
+                status: Status::GapFilled
+            }));
+        assert_eq!(
+            ring.find(&mut storage, back).unwrap(),
+            Some(Entry::valid(99.0))
+        );
+    }
+
+    #[test]
+    fn test_outage_keeps_records_still_in_range() {
+        let mut storage = MemStorage::new();
+        // 60 hours through 48 slots, then 30 hours offline
+        let mut ring = hourly(&mut storage, GapFill::Zero, 0..60);
+        ring.add(&mut storage, 99.0, T0 + 90 * HOUR).unwrap();
+
+        let mut ring = OutageRing::new(&mut storage).unwrap();
+        assert_eq!(ring.first_stored_timestamp(), T0 + 43 * HOUR);
+        for hour in 43..60 {
+            let entry = ring.find(&mut storage, T0 + hour * HOUR).unwrap();
+            assert_eq!(entry, Some(Entry::valid(hour as f32 + 1.0)));
+        }
+        let gap = ring.find(&mut storage, T0 + 75 * HOUR).unwrap().unwrap();
+        assert_eq!(gap.status, Status::GapFilled);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 90 * HOUR).unwrap(),
+            Some(Entry::valid(99.0))
+        );
+    }
+
+    #[test]
+    fn test_outage_longer_than_ring() {
+        let mut storage = MemStorage::new();
+        let mut ring = hourly(&mut storage, GapFill::Zero, 0..10);
+        ring.add(&mut storage, 99.0, T0 + 10 * 24 * HOUR).unwrap();
+
+        let mut ring = OutageRing::new(&mut storage).unwrap();
+        assert_eq!(ring.size(), 1);
+        assert_eq!(ring.first_stored_timestamp(), T0 + 10 * 24 * HOUR);
+        assert_eq!(all_records(&mut ring, &mut storage).len(), 1);
+    }
+
+    #[test]
+    fn test_gap_fill_marker() {
+        let mut storage = MemStorage::new();
+        let mut ring = hourly(&mut storage, GapFill::Marker, 0..2);
+        ring.add(&mut storage, 5.0, T0 + 5 * HOUR).unwrap();
+
+        let gap = ring.find(&mut storage, T0 + 3 * HOUR).unwrap().unwrap();
+        assert!(gap.value.is_nan());
+        assert_eq!(gap.status, Status::GapFilled);
+        assert_eq!(ring.size(), 6);
+    }
+
+    #[test]
+    fn test_gap_fill_skip() {
+        let mut storage = MemStorage::new();
+        let mut ring = hourly(&mut storage, GapFill::Skip, 0..2);
+        ring.add(&mut storage, 5.0, T0 + 5 * HOUR).unwrap();
+
+        assert_eq!(ring.size(), 6);
+        assert_eq!(ring.find(&mut storage, T0 + 3 * HOUR).unwrap(), None);
+        let times: std::vec::Vec<u32> = all_records(&mut ring, &mut storage)
+            .iter()
+            .map(|(time, _)| (time - T0) / HOUR)
+            .collect();
+        assert_eq!(times, [0, 1, 5]);
+        let next = ring
+            .find_nearest(&mut storage, T0 + HOUR, Direction::After)
+            .unwrap();
+        assert_eq!(next, Some((T0 + 5 * HOUR, Entry::valid(5.0))));
+        let previous = ring
+            .find_nearest(&mut storage, T0 + 5 * HOUR, Direction::Before)
+            .unwrap();
+        assert_eq!(previous, Some((T0 + HOUR, Entry::valid(2.0))));
+    }
+
+    #[test]
+    fn test_power_cut_during_outage_fill() {
+        // Full ring: the gap overwrites old records, so the cut may also land
+        // after they were dropped. No record may ever show a wrong value.
+        let mut image = MemStorage::new();
+        hourly(&mut image, GapFill::Zero, 0..50);
+        let mut storage = image.clone();
+        let mut ring = OutageRing::new(&mut storage).unwrap();
+        storage.written = 0;
+        ring.add(&mut storage, 99.0, T0 + 60 * HOUR).unwrap();
+        let total = storage.written;
+        let before = all_records(
+            &mut OutageRing::new(&mut image.clone()).unwrap(),
+            &mut image.clone(),
+        );
+        let after = all_records(&mut ring, &mut storage);
+
+        for cut in 0..total {
+            let mut storage = image.clone();
+            let mut ring = OutageRing::new(&mut storage).unwrap();
+            storage.written = 0;
+            storage.power_cut_after = Some(cut);
+            assert!(ring.add(&mut storage, 99.0, T0 + 60 * HOUR).is_err());
+            storage.power_cut_after = None;
+
+            let mut ring = OutageRing::new(&mut storage).unwrap();
+            let last = ring.last_stored_timestamp();
+            assert!(last == T0 + 49 * HOUR || last == T0 + 60 * HOUR);
+            for record in all_records(&mut ring, &mut storage) {
+                assert!(
+                    before.contains(&record) || after.contains(&record),
+                    "wrong record {:?} after a cut at byte {}",
+                    record,
+                    cut
+                );
+            }
+        }
+    }
+
+    fn values(ring: &mut OutageRing, storage: &mut MemStorage) -> std::vec::Vec<f32> {
+        all_records(ring, storage)
+            .iter()
+            .map(|(_, entry)| entry.value)
+            .collect()
+    }
+
+    #[test]
+    fn test_clock_set_back_keeps_records() {
+        let mut storage = MemStorage::new();
+        let mut ring = hourly(&mut storage, GapFill::Zero, 0..10);
+        let stored = values(&mut ring, &mut storage);
+
+        ring.clock_changed(&mut storage, T0 + 3 * HOUR + 120)
+            .unwrap();
+        let newest = ring.find(&mut storage, T0 + 9 * HOUR).unwrap().unwrap();
+        assert_eq!(newest.status, Status::ClockAdjusted);
+
+        // Periods already stored are refused, the caller keeps its total
+        for hour in 4..10 {
+            let result = ring.add(&mut storage, 50.0, T0 + hour * HOUR);
+            assert!(matches!(result, Err(Error::ClockBehind)));
+        }
+        ring.add(&mut storage, 60.0, T0 + 10 * HOUR).unwrap();
+        ring.add(&mut storage, 1.0, T0 + 11 * HOUR).unwrap();
+
+        let mut ring = OutageRing::new(&mut storage).unwrap();
+        let mut expected = stored;
+        expected.extend([60.0, 1.0]);
+        assert_eq!(values(&mut ring, &mut storage), expected);
+        let caught_up = ring.find(&mut storage, T0 + 10 * HOUR).unwrap().unwrap();
+        assert_eq!(caught_up.status, Status::ClockAdjusted);
+        let next = ring.find(&mut storage, T0 + 11 * HOUR).unwrap().unwrap();
+        assert_eq!(next.status, Status::Valid);
+    }
+
+    #[test]
+    fn test_large_backwards_jump() {
+        let mut storage = MemStorage::new();
+        let mut ring = hourly(&mut storage, GapFill::Zero, 0..60);
+        let stored = values(&mut ring, &mut storage);
+        let writes = ring.write_count();
+
+        // A mistaken `date set` a year back
+        let year_ago = T0 - 365 * 24 * HOUR;
+        ring.clock_changed(&mut storage, year_ago).unwrap();
+        for hour in 0..100 {
+            let result = ring.add(&mut storage, 1.0, year_ago + hour * HOUR);
+            assert!(matches!(result, Err(Error::ClockBehind)));
+        }
+
+        let mut ring = OutageRing::new(&mut storage).unwrap();
+        assert_eq!(ring.write_count(), writes + 1);
+        assert_eq!(ring.size(), 48);
+        assert_eq!(ring.last_stored_timestamp(), T0 + 59 * HOUR);
+        assert_eq!(values(&mut ring, &mut storage), stored);
+    }
+
+    #[test]
+    fn test_record_in_same_period_is_refused() {
+        let mut storage = MemStorage::new();
+        let mut ring = hourly(&mut storage, GapFill::Zero, 0..3);
+        let result = ring.add(&mut storage, 9.0, T0 + 2 * HOUR + 1800);
+        assert!(matches!(result, Err(Error::ClockBehind)));
+        assert_eq!(values(&mut ring, &mut storage), [1.0, 2.0, 3.0]);
+        // A repeated call is not a clock change
+        ring.add(&mut storage, 4.0, T0 + 3 * HOUR).unwrap();
+        let entry = ring.find(&mut storage, T0 + 3 * HOUR).unwrap();
+        assert_eq!(entry, Some(Entry::valid(4.0)));
+    }
+
+    #[test]
+    fn test_clock_set_forward() {
+        let mut storage = MemStorage::new();
+        let mut ring = hourly(&mut storage, GapFill::Zero, 0..3);
+        let writes = ring.write_count();
+        ring.clock_changed(&mut storage, T0 + 5 * HOUR + 600)
+            .unwrap();
+        assert_eq!(ring.write_count(), writes);
+
+        ring.add(&mut storage, 4.0, T0 + 6 * HOUR).unwrap();
+        let gap = ring.find(&mut storage, T0 + 4 * HOUR).unwrap().unwrap();
+        assert_eq!(gap.status, Status::GapFilled);
+        let entry = ring.find(&mut storage, T0 + 6 * HOUR).unwrap().unwrap();
+        assert_eq!(
+            entry,
+            Entry {
+                value: 4.0,
+                status: Status::ClockAdjusted
+            }
+        );
+        assert_eq!(
+            ring.find(&mut storage, T0 + 2 * HOUR).unwrap(),
+            Some(Entry::valid(3.0))
+        );
+    }
+
+    #[test]
+    fn test_clock_change_on_empty_ring() {
+        let mut storage = MemStorage::new();
+        let mut ring = OutageRing::new(&mut storage).unwrap();
+        ring.clock_changed(&mut storage, T0).unwrap();
+        assert_eq!(ring.write_count(), 0);
+        ring.add(&mut storage, 1.0, T0).unwrap();
+        assert_eq!(ring.size(), 1);
+    }
+
+    #[test]
+    fn test_power_cut_clock_set_back() {
+        check_power_cuts(&ring_with(&[1.0, 2.0, 3.0]), |ring, storage| {
+            ring.clock_changed(storage, T0).ok();
+        });
+    }
+
+    type ProfileRing = RingStorage<0, 96, 900, f32, Interval>;
+
+    #[test]
+    fn test_interval_bucketing() {
+        let quarter = Interval::minutes(15);
+        assert_eq!(quarter.seconds(), 900);
+        assert_eq!(quarter.start(T0 + 1799), T0 + 900);
+        assert_eq!(quarter.advance(T0, 4), T0 + 3600);
+        assert_eq!(quarter.periods(T0, T0 + 86400), 96);
+        assert_eq!(Interval::default(), quarter);
+        assert_eq!(Interval::minutes(0).seconds(), 60);
+    }
+
+    #[test]
+    fn test_load_profile_ring() {
+        let mut storage = MemStorage::new();
+        let five = Interval::minutes(5);
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        assert!(ring.set_bucketing(&mut storage, five).unwrap());
+        for i in 0..12 {
+            ring.add(&mut storage, i as f32, T0 + i * 300 + 10).unwrap();
+        }
+        // One interval missed
+        ring.add(&mut storage, 13.0, T0 + 13 * 300).unwrap();
+
+        // The interval is read back from the ring
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        assert_eq!(ring.bucketing(), five);
+        assert_eq!(ring.size(), 14);
+        assert_eq!(ring.first_stored_timestamp(), T0);
+        assert_eq!(ring.last_stored_timestamp(), T0 + 13 * 300);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 5 * 300 + 299).unwrap(),
+            Some(Entry::valid(5.0))
+        );
+        let gap = ring.find(&mut storage, T0 + 12 * 300).unwrap().unwrap();
+        assert_eq!(gap.status, Status::GapFilled);
+    }
+
+    #[test]
+    fn test_ring_depth() {
+        let mut storage = MemStorage::new();
+        let mut ring = ProfileRing::new(&mut storage).unwrap().with_depth(10);
+        for i in 0..30 {
+            ring.add(&mut storage, i as f32, T0 + i * 900).unwrap();
+        }
+        assert_eq!(ring.size(), 10);
+        assert_eq!(ring.first_stored_timestamp(), T0 + 20 * 900);
+
+        // A gap longer than the depth leaves only the new record
+        ring.add(&mut storage, 99.0, T0 + 45 * 900).unwrap();
+        assert_eq!(ring.size(), 1);
+
+        // Lowering the depth drops the oldest records from the window
+        for i in 46..55 {
+            ring.add(&mut storage, i as f32, T0 + i * 900).unwrap();
+        }
+        let mut ring = ProfileRing::new(&mut storage).unwrap().with_depth(4);
+        assert_eq!(ring.size(), 4);
+        assert_eq!(ring.first_stored_timestamp(), T0 + 51 * 900);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 51 * 900).unwrap(),
+            Some(Entry::valid(51.0))
+        );
+        assert_eq!(ProfileRing::new_empty().with_depth(0).depth(), 1);
+        assert_eq!(ProfileRing::new_empty().with_depth(u32::MAX).depth(), 96);
+    }
+
+    #[test]
+    fn test_ring_set_bucketing() {
+        let mut storage = MemStorage::new();
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        for i in 0..4 {
+            ring.add(&mut storage, i as f32, T0 + i * 900).unwrap();
+        }
+
+        // The same interval keeps the records
+        assert!(!ring
+            .set_bucketing(&mut storage, Interval::minutes(15))
+            .unwrap());
+        assert_eq!(ring.size(), 4);
+
+        // Another one clears them, also after a restart
+        assert!(ring
+            .set_bucketing(&mut storage, Interval::minutes(30))
+            .unwrap());
+        assert_eq!(ring.size(), 0);
+        ring.add(&mut storage, 7.0, T0 + 3600).unwrap();
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        assert_eq!(ring.bucketing(), Interval::minutes(30));
+        assert!(!ring
+            .set_bucketing(&mut storage, Interval::minutes(30))
+            .unwrap());
+        assert_eq!(ring.size(), 1);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 3600 + 1799).unwrap(),
+            Some(Entry::valid(7.0))
+        );
+
+        // The depth changes without clearing
+        ring.add(&mut storage, 8.0, T0 + 5400).unwrap();
+        ring.set_depth(1);
+        assert_eq!(ring.size(), 1);
+        assert_eq!(ring.first_stored_timestamp(), T0 + 5400);
+    }
+
+    #[test]
+    fn test_ring_interval_changed_while_off() {
+        let mut storage = MemStorage::new();
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        for i in 0..4 {
+            ring.add(&mut storage, i as f32, T0 + i * 900).unwrap();
+        }
+
+        // Options written with a new interval, then a restart
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        assert_eq!(ring.bucketing(), Interval::default());
+        assert!(ring
+            .set_bucketing(&mut storage, Interval::minutes(5))
+            .unwrap());
+        assert_eq!(ring.size(), 0);
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        assert_eq!(ring.size(), 0);
+        assert_eq!(ring.bucketing(), Interval::minutes(5));
+
+        // Word not written yet, e.g. by older firmware: the default interval
+        let mut storage = MemStorage::new();
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        ring.add(&mut storage, 1.0, T0).unwrap();
+        storage
+            .write(ProfileRing::OFFSET_OF_BUCKETING as u32, &[0xFF; 4])
+            .unwrap();
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        assert_eq!(ring.bucketing(), Interval::default());
+        assert_eq!(ring.size(), 1);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut storage = MemStorage::new();
+        let mut ring = ProfileRing::new(&mut storage).unwrap();
+        for i in 0..5 {
+            ring.add(&mut storage, i as f32, T0 + i * 900).unwrap();
+        }
+        ring.clear(&mut storage).unwrap();
+        assert_eq!(ProfileRing::new(&mut storage).unwrap().size(), 0);
+
+        // Restart at a different interval
+        let mut ring = ProfileRing::new(&mut storage)
+            .unwrap()
+            .with_bucketing(Interval::minutes(30));
+        ring.add(&mut storage, 1.0, T0 + 1800).unwrap();
+        assert_eq!(ring.size(), 1);
+        assert_eq!(
+            ring.find(&mut storage, T0 + 1800).unwrap(),
+            Some(Entry::valid(1.0))
+        );
+    }
+}
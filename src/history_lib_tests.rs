@@ -1,6 +1,7 @@
 #![cfg(test)]
 
-use crate::history_lib::{RingStorage, ServiceData};
+use crate::history::{RingStorage, ServiceData};
+use crate::history_tests::MockHistoryStorage;
 
 #[test]
 fn test_service_data_default() {
@@ -43,9 +44,7 @@ fn test_advance_offset_wrapping() {
     const ELEMENT_SIZE: i32 = 60;
     const OFFSET: usize = 0;
 
-    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE> {
-        data: ServiceData::default(),
-    };
+    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE>::new_empty();
 
     // Test offset advancing without wrapping
     rs.data.set_offset_of_last(5);
@@ -64,14 +63,14 @@ fn test_size_increment() {
     const ELEMENT_SIZE: i32 = 60;
     const OFFSET: usize = 0;
 
-    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE> {
-        data: ServiceData::default(),
-    };
+    let mut eeprom = MockHistoryStorage::new(8192);
+    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE>::new(&mut eeprom).unwrap();
 
     assert_eq!(rs.data.size(), 0);
-    let tmp = rs.data.size() + 1;
-    rs.data.set_size(tmp);
+    rs.add(&mut eeprom, 1, 6000).unwrap();
     assert_eq!(rs.data.size(), 1);
+    rs.add(&mut eeprom, 2, 6060).unwrap();
+    assert_eq!(rs.data.size(), 2);
 }
 
 #[test]
@@ -92,9 +91,7 @@ fn test_first_stored_timestamp_empty() {
     const ELEMENT_SIZE: i32 = 60;
     const OFFSET: usize = 0;
 
-    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE> {
-        data: ServiceData::default(),
-    };
+    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE>::new_empty();
 
     rs.data.set_time_of_last(5000);
     rs.data.set_size(0);
@@ -108,16 +105,16 @@ fn test_first_stored_timestamp_with_data() {
     const ELEMENT_SIZE: i32 = 60;
     const OFFSET: usize = 0;
 
-    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE> {
-        data: ServiceData::default(),
-    };
-
-    rs.data.set_time_of_last(5000);
-    rs.data.set_size(5);
+    let mut eeprom = MockHistoryStorage::new(8192);
+    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE>::new(&mut eeprom).unwrap();
+    for i in 0..5 {
+        rs.add(&mut eeprom, i, 4800 + i as u32 * 60).unwrap();
+    }
 
     // first_timestamp = last_timestamp - ELEMENT_SIZE * (size - 1)
-    // = 5000 - 60 * 4 = 5000 - 240 = 4760
-    assert_eq!(rs.first_stored_timestamp(), 4760);
+    // = 5040 - 60 * 4 = 5040 - 240 = 4800
+    assert_eq!(rs.last_stored_timestamp(), 5040);
+    assert_eq!(rs.first_stored_timestamp(), 4800);
 }
 
 #[test]
@@ -126,9 +123,7 @@ fn test_last_stored_timestamp() {
     const ELEMENT_SIZE: i32 = 60;
     const OFFSET: usize = 0;
 
-    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE> {
-        data: ServiceData::default(),
-    };
+    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE>::new_empty();
 
     rs.data.set_time_of_last(9999);
     assert_eq!(rs.last_stored_timestamp(), 9999);
@@ -140,9 +135,7 @@ fn test_multiple_advances() {
     const ELEMENT_SIZE: i32 = 60;
     const OFFSET: usize = 0;
 
-    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE> {
-        data: ServiceData::default(),
-    };
+    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE>::new_empty();
 
     rs.data.set_offset_of_last(0);
 
@@ -159,9 +152,7 @@ fn test_offset_calculation() {
     const ELEMENT_SIZE: i32 = 60;
     const OFFSET: usize = 0;
 
-    let mut rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE> {
-        data: ServiceData::default(),
-    };
+    let rs = RingStorage::<OFFSET, RING_SIZE, ELEMENT_SIZE>::new_empty();
 
     // offset = Self::OFFSET + size_of::<ServiceData>() + size_of::<u16>() + (index * 4)
     let offset0 = rs.offset(0);
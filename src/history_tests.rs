@@ -1,16 +1,17 @@
 #![cfg(test)]
 
-use crate::history_lib::*;
+use crate::history::*;
 use core::mem::size_of;
+use embedded_storage::{ReadStorage, Storage};
 
 // ============================================================================
 // MOCK STORAGE IMPLEMENTATION
 // ============================================================================
 
-/// Mock storage for testing RingStorage without actual flash operations
+/// In-memory EEPROM for testing RingStorage without the SPI bus
 #[derive(Debug, Clone)]
-struct MockHistoryStorage {
-    /// Storage buffer simulating flash memory
+pub(crate) struct MockHistoryStorage {
+    /// Storage buffer simulating the EEPROM
     buffer: Vec<u8>,
     /// Records stored
     records: Vec<u32>,
@@ -25,9 +26,10 @@ struct MockHistoryStorage {
 }
 
 impl MockHistoryStorage {
-    fn new(capacity: usize) -> Self {
+    pub(crate) fn new(capacity: usize) -> Self {
         Self {
-            buffer: vec![0u8; capacity],
+            // Erased EEPROM reads as 0xFF
+            buffer: vec![0xFFu8; capacity],
             records: Vec::new(),
             write_count: 0,
             read_count: 0,
@@ -52,6 +54,43 @@ impl MockHistoryStorage {
     }
 }
 
+impl ReadStorage for MockHistoryStorage {
+    type Error = Error;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        if let Some(error) = self.last_error {
+            return Err(error);
+        }
+        let start = offset as usize;
+        let data = self
+            .buffer
+            .get(start..start + bytes.len())
+            .ok_or(Error::Storage)?;
+        bytes.copy_from_slice(data);
+        self.read_count += 1;
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl Storage for MockHistoryStorage {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        if let Some(error) = self.last_error {
+            return Err(error);
+        }
+        let start = offset as usize;
+        self.buffer
+            .get_mut(start..start + bytes.len())
+            .ok_or(Error::Storage)?
+            .copy_from_slice(bytes);
+        self.write_count += 1;
+        Ok(())
+    }
+}
+
 /// Statistics structure for mock storage assertions
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct StorageStats {
@@ -211,15 +250,14 @@ mod ring_storage_tests {
 
     #[test]
     fn test_ring_storage_size_on_flash() {
-        // SIZE_ON_FLASH = sizeof(u32) + SIZE + sizeof(ServiceData) + sizeof(u16)
-        let _storage: RingStorage<0, 100, 10> = RingStorage::new_empty();
-        let expected_size = size_of::<u32>() + 100 + size_of::<ServiceData>() + size_of::<u16>();
-        assert!(expected_size > 0);
+        // ServiceData + format tag + SIZE * (record + status) + 8 header slots
+        let expected_size = size_of::<ServiceData>() + size_of::<u16>() + 100 * 5 + 8 * 35;
+        assert_eq!(RingStorage::<0, 100, 10>::SIZE_ON_FLASH, expected_size);
     }
 
     #[test]
     fn test_ring_storage_offset_calculation() {
-        let mut storage: RingStorage<0, 100, 10> = RingStorage::new_empty();
+        let storage: RingStorage<0, 100, 10> = RingStorage::new_empty();
 
         let offset_0 = storage.offset(0);
         let offset_1 = storage.offset(1);
@@ -523,24 +561,59 @@ mod history_integration_tests {
 
     #[test]
     fn test_ring_storage_full_workflow_with_mock() {
-        let mut storage: RingStorage<0, 10, 5> = RingStorage::new_empty();
+        let mut eeprom = MockHistoryStorage::new(8192);
+        let mut storage: RingStorage<0, 10, 60> = RingStorage::new(&mut eeprom).unwrap();
         let mut state = HistoryStateMock::new();
-        let mut mock_records = MockHistoryStorage::new(1024);
 
         // Initial state
         assert_eq!(storage.data.size(), 0);
         state.update_size(storage.data.size());
 
-        // Add some operations
-        for _ in 0..5 {
-            storage.advance_offset_by_one();
-            mock_records.write_count += 1;
+        // Add some records
+        for i in 0..5 {
+            storage
+                .add(&mut eeprom, i * 10, 6000 + i as u32 * 60)
+                .unwrap();
         }
 
         state.update_offset(storage.data.offset_of_last());
 
         assert_eq!(storage.data.offset_of_last(), 5);
-        assert_eq!(mock_records.write_count, 5);
+        assert_eq!(state.offset_of_last, 5);
+        assert!(eeprom.write_count >= 5);
+    }
+
+    #[test]
+    fn test_ring_storage_reopen_from_mock() {
+        let mut eeprom = MockHistoryStorage::new(8192);
+        let mut storage: RingStorage<0, 10, 60> = RingStorage::new(&mut eeprom).unwrap();
+        for i in 0..15 {
+            storage.add(&mut eeprom, i, 6000 + i as u32 * 60).unwrap();
+        }
+
+        // Only the last 10 records fit
+        let mut reopened: RingStorage<0, 10, 60> = RingStorage::new(&mut eeprom).unwrap();
+        assert_eq!(reopened.size(), 10);
+        assert_eq!(reopened.first_stored_timestamp(), 6000 + 5 * 60);
+        assert_eq!(reopened.last_stored_timestamp(), 6000 + 14 * 60);
+        for i in 5..15 {
+            let entry = reopened.find(&mut eeprom, 6000 + i as u32 * 60).unwrap();
+            assert_eq!(entry.map(|entry| entry.value), Some(i));
+        }
+        assert!(reopened.find(&mut eeprom, 6000).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_ring_storage_mock_error() {
+        let mut eeprom = MockHistoryStorage::new(8192).with_error(Error::Storage);
+        let result: Result<RingStorage<0, 10, 60>, Error> = RingStorage::new(&mut eeprom);
+        assert!(matches!(result, Err(Error::Storage)));
+
+        let mut storage: RingStorage<0, 10, 60> = RingStorage::new_empty();
+        assert!(matches!(
+            storage.add(&mut eeprom, 1, 6000),
+            Err(Error::Storage)
+        ));
     }
 
     #[test]
@@ -587,7 +660,7 @@ mod edge_case_tests {
 
     #[test]
     fn test_ring_storage_offset_calculation_edge() {
-        let mut storage: RingStorage<0, 1, 1> = RingStorage::new_empty();
+        let storage: RingStorage<0, 1, 1> = RingStorage::new_empty();
         let offset = storage.offset(0);
         assert!(offset > 0);
     }
@@ -662,7 +735,7 @@ mod property_tests {
 
     #[test]
     fn test_offset_calculation_is_monotonic_within_size() {
-        let mut storage: RingStorage<0, 100, 10> = RingStorage::new_empty();
+        let storage: RingStorage<0, 100, 10> = RingStorage::new_empty();
 
         let offset_0 = storage.offset(0);
         let offset_1 = storage.offset(1);
@@ -15,8 +15,8 @@ extern crate stm32l1xx_hal as hal;
 
 pub mod apps;
 pub mod calibration;
+pub mod config_text;
 pub mod gui;
-pub mod history_lib;
 pub mod measurement;
 pub mod ui;
 
@@ -38,12 +38,18 @@ pub mod hardware {
     pub use pins::*;
 }
 
+pub mod audit;
+pub mod events;
 pub mod history;
 pub mod mbus;
 pub mod modbus;
 pub mod modbus_handler;
 pub mod options;
 pub mod shell;
+pub mod totals;
+
+#[cfg(test)]
+pub(crate) mod test_storage;
 
 #[cfg(test)]
 mod history_lib_tests;
@@ -9,7 +9,10 @@ extern crate alloc;
 extern crate stm32l1xx_hal as hal;
 
 mod apps;
+mod audit;
 mod calibration;
+mod config_text;
+mod events;
 mod gui;
 mod hardware;
 mod history;
@@ -18,13 +21,16 @@ mod modbus;
 mod modbus_handler;
 mod options;
 mod shell;
+mod totals;
 mod ui;
 
 use apps::*;
+use audit::AuditLog;
 use core::fmt::Write;
 use defmt_rtt as _;
 use embedded_hal::digital::v2::OutputPin;
 use embedded_storage::ReadStorage;
+use events::{history_id, EventCode, EventLog};
 use gui::*;
 use hal::exti::ExtiExt;
 use hal::gpio::AltMode;
@@ -53,6 +59,7 @@ use time::{
     macros::{date, time},
     PrimitiveDateTime,
 };
+use totals::TotalsStore;
 use ui::*;
 
 impl CharacterDisplay for hardware::Lcd {
@@ -73,13 +80,155 @@ type BusType = spi::Spi<hal::stm32::SPI2, (SpiSck, SpiMiso, SpiMosi)>;
 type MyStorage = Storage<SharedBus<BusType>, MemoryEn, MemoryWp, MemoryHold>;
 type Tdc1000Dev = TDC1000<SharedBus<BusType>, Tdc1000Cs, Tdc1000Res, Tdc1000En>;
 type Tdc7200Dev = Tdc7200<SharedBus<BusType>, Tdc7200Cs>;
-type HourHistory = RingStorage<0, 2160, 3600>;
-type DayHistory = RingStorage<{ HourHistory::SIZE_ON_FLASH }, { 31 * 12 * 3 }, { 3600 * 24 }>;
+type HourHistory = RingStorage<0, 2160, 3600, f32, Hourly>;
+type DayHistory =
+    RingStorage<{ HourHistory::SIZE_ON_FLASH }, { 31 * 12 * 3 }, { 3600 * 24 }, f32, Daily>;
 type MonthHistory = RingStorage<
     { HourHistory::SIZE_ON_FLASH + DayHistory::SIZE_ON_FLASH },
     { 10 * 12 },
     { 3600 * 24 * 31 },
+    f32,
+    Monthly,
 >;
+/// Load profile: up to 40 days of 15-minute records, interval and depth from `Options`
+type ProfileHistory = RingStorage<
+    { HourHistory::SIZE_ON_FLASH + DayHistory::SIZE_ON_FLASH + MonthHistory::SIZE_ON_FLASH },
+    { 96 * 40 },
+    { 60 * 15 },
+    f32,
+    Interval,
+>;
+/// Min/max instantaneous flow per hour, same periods as `HourHistory`
+type HourStatsHistory = RingStorage<
+    {
+        HourHistory::SIZE_ON_FLASH
+            + DayHistory::SIZE_ON_FLASH
+            + MonthHistory::SIZE_ON_FLASH
+            + ProfileHistory::SIZE_ON_FLASH
+    },
+    2160,
+    3600,
+    FlowStats,
+    Hourly,
+>;
+/// Min/max instantaneous flow per day, same periods as `DayHistory`
+type DayStatsHistory = RingStorage<
+    {
+        HourHistory::SIZE_ON_FLASH
+            + DayHistory::SIZE_ON_FLASH
+            + MonthHistory::SIZE_ON_FLASH
+            + ProfileHistory::SIZE_ON_FLASH
+            + HourStatsHistory::SIZE_ON_FLASH
+    },
+    { 31 * 12 * 3 },
+    { 3600 * 24 },
+    FlowStats,
+    Daily,
+>;
+/// Event/alarm log, 256 records after the history rings
+type Events = EventLog<
+    {
+        HourHistory::SIZE_ON_FLASH
+            + DayHistory::SIZE_ON_FLASH
+            + MonthHistory::SIZE_ON_FLASH
+            + ProfileHistory::SIZE_ON_FLASH
+            + HourStatsHistory::SIZE_ON_FLASH
+            + DayStatsHistory::SIZE_ON_FLASH
+    },
+    256,
+>;
+/// Metrological audit trail, 512 changes after the event log; never wraps
+type Audit = AuditLog<
+    {
+        HourHistory::SIZE_ON_FLASH
+            + DayHistory::SIZE_ON_FLASH
+            + MonthHistory::SIZE_ON_FLASH
+            + ProfileHistory::SIZE_ON_FLASH
+            + HourStatsHistory::SIZE_ON_FLASH
+            + DayStatsHistory::SIZE_ON_FLASH
+            + Events::SIZE_ON_FLASH
+    },
+    512,
+>;
+/// Totalizer checkpoints, 16 page-sized slots after the audit trail
+type Totalizer = TotalsStore<
+    {
+        HourHistory::SIZE_ON_FLASH
+            + DayHistory::SIZE_ON_FLASH
+            + MonthHistory::SIZE_ON_FLASH
+            + ProfileHistory::SIZE_ON_FLASH
+            + HourStatsHistory::SIZE_ON_FLASH
+            + DayStatsHistory::SIZE_ON_FLASH
+            + Events::SIZE_ON_FLASH
+            + Audit::SIZE_ON_FLASH
+    },
+    16,
+>;
+/// IWDGRSTF in RCC_CSR, shifted down by 24 with the other reset flags
+const RESET_FLAG_IWDG: u32 = 1 << 5;
+
+/// Silence (ms) that ends an RTU frame or drops a partial ASCII one
+fn rx_timeout(framing: modbus::Framing) -> u64 {
+    match framing {
+        modbus::Framing::Rtu => modbus::Receiver::RTU_SILENCE_MS,
+        modbus::Framing::Ascii => modbus::ascii::CHAR_TIMEOUT_MS,
+    }
+}
+
+/// Write a shell reply, prefixed with `Error: ` on failure, and the prompt
+fn shell_reply<W: embedded_hal::serial::Write<u8>>(serial: &mut W, reply: Result<&str, &str>) {
+    let (prefix, text, end): (&[u8], &str, &[u8]) = match reply {
+        Ok(text) => (b"", text, b""),
+        Err(msg) => (b"Error: ", msg, b"\r\n"),
+    };
+    for byte in prefix.iter().chain(text.as_bytes()).chain(end) {
+        nb::block!(serial.write(*byte)).ok();
+    }
+    nb::block!(serial.write(b'>')).ok();
+    nb::block!(serial.write(b' ')).ok();
+    nb::block!(serial.flush()).ok();
+}
+
+/// Audit and save options changed from the shell or a Modbus command.
+/// `apply` changes a copy of the options; they are replaced only once the
+/// change is recorded.
+fn save_options(
+    options: &mut Options,
+    storage: &mut MyStorage,
+    audit: &mut Audit,
+    time: u32,
+    source: audit::Source,
+    apply: impl FnOnce(&mut Options) -> Result<(), &'static str>,
+) -> Result<(), &'static str> {
+    let mut updated = *options;
+    apply(&mut updated)?;
+    audit
+        .record(storage, time, source, options, &updated)
+        .map_err(|e| match e {
+            history::Error::Full => "audit trail full",
+            _ => "audit write",
+        })?;
+    *options = updated;
+    options.save(storage).map_err(|_| "options save")?;
+    Ok(())
+}
+
+/// Collect measurements for `job`; `calibration_done` saves the result
+fn start_calibration(
+    app: &mut App,
+    job: calibration::Job,
+    source: audit::Source,
+) -> Result<(), &'static str> {
+    if app.calibrating.is_some() {
+        return Err("calibration in progress");
+    }
+    app.calibrating = Some(Calibrating {
+        job,
+        source,
+        sampler: calibration::Sampler::default(),
+    });
+    Ok(())
+}
 #[global_allocator]
 static ALLOCATOR: emballoc::Allocator<4096> = emballoc::Allocator::new();
 
@@ -98,14 +247,21 @@ mod app {
         hour_history: HourHistory,
         day_history: DayHistory,
         month_history: MonthHistory,
+        profile_history: ProfileHistory,
+        hour_stats_history: HourStatsHistory,
+        day_stats_history: DayStatsHistory,
+        events: Events,
+        audit: Audit,
+        totalizer: Totalizer,
         storage: MyStorage,
         app: App,
         ui: MenuController,
         modbus_handler: modbus_handler::ModbusHandler,
         serial: hal::serial::Serial<hal::stm32::USART1>,
-        modbus_rx_buf: heapless::Vec<u8, 256>,
+        /// Modbus requests and shell lines received on USART1, framed by
+        /// `Options::modbus_mode`
+        serial_rx: modbus::Receiver,
         modbus_last_rx: u64,
-        shell_line_buf: heapless::Vec<u8, 80>,
         options: Options,
         tdc1000: Tdc1000Dev,
         tdc7200: Tdc7200Dev,
@@ -139,6 +295,10 @@ mod app {
                 .set_bit()
         });
 
+        // Reset cause, read before the flags are cleared
+        let reset_flags = p.RCC.csr.read().bits() >> 24;
+        p.RCC.csr.modify(|_, w| w.rmvf().set_bit());
+
         let mut rcc = p.RCC.freeze(Config::pll(
             hal::rcc::PLLSource::HSE(24.mhz()),
             hal::rcc::PLLMul::Mul4,
@@ -236,15 +396,51 @@ mod app {
 
         let mut storage = microchip_eeprom_25lcxx::Storage::new(eeprom25x);
 
-        let mut opt = Options::load(&mut storage).unwrap_or_else(|_e| {
-            defmt::error!("Options load failed");
-            Options::default()
+        let events = Events::new(&mut storage).unwrap_or_else(|_e| {
+            defmt::error!("Event log init failed");
+            Events::new_empty()
+        });
+        let audit = Audit::new(&mut storage).unwrap_or_else(|_e| {
+            defmt::error!("Audit trail init failed, metrology writes locked");
+            Audit::new_full()
         });
-        let reg = [
-            0x31_u8, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x30, 0, 0, 0, 0, 0, 0,
-        ];
+        let totalizer = Totalizer::new(&mut storage).unwrap_or_else(|_e| {
+            defmt::error!("Totalizer init failed");
+            Totalizer::new_empty()
+        });
+        let mut app = App::default();
+        match totalizer.restore(&mut storage) {
+            Ok(Some(totals)) => app.restore_totals(&totals),
+            Ok(None) => {}
+            Err(_e) => defmt::error!("Totalizer restore failed"),
+        }
+        log_event::spawn(EventCode::PowerUp, reset_flags).ok();
+        if reset_flags & RESET_FLAG_IWDG != 0 {
+            log_event::spawn(EventCode::WatchdogReset, reset_flags).ok();
+        }
 
-        opt.set_tdc7200_regs(u128::from_le_bytes(reg));
+        let opt = match Options::load_page(&mut storage) {
+            Ok((mut opt, loaded)) => {
+                if loaded.repair {
+                    // The save goes over the unusable page
+                    let bad = loaded.page.other();
+                    defmt::warn!("Options page {} unusable, repairing", bad);
+                    log_event::spawn(EventCode::OptionsPageCrc, bad as u32).ok();
+                    app.alarms.insert(MeterStatus::OPTIONS_FALLBACK);
+                    if opt.save(&mut storage).is_err() {
+                        defmt::error!("Options repair failed");
+                    }
+                }
+                opt
+            }
+            Err(_e) => {
+                // Not saved: the pages stay as found for inspection
+                defmt::error!("Options load failed, using factory defaults");
+                log_event::spawn(EventCode::OptionsLoadFailed, 0).ok();
+                app.alarms.insert(MeterStatus::OPTIONS_FALLBACK);
+                Options::factory(0)
+            }
+        };
         defmt::info!("opt: {:x}", opt.into_bytes());
 
         let mut tdc1000 = TDC1000::new(bus.acquire(), tdc1000_cs, tdc1000_res, tdc1000_en);
@@ -283,18 +479,38 @@ mod app {
 
         let mut asd = HourHistory::new(&mut storage).unwrap_or_else(|_e| {
             defmt::error!("HourHistory init failed");
+            log_event::spawn(EventCode::HistoryInit, history_id::HOUR).ok();
             // Return default empty history — will start fresh
-            HourHistory {
-                data: ServiceData::default(),
-            }
+            HourHistory::new_empty()
         });
         defmt::info!(
-            "read data.size: {:?} {:?} {:?}",
+            "read data.size: {:?} {:?} {:?} writes: {:?}",
             asd.data.size(),
             asd.first_stored_timestamp(),
-            asd.last_stored_timestamp()
+            asd.last_stored_timestamp(),
+            asd.write_count()
         );
 
+        let mut profile_history = ProfileHistory::new(&mut storage)
+            .unwrap_or_else(|_e| {
+                defmt::error!("ProfileHistory init");
+                log_event::spawn(EventCode::HistoryInit, history_id::PROFILE).ok();
+                ProfileHistory::new_empty()
+            })
+            .with_depth(opt.load_profile_depth());
+        // Options may have changed the interval since the ring was written
+        match profile_history.set_bucketing(&mut storage, opt.load_profile_interval()) {
+            Ok(true) => {
+                defmt::warn!("Load profile interval changed, profile cleared");
+                log_event::spawn(EventCode::HistoryCleared, history_id::PROFILE).ok();
+            }
+            Ok(false) => {}
+            Err(_e) => {
+                defmt::error!("Failed to clear the load profile");
+                log_event::spawn(EventCode::HistoryWrite, history_id::PROFILE).ok();
+            }
+        }
+
         rs_power_en.set_low().ok();
 
         let mut serial = p
@@ -360,30 +576,39 @@ mod app {
                 lcd,
                 hour_history: HourHistory::new(&mut storage).unwrap_or_else(|_e| {
                     defmt::error!("HourHistory init");
-                    HourHistory {
-                        data: ServiceData::default(),
-                    }
+                    HourHistory::new_empty()
                 }),
                 day_history: DayHistory::new(&mut storage).unwrap_or_else(|_e| {
                     defmt::error!("DayHistory init");
-                    DayHistory {
-                        data: ServiceData::default(),
-                    }
+                    log_event::spawn(EventCode::HistoryInit, history_id::DAY).ok();
+                    DayHistory::new_empty()
                 }),
                 month_history: MonthHistory::new(&mut storage).unwrap_or_else(|_e| {
                     defmt::error!("MonthHistory init");
-                    MonthHistory {
-                        data: ServiceData::default(),
-                    }
+                    log_event::spawn(EventCode::HistoryInit, history_id::MONTH).ok();
+                    MonthHistory::new_empty()
+                }),
+                profile_history,
+                hour_stats_history: HourStatsHistory::new(&mut storage).unwrap_or_else(|_e| {
+                    defmt::error!("HourStatsHistory init");
+                    log_event::spawn(EventCode::HistoryInit, history_id::HOUR_STATS).ok();
+                    HourStatsHistory::new_empty()
                 }),
+                day_stats_history: DayStatsHistory::new(&mut storage).unwrap_or_else(|_e| {
+                    defmt::error!("DayStatsHistory init");
+                    log_event::spawn(EventCode::HistoryInit, history_id::DAY_STATS).ok();
+                    DayStatsHistory::new_empty()
+                }),
+                events,
+                audit,
+                totalizer,
                 storage,
-                app: App::default(),
+                app,
                 ui: MenuController::new(),
                 modbus_handler: modbus_handler::ModbusHandler::new(1), // Slave address 1
                 serial,
-                modbus_rx_buf: heapless::Vec::new(),
+                serial_rx: modbus::Receiver::new(modbus::Framing::from_mode(opt.modbus_mode())),
                 modbus_last_rx: 0,
-                shell_line_buf: heapless::Vec::new(),
                 options: opt,
                 tdc1000,
                 tdc7200,
@@ -496,7 +721,7 @@ mod app {
         }
     }
 
-    #[task(capacity = 8, priority = 1, shared = [power, lcd, rtc, app, tdc1000, hour_history, day_history, month_history, storage])]
+    #[task(capacity = 8, priority = 1, shared = [power, lcd, rtc, app, tdc1000, tdc7200, hour_history, day_history, month_history, profile_history, hour_stats_history, day_stats_history, events, totalizer, storage, options])]
     fn app_request(ctx: app_request::Context, req: AppRequest) {
         let app_request::SharedResources {
             power,
@@ -504,10 +729,17 @@ mod app {
             mut rtc,
             mut app,
             mut tdc1000,
+            mut tdc7200,
             hour_history,
             day_history,
             month_history,
+            mut profile_history,
+            mut hour_stats_history,
+            mut day_stats_history,
+            events,
+            totalizer,
             mut storage,
+            mut options,
         } = ctx.shared;
         match req {
             AppRequest::Process => {
@@ -515,67 +747,200 @@ mod app {
                 let datetime = rtc.lock(|rtc| rtc.get_datetime());
 
                 // Trigger real measurement via TDC1000
+                let channel = app.lock(|app| app.channel);
                 let flow = tdc1000.lock(|tdc| {
-                    // Set channel 1 (downstream) and start measurement
+                    // Select the channel and start measurement; tdc7200_result
+                    // takes the result for `app.channel`
                     // TDC1000 sends ultrasonic pulses on selected channel
-                    if let Err(_e) = tdc.set_channel(false) {
+                    if let Err(_e) = tdc.set_channel(channel == 1) {
                         defmt::error!("TDC1000 set_channel failed");
+                        log_event::spawn(EventCode::TdcBus, 1000).ok();
                     }
                     // Clear any previous error flags
                     let _ = tdc.clear_error_flags();
-                    // TODO: Read TDC7200 measurement results when TDC7200 ISR is connected
-                    // TDC7200 INT pin will signal completion — for now return 0.0
-                    // until TDC7200 driver is integrated into RTIC
+                    if tdc7200.lock(|tdc| tdc.start_measurement()).is_err() {
+                        defmt::error!("TDC7200 start failed");
+                        log_event::spawn(EventCode::TdcBus, 7200).ok();
+                    }
+                    // TDC7200 INT pin will signal completion and tdc7200_result
+                    // reads it — for now return 0.0 until the flow is calculated
                     0.0f32
                 });
 
-                let (hour_flow, day_flow, month_flow) = app.lock(|app| {
-                    app.flow = flow;
-                    defmt::info!("flow: {}", app.flow);
-                    app.hour_flow += app.flow;
-                    app.day_flow += app.flow;
-                    app.month_flow += app.flow;
-                    (app.hour_flow, app.day_flow, app.month_flow)
-                });
+                let now = datetime.as_utc().unix_timestamp() as u32;
+                let (hour_flow, day_flow, month_flow, profile_flow, hour_stats, day_stats) = app
+                    .lock(|app| {
+                        app.flow = flow;
+                        defmt::info!("flow: {}", app.flow);
+                        app.accumulate();
+                        app.hour_stats.record(app.flow, now);
+                        app.day_stats.record(app.flow, now);
+                        (
+                            app.hour_flow,
+                            app.day_flow,
+                            app.month_flow,
+                            app.profile_flow,
+                            app.hour_stats,
+                            app.day_stats,
+                        )
+                    });
+                // An accumulator was reset: checkpoint before the old total comes back
+                let mut period_closed = false;
                 if datetime.time().second() < 5 {
                     let timestamp = datetime.as_utc().unix_timestamp();
+                    let interval =
+                        profile_history.lock(|profile_history| profile_history.bucketing());
+                    // First minute of a load-profile interval
+                    if timestamp as u32 - interval.start(timestamp as u32) < 60 {
+                        if let Err(e) =
+                            (&mut profile_history, &mut storage).lock(|profile_history, storage| {
+                                profile_history.add(storage, profile_flow, timestamp as u32)
+                            })
+                        {
+                            match e {
+                                // Period already stored: carry the flow over
+                                history::Error::ClockBehind => {
+                                    defmt::warn!("Profile flow carried over, clock behind history")
+                                }
+                                _ => {
+                                    defmt::error!("Failed to log profile flow:");
+                                    log_event::spawn(EventCode::HistoryWrite, history_id::PROFILE)
+                                        .ok();
+                                }
+                            }
+                        } else {
+                            defmt::info!("Profile flow logged: {} at {}", profile_flow, timestamp);
+                            // Reset profile accumulator after successful save
+                            app.lock(|app| app.profile_flow = 0.0);
+                            period_closed = true;
+                        }
+                    }
                     if datetime.time().minute() == 0 {
-                        if let Err(_e) =
+                        if let Err(e) =
                             (hour_history, &mut storage).lock(|hour_history, storage| {
-                                hour_history.add(storage, hour_flow as i32, timestamp as u32)
+                                hour_history.add(storage, hour_flow, timestamp as u32)
                             })
                         {
-                            defmt::error!("Failed to log hour flow:");
+                            match e {
+                                // Period already stored: carry the flow over
+                                history::Error::ClockBehind => {
+                                    defmt::warn!("Hour flow carried over, clock behind history")
+                                }
+                                _ => {
+                                    defmt::error!("Failed to log hour flow:");
+                                    log_event::spawn(EventCode::HistoryWrite, history_id::HOUR)
+                                        .ok();
+                                }
+                            }
                         } else {
                             defmt::info!("Hour flow logged: {} at {}", hour_flow, timestamp);
                             // Reset hour accumulator after successful save
                             app.lock(|app| app.hour_flow = 0.0);
+                            period_closed = true;
+                        }
+                        if let Err(e) = (&mut hour_stats_history, &mut storage).lock(
+                            |hour_stats_history, storage| {
+                                hour_stats_history.add(storage, hour_stats, timestamp as u32)
+                            },
+                        ) {
+                            match e {
+                                // Period already stored: keep tracking the extremes
+                                history::Error::ClockBehind => {
+                                    defmt::warn!("Hour stats carried over, clock behind history")
+                                }
+                                _ => {
+                                    defmt::error!("Failed to log hour stats:");
+                                    log_event::spawn(
+                                        EventCode::HistoryWrite,
+                                        history_id::HOUR_STATS,
+                                    )
+                                    .ok();
+                                }
+                            }
+                        } else {
+                            defmt::info!(
+                                "Hour stats logged: {} .. {} at {}",
+                                hour_stats.min,
+                                hour_stats.max,
+                                timestamp
+                            );
+                            app.lock(|app| app.hour_stats = FlowStats::EMPTY);
                         }
 
                         if datetime.time().hour() == 0 {
-                            if let Err(_e) =
+                            if let Err(e) =
                                 (day_history, &mut storage).lock(|day_history, storage| {
-                                    day_history.add(storage, day_flow as i32, timestamp as u32)
+                                    day_history.add(storage, day_flow, timestamp as u32)
                                 })
                             {
-                                defmt::error!("Failed to log day flow:");
+                                match e {
+                                    // Period already stored: carry the flow over
+                                    history::Error::ClockBehind => {
+                                        defmt::warn!("Day flow carried over, clock behind history")
+                                    }
+                                    _ => {
+                                        defmt::error!("Failed to log day flow:");
+                                        log_event::spawn(EventCode::HistoryWrite, history_id::DAY)
+                                            .ok();
+                                    }
+                                }
                             } else {
                                 defmt::info!("Day flow logged: {} at {}", day_flow, timestamp);
                                 // Reset day accumulator after successful save
                                 app.lock(|app| app.day_flow = 0.0);
+                                period_closed = true;
+                            }
+                            if let Err(e) = (&mut day_stats_history, &mut storage).lock(
+                                |day_stats_history, storage| {
+                                    day_stats_history.add(storage, day_stats, timestamp as u32)
+                                },
+                            ) {
+                                match e {
+                                    // Period already stored: keep tracking the extremes
+                                    history::Error::ClockBehind => {
+                                        defmt::warn!("Day stats carried over, clock behind history")
+                                    }
+                                    _ => {
+                                        defmt::error!("Failed to log day stats:");
+                                        log_event::spawn(
+                                            EventCode::HistoryWrite,
+                                            history_id::DAY_STATS,
+                                        )
+                                        .ok();
+                                    }
+                                }
+                            } else {
+                                defmt::info!(
+                                    "Day stats logged: {} .. {} at {}",
+                                    day_stats.min,
+                                    day_stats.max,
+                                    timestamp
+                                );
+                                app.lock(|app| app.day_stats = FlowStats::EMPTY);
                             }
 
                             if datetime.date().day() == 1 {
-                                if let Err(_e) =
+                                if let Err(e) =
                                     (month_history, &mut storage).lock(|month_history, storage| {
-                                        month_history.add(
-                                            storage,
-                                            month_flow as i32,
-                                            timestamp as u32,
-                                        )
+                                        month_history.add(storage, month_flow, timestamp as u32)
                                     })
                                 {
-                                    defmt::error!("Failed to log month flow:");
+                                    match e {
+                                        // Period already stored: carry the flow over
+                                        history::Error::ClockBehind => {
+                                            defmt::warn!(
+                                                "Month flow carried over, clock behind history"
+                                            )
+                                        }
+                                        _ => {
+                                            defmt::error!("Failed to log month flow:");
+                                            log_event::spawn(
+                                                EventCode::HistoryWrite,
+                                                history_id::MONTH,
+                                            )
+                                            .ok();
+                                        }
+                                    }
                                 } else {
                                     defmt::info!(
                                         "Month flow logged: {} at {}",
@@ -584,11 +949,21 @@ mod app {
                                     );
                                     // Reset month accumulator after successful save
                                     app.lock(|app| app.month_flow = 0.0);
+                                    period_closed = true;
                                 }
                             }
                         }
                     }
                 }
+
+                let totals = app.lock(|app| app.totals());
+                (totalizer, &mut storage).lock(|totalizer, storage| {
+                    if (period_closed || totalizer.due(now))
+                        && totalizer.checkpoint(storage, now, &totals).is_err()
+                    {
+                        defmt::error!("Totals checkpoint failed");
+                    }
+                });
                 app_request::spawn_after(25_u64.millis(), AppRequest::DeepSleep).ok();
             }
             AppRequest::LcdLed(on) => {
@@ -596,7 +971,59 @@ mod app {
                 lcd.lock(|lcd| lcd.led(on));
             }
             AppRequest::SetDateTime(dt) => {
-                rtc.lock(|rtc| rtc.set_datetime(&dt).ok());
+                defmt::info!("SetDateTime");
+                let old = rtc.lock(|rtc| rtc.get_datetime());
+                if rtc.lock(|rtc| rtc.set_datetime(&dt)).is_err() {
+                    defmt::error!("RTC set datetime failed");
+                    return;
+                }
+                log_event::spawn(
+                    EventCode::ClockChanged,
+                    old.as_utc().unix_timestamp() as u32,
+                )
+                .ok();
+                // Let the rings keep their records across the clock change
+                let timestamp = dt.as_utc().unix_timestamp() as u32;
+                (
+                    hour_history,
+                    day_history,
+                    month_history,
+                    profile_history,
+                    hour_stats_history,
+                    day_stats_history,
+                    storage,
+                )
+                    .lock(
+                        |hour_history,
+                         day_history,
+                         month_history,
+                         profile_history,
+                         hour_stats_history,
+                         day_stats_history,
+                         storage| {
+                            if hour_history.clock_changed(storage, timestamp).is_err() {
+                                defmt::error!("HourHistory clock change");
+                            }
+                            if day_history.clock_changed(storage, timestamp).is_err() {
+                                defmt::error!("DayHistory clock change");
+                            }
+                            if month_history.clock_changed(storage, timestamp).is_err() {
+                                defmt::error!("MonthHistory clock change");
+                            }
+                            if profile_history.clock_changed(storage, timestamp).is_err() {
+                                defmt::error!("ProfileHistory clock change");
+                            }
+                            if hour_stats_history
+                                .clock_changed(storage, timestamp)
+                                .is_err()
+                            {
+                                defmt::error!("HourStatsHistory clock change");
+                            }
+                            if day_stats_history.clock_changed(storage, timestamp).is_err() {
+                                defmt::error!("DayStatsHistory clock change");
+                            }
+                        },
+                    );
             }
             AppRequest::DeepSleep => {
                 defmt::debug!("DeepSleep");
@@ -612,27 +1039,60 @@ mod app {
                 defmt::info!("SetHistory");
                 match history_type {
                     HistoryType::Hour => {
-                        (app, hour_history, storage).lock(|app, hour_history, storage| {
-                            if let Ok(Some(flow)) = hour_history.find(storage, timestamp) {
-                                app.history_state.flow = Some(flow as f32);
-                            } else {
-                                app.history_state.flow = None;
-                            }
-                        });
+                        (app, hour_history, hour_stats_history, storage).lock(
+                            |app, hour_history, hour_stats_history, storage| {
+                                if let Ok(Some(entry)) = hour_history.find(storage, timestamp) {
+                                    app.history_state.flow = Some(entry.value);
+                                    app.history_state.status = entry.status;
+                                } else {
+                                    app.history_state.flow = None;
+                                }
+                                app.history_state.stats =
+                                    match hour_stats_history.find(storage, timestamp) {
+                                        Ok(Some(entry)) if entry.status != Status::GapFilled => {
+                                            Some(entry.value).filter(|stats| !stats.is_empty())
+                                        }
+                                        _ => None,
+                                    };
+                            },
+                        );
                     }
                     HistoryType::Day => {
-                        (app, day_history, storage).lock(|app, day_history, storage| {
-                            if let Ok(Some(flow)) = day_history.find(storage, timestamp) {
-                                app.history_state.flow = Some(flow as f32);
+                        (app, day_history, day_stats_history, storage).lock(
+                            |app, day_history, day_stats_history, storage| {
+                                if let Ok(Some(entry)) = day_history.find(storage, timestamp) {
+                                    app.history_state.flow = Some(entry.value);
+                                    app.history_state.status = entry.status;
+                                } else {
+                                    app.history_state.flow = None;
+                                }
+                                app.history_state.stats =
+                                    match day_stats_history.find(storage, timestamp) {
+                                        Ok(Some(entry)) if entry.status != Status::GapFilled => {
+                                            Some(entry.value).filter(|stats| !stats.is_empty())
+                                        }
+                                        _ => None,
+                                    };
+                            },
+                        );
+                    }
+                    HistoryType::Month => {
+                        (app, month_history, storage).lock(|app, month_history, storage| {
+                            app.history_state.stats = None;
+                            if let Ok(Some(entry)) = month_history.find(storage, timestamp) {
+                                app.history_state.flow = Some(entry.value);
+                                app.history_state.status = entry.status;
                             } else {
                                 app.history_state.flow = None;
                             }
                         });
                     }
-                    HistoryType::Month => {
-                        (app, month_history, storage).lock(|app, month_history, storage| {
-                            if let Ok(Some(flow)) = month_history.find(storage, timestamp) {
-                                app.history_state.flow = Some(flow as f32);
+                    HistoryType::Profile => {
+                        (app, profile_history, storage).lock(|app, profile_history, storage| {
+                            app.history_state.stats = None;
+                            if let Ok(Some(entry)) = profile_history.find(storage, timestamp) {
+                                app.history_state.flow = Some(entry.value);
+                                app.history_state.status = entry.status;
                             } else {
                                 app.history_state.flow = None;
                             }
@@ -662,92 +1122,217 @@ mod app {
             }
             AppRequest::SystemReset => {
                 defmt::info!("SystemReset");
-                // TODO: NVIC_SystemReset()
+                cortex_m::peripheral::SCB::sys_reset();
             }
             AppRequest::EnterCalibration => {
                 defmt::info!("EnterCalibration");
                 // TODO: switch to calibration menu + shell
             }
+            // Only the Modbus coils send these
+            AppRequest::ResetTotals => {
+                let now = rtc.lock(|rtc| rtc.get_datetime()).as_utc().unix_timestamp() as u32;
+                let totals = app.lock(|app| {
+                    app.reset_totals();
+                    app.totals()
+                });
+                // Checkpoint at once so a reset does not bring the old rest back
+                if (totalizer, &mut storage)
+                    .lock(|totalizer, storage| totalizer.checkpoint(storage, now, &totals))
+                    .is_err()
+                {
+                    defmt::error!("Totals checkpoint failed");
+                }
+            }
+            AppRequest::AutoZero => {
+                // calibration_done saves the offsets once the measurements are in
+                let started = app.lock(|app| {
+                    start_calibration(app, calibration::Job::Zero, audit::Source::Modbus)
+                });
+                if let Err(msg) = started {
+                    defmt::warn!("Modbus command failed: {}", msg);
+                }
+            }
+            AppRequest::SaveOptions => {
+                if (&mut options, &mut storage)
+                    .lock(|options, storage| options.save(storage))
+                    .is_err()
+                {
+                    defmt::error!("Modbus command failed: options save");
+                }
+            }
+            AppRequest::ProfileOptions => {
+                // Records of another interval would be misplaced, so they go
+                let cleared = (&mut profile_history, &mut options, &mut storage).lock(
+                    |profile_history, options, storage| {
+                        profile_history.set_depth(options.load_profile_depth());
+                        profile_history.set_bucketing(storage, options.load_profile_interval())
+                    },
+                );
+                match cleared {
+                    Ok(true) => {
+                        defmt::warn!("Load profile interval changed, profile cleared");
+                        log_event::spawn(EventCode::HistoryCleared, history_id::PROFILE).ok();
+                    }
+                    Ok(false) => {}
+                    Err(_e) => {
+                        defmt::error!("Failed to clear the load profile");
+                        log_event::spawn(EventCode::HistoryWrite, history_id::PROFILE).ok();
+                    }
+                }
+            }
+            AppRequest::SetEvent(index) => {
+                defmt::info!("SetEvent {}", index);
+                (app, events, storage).lock(|app, events, storage| {
+                    app.event_state.index = index;
+                    app.event_state.count = events.len();
+                    app.event_state.event = events.get(storage, index).unwrap_or(None);
+                });
+            }
         }
     }
 
-    /// USART1 RX interrupt — receives bytes for Modbus RTU or Shell
-    #[task(binds = USART1, priority = 3, shared = [serial, modbus_rx_buf, modbus_last_rx, shell_line_buf])]
+    /// Append an event to the EEPROM event log, stamped with the RTC time
+    #[task(capacity = 16, priority = 1, shared = [rtc, app, events, storage])]
+    fn log_event(ctx: log_event::Context, code: EventCode, param: u32) {
+        let log_event::SharedResources {
+            mut rtc,
+            mut app,
+            events,
+            storage,
+        } = ctx.shared;
+        if matches!(
+            code,
+            EventCode::TdcTimeout | EventCode::TdcOverflow | EventCode::TdcBus
+        ) {
+            // Cleared by the next good TDC7200 result
+            app.lock(|app| app.alarms.insert(MeterStatus::TDC_ERROR));
+        }
+        let time = rtc.lock(|rtc| rtc.get_datetime()).as_utc().unix_timestamp() as u32;
+        defmt::info!("Event {} {}", code.to_u16(), param);
+        let count = (events, storage).lock(|events, storage| {
+            let event = events::Event { time, code, param };
+            if events.append(storage, event).is_err() {
+                defmt::error!("Event log write failed");
+            }
+            events.len()
+        });
+        app.lock(|app| {
+            let state = &mut app.event_state;
+            // The shown event is now one place further back from the newest
+            if state.event.is_some() && state.count > 0 {
+                state.index = (state.index + 1).min(count.saturating_sub(1));
+            }
+            state.count = count;
+        });
+    }
+
+    /// USART1 RX interrupt — receives bytes for Modbus RTU/ASCII or Shell
+    #[task(binds = USART1, priority = 3, shared = [serial, serial_rx, modbus_last_rx])]
     fn usart1_irq(ctx: usart1_irq::Context) {
-        let (mut serial, mut modbus_rx_buf, mut modbus_last_rx, mut shell_line_buf) = (
+        let (mut serial, mut serial_rx, mut modbus_last_rx) = (
             ctx.shared.serial,
-            ctx.shared.modbus_rx_buf,
+            ctx.shared.serial_rx,
             ctx.shared.modbus_last_rx,
-            ctx.shared.shell_line_buf,
         );
-        serial.lock(|serial| {
-            while let Ok(byte) = serial.read() {
-                // If byte is printable ASCII or newline, try shell line buffer
-                if byte == b'\n' || byte == b'\r' {
-                    // End of line — try shell command
-                    shell_line_buf.lock(|buf| {
-                        if !buf.is_empty() {
-                            // Check if this looks like a shell command
-                            let is_shell = buf.iter().all(|&b| b.is_ascii());
-                            if is_shell {
-                                shell_cmd::spawn().ok();
-                            } else {
-                                // Not a shell command — move to Modbus buffer
-                                modbus_rx_buf.lock(|mbuf| {
-                                    for &b in buf.iter() {
-                                        let _ = mbuf.push(b);
-                                    }
-                                });
-                                buf.clear();
-                            }
-                        }
-                    });
-                } else if byte.is_ascii() && byte >= b' ' {
-                    // Printable ASCII — accumulate in shell line buffer
-                    shell_line_buf.lock(|buf| {
-                        if buf.push(byte).is_err() {
-                            buf.clear(); // overflow, reset
-                        }
-                    });
-                } else {
-                    // Binary byte — Modbus mode, clear shell buffer if any
-                    shell_line_buf.lock(|buf| buf.clear());
-                    modbus_rx_buf.lock(|mbuf| {
-                        if mbuf.len() >= 255 {
-                            mbuf.clear();
+        let now = monotonics::now().ticks();
+        let silent = now - modbus_last_rx.lock(|last| *last);
+        let receiving = serial_rx.lock(|rx| {
+            // A pause since the last byte may end an RTU frame
+            if rx.silence(silent) == modbus::Received::Frame {
+                modbus_poll::spawn().ok();
+            }
+            serial.lock(|serial| {
+                while let Ok(byte) = serial.read() {
+                    match rx.push(byte) {
+                        modbus::Received::Frame => {
+                            modbus_poll::spawn().ok();
                         }
-                        if mbuf.push(byte).is_err() {
-                            mbuf.clear();
+                        modbus::Received::Line => {
+                            shell_cmd::spawn().ok();
                         }
-                    });
+                        modbus::Received::Nothing => {}
+                    }
                 }
-            }
+            });
+            rx.receiving().then(|| rx_timeout(rx.framing()))
         });
-        modbus_last_rx.lock(|last| *last = monotonics::now().ticks());
+        modbus_last_rx.lock(|last| *last = now);
 
-        // Check if we have enough for a Modbus frame
-        let len = modbus_rx_buf.lock(|buf| buf.len());
-        if len >= 8 {
-            modbus_poll::spawn().ok();
+        // A frame without a length to wait for ends at silence
+        if let Some(timeout) = receiving {
+            modbus_poll::spawn_after(timeout.millis()).ok();
         }
     }
 
     /// Process shell command from USART1 line buffer
-    #[task(priority = 1, shared = [serial, shell_line_buf])]
+    #[task(priority = 1, shared = [serial, serial_rx, events, audit, storage, app, options, rtc, modbus_handler], local = [config_load: Option<config_text::ConfigLoad> = None])]
     fn shell_cmd(ctx: shell_cmd::Context) {
-        let (mut serial, mut shell_line_buf) = (ctx.shared.serial, ctx.shared.shell_line_buf);
+        let (
+            mut serial,
+            mut serial_rx,
+            mut events,
+            mut audit,
+            mut storage,
+            mut app,
+            mut options,
+            mut rtc,
+            mut modbus_handler,
+        ) = (
+            ctx.shared.serial,
+            ctx.shared.serial_rx,
+            ctx.shared.events,
+            ctx.shared.audit,
+            ctx.shared.storage,
+            ctx.shared.app,
+            ctx.shared.options,
+            ctx.shared.rtc,
+            ctx.shared.modbus_handler,
+        );
 
-        // Take the line buffer contents
-        let line = shell_line_buf.lock(|buf| {
-            let l = buf.clone();
-            buf.clear();
-            l
-        });
+        // Take the oldest line; lines pasted at once wait for the next run
+        let Some(line) = serial_rx.lock(|rx| rx.take_line()) else {
+            return;
+        };
+        if serial_rx.lock(|rx| rx.has_line()) {
+            shell_cmd::spawn().ok();
+        }
 
         if line.is_empty() {
             return;
         }
 
+        // Lines of a `config load` go to the loader up to its crc= line
+        if let Some(load) = ctx.local.config_load.as_mut() {
+            let saved = match load.feed(&line) {
+                Ok(None) => return,
+                Ok(Some(loaded)) => {
+                    let time = rtc.lock(|rtc| rtc.get_datetime()).as_utc().unix_timestamp() as u32;
+                    (&mut options, &mut storage, &mut audit).lock(|options, storage, audit| {
+                        save_options(
+                            options,
+                            storage,
+                            audit,
+                            time,
+                            audit::Source::Shell,
+                            |updated| {
+                                *updated = loaded;
+                                Ok(())
+                            },
+                        )
+                    })
+                }
+                Err(e) => Err(e.message()),
+            };
+            *ctx.local.config_load = None;
+            if saved.is_ok() {
+                app_request::spawn(AppRequest::ProfileOptions).ok();
+                let mode = options.lock(|options| options.modbus_mode());
+                serial_rx.lock(|rx| rx.set_framing(modbus::Framing::from_mode(mode)));
+            }
+            serial.lock(|serial| shell_reply(serial, saved.map(|_| "Config saved\r\n")));
+            return;
+        }
+
         // Try shell command
         match shell::process_line(&line) {
             shell::ShellResult::Ok(response) => {
@@ -776,32 +1361,150 @@ mod app {
                     nb::block!(serial.flush()).ok();
                 });
             }
+            shell::ShellResult::ReadEvents(count) => {
+                (&mut events, &mut storage, &mut serial).lock(|events, storage, serial| {
+                    for index in 0..u32::from(count) {
+                        match events.get(storage, index) {
+                            Ok(Some(event)) => {
+                                let line = shell::event_line(index, &event);
+                                for byte in line.as_bytes() {
+                                    nb::block!(serial.write(*byte)).ok();
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(_e) => {
+                                for byte in b"Error: event read\r\n" {
+                                    nb::block!(serial.write(*byte)).ok();
+                                }
+                                break;
+                            }
+                        }
+                    }
+                    nb::block!(serial.write(b'>')).ok();
+                    nb::block!(serial.write(b' ')).ok();
+                    nb::block!(serial.flush()).ok();
+                });
+            }
+            shell::ShellResult::ReadAudit(count) => {
+                (&mut audit, &mut storage, &mut serial).lock(|audit, storage, serial| {
+                    for index in 0..u32::from(count) {
+                        match audit.get(storage, index) {
+                            Ok(Some(record)) => {
+                                let line = shell::audit_line(&record);
+                                for byte in line.as_bytes() {
+                                    nb::block!(serial.write(*byte)).ok();
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(_e) => {
+                                for byte in b"Error: audit read\r\n" {
+                                    nb::block!(serial.write(*byte)).ok();
+                                }
+                                break;
+                            }
+                        }
+                    }
+                    nb::block!(serial.write(b'>')).ok();
+                    nb::block!(serial.write(b' ')).ok();
+                    nb::block!(serial.flush()).ok();
+                });
+            }
+            result @ (shell::ShellResult::AutoZero | shell::ShellResult::Calibrate(..)) => {
+                let job = match result {
+                    shell::ShellResult::Calibrate(point, lph) => {
+                        calibration::Job::Point(point, lph as f32 / 1000.0)
+                    }
+                    _ => calibration::Job::Zero,
+                };
+                // calibration_done replies once the measurements are in
+                let started = app.lock(|app| start_calibration(app, job, audit::Source::Shell));
+                if let Err(msg) = started {
+                    serial.lock(|serial| shell_reply(serial, Err(msg)));
+                }
+            }
+            shell::ShellResult::RestoreDefaults(keep) => {
+                let time = rtc.lock(|rtc| rtc.get_datetime()).as_utc().unix_timestamp() as u32;
+                let saved =
+                    (&mut options, &mut storage, &mut audit).lock(|options, storage, audit| {
+                        save_options(
+                            options,
+                            storage,
+                            audit,
+                            time,
+                            audit::Source::Shell,
+                            |updated| {
+                                updated.restore_defaults(keep);
+                                Ok(())
+                            },
+                        )
+                    });
+                if saved.is_ok() {
+                    app_request::spawn(AppRequest::ProfileOptions).ok();
+                    let mode = options.lock(|options| options.modbus_mode());
+                    serial_rx.lock(|rx| rx.set_framing(modbus::Framing::from_mode(mode)));
+                }
+                serial.lock(|serial| {
+                    shell_reply(
+                        serial,
+                        saved
+                            .map(|_| shell::defaults_restored())
+                            .as_ref()
+                            .map(|reply| reply.as_str())
+                            .map_err(|msg| *msg),
+                    )
+                });
+            }
+            shell::ShellResult::DumpConfig => {
+                let options = options.lock(|options| *options);
+                serial.lock(|serial| {
+                    for line in config_text::dump(&options) {
+                        for byte in line.as_bytes() {
+                            nb::block!(serial.write(*byte)).ok();
+                        }
+                    }
+                    shell_reply(serial, Ok(""));
+                });
+            }
+            shell::ShellResult::LoadConfig => {
+                let current = options.lock(|options| *options);
+                *ctx.local.config_load = Some(config_text::ConfigLoad::new(&current));
+                serial.lock(|serial| {
+                    shell_reply(serial, Ok("Send the config lines, ending with crc=\r\n"))
+                });
+            }
+            shell::ShellResult::ModbusStats => {
+                let counters = modbus_handler.lock(|handler| handler.counters());
+                serial.lock(|serial| {
+                    shell_reply(serial, Ok(shell::modbus_stats(&counters).as_str()))
+                });
+            }
             shell::ShellResult::NotAShellCommand => {
                 // Not a shell command — ignore (Modbus handles binary separately)
             }
         }
     }
 
-    /// Process complete Modbus RTU frame after 3.5-char silence
-    #[task(priority = 1, shared = [serial, modbus_handler, app, options, storage, hour_history, day_history, month_history, modbus_rx_buf, modbus_last_rx])]
+    /// Process a complete Modbus frame, or end one at silence
+    #[task(priority = 1, shared = [serial, modbus_handler, app, options, storage, hour_history, day_history, month_history, profile_history, hour_stats_history, day_stats_history, events, audit, rtc, serial_rx, modbus_last_rx])]
     fn modbus_poll(mut ctx: modbus_poll::Context) {
-        let mut modbus_last_rx = ctx.shared.modbus_last_rx;
+        let mut serial_rx = ctx.shared.serial_rx;
         let now = monotonics::now().ticks();
-        let last = modbus_last_rx.lock(|l| *l);
-        if now - last < 1 {
-            modbus_poll::spawn_after(1_u64.millis()).ok();
-            return;
-        }
-
-        let frame = ctx.shared.modbus_rx_buf.lock(|buf| {
-            let f = buf.clone();
-            buf.clear();
-            f
+        let last = ctx.shared.modbus_last_rx.lock(|l| *l);
+        // The IRQ may have stamped a byte since `now`
+        let silent = now.saturating_sub(last);
+
+        let (frame, framing) = serial_rx.lock(|rx| {
+            rx.silence(silent);
+            if rx.receiving() {
+                // Not silent long enough yet to end or drop the frame
+                let left = rx_timeout(rx.framing()).saturating_sub(silent).max(1);
+                modbus_poll::spawn_after(left.millis()).ok();
+            }
+            (rx.take_frame(), rx.framing())
         });
-
-        if frame.is_empty() {
+        let Some(frame) = frame else {
             return;
-        }
+        };
 
         let (
             modbus_handler,
@@ -811,6 +1514,12 @@ mod app {
             hour_history,
             day_history,
             month_history,
+            profile_history,
+            hour_stats_history,
+            day_stats_history,
+            events,
+            audit,
+            mut rtc,
             mut serial,
         ) = (
             ctx.shared.modbus_handler,
@@ -820,8 +1529,16 @@ mod app {
             ctx.shared.hour_history,
             ctx.shared.day_history,
             ctx.shared.month_history,
+            ctx.shared.profile_history,
+            ctx.shared.hour_stats_history,
+            ctx.shared.day_stats_history,
+            ctx.shared.events,
+            ctx.shared.audit,
+            ctx.shared.rtc,
             ctx.shared.serial,
         );
+        // Audit records of this request are stamped with the time it arrived
+        let time = rtc.lock(|rtc| rtc.get_datetime()).as_utc().unix_timestamp() as u32;
 
         (
             modbus_handler,
@@ -831,6 +1548,11 @@ mod app {
             hour_history,
             day_history,
             month_history,
+            profile_history,
+            hour_stats_history,
+            day_stats_history,
+            events,
+            audit,
         )
             .lock(
                 |modbus_handler,
@@ -839,7 +1561,15 @@ mod app {
                  storage,
                  hour_history,
                  day_history,
-                 month_history| {
+                 month_history,
+                 profile_history,
+                 hour_stats_history,
+                 day_stats_history,
+                 events,
+                 audit| {
+                    modbus_handler.set_framing(framing);
+                    modbus_handler.set_status(app.status());
+                    modbus_handler.set_rest_total(app.rest_total());
                     let result = modbus_handler.handle_request(
                         &frame,
                         options,
@@ -848,16 +1578,37 @@ mod app {
                         app.hour_flow,
                         app.day_flow,
                         app.month_flow,
-                        hour_history,
-                        day_history,
+                        &mut modbus_handler::WithStats {
+                            history: hour_history,
+                            stats: hour_stats_history,
+                        },
+                        &mut modbus_handler::WithStats {
+                            history: day_history,
+                            stats: day_stats_history,
+                        },
                         month_history,
+                        profile_history,
+                        events,
+                        &mut modbus_handler::AuditAt { log: audit, time },
                     );
+                    if let Some(register) = modbus_handler.take_config_write() {
+                        log_event::spawn(EventCode::ConfigWrite, u32::from(register)).ok();
+                        app_request::spawn(AppRequest::ProfileOptions).ok();
+                        // The reply still goes out in the framing of the request
+                        serial_rx.lock(|rx| {
+                            rx.set_framing(modbus::Framing::from_mode(options.modbus_mode()))
+                        });
+                    }
+                    for request in modbus_handler.take_commands() {
+                        app_request::spawn(request).ok();
+                    }
 
                     if let Ok(response) = result {
+                        let framing = modbus_handler.framing();
                         serial.lock(|serial| {
-                            for byte in response.iter() {
-                                nb::block!(serial.write(*byte)).ok();
-                            }
+                            framing.write(&response, |byte| {
+                                nb::block!(serial.write(byte)).ok();
+                            });
                             nb::block!(serial.flush()).ok();
                         });
                     }
@@ -883,48 +1634,132 @@ mod app {
                     }
                     if status.contains(hardware::tdc7200::InterruptStatus::TIMEOUT_ERROR) {
                         defmt::warn!("TDC7200 timeout error");
+                        log_event::spawn(EventCode::TdcTimeout, 0).ok();
                     }
                     if status.contains(hardware::tdc7200::InterruptStatus::COARSE_COUNTER_OVERFLOW)
                     {
                         defmt::warn!("TDC7200 coarse counter overflow");
+                        log_event::spawn(EventCode::TdcOverflow, 0).ok();
                     }
                     let _ = tdc.clear_interrupt_status(status);
                 }
                 Err(_) => {
                     defmt::error!("TDC7200 SPI read failed");
+                    log_event::spawn(EventCode::TdcBus, 7200).ok();
                 }
             }
         });
     }
 
     /// Read TDC7200 measurement results and calculate flow
-    #[task(priority = 2, shared = [tdc7200, app])]
+    #[task(priority = 2, shared = [tdc7200, app, options])]
     fn tdc7200_result(ctx: tdc7200_result::Context) {
-        let (mut tdc7200, mut app) = (ctx.shared.tdc7200, ctx.shared.app);
+        let (mut tdc7200, mut app, mut options) =
+            (ctx.shared.tdc7200, ctx.shared.app, ctx.shared.options);
+
+        // Read measurement results
+        let results = tdc7200.lock(|tdc| {
+            (
+                tdc.get_measurement1(),
+                tdc.get_measurement2(),
+                tdc.get_reference_clock_counter(),
+            )
+        });
 
-        tdc7200.lock(|tdc| {
-            // Read measurement results
-            let m1 = tdc.get_measurement1();
-            let m2 = tdc.get_measurement2();
-            let ref_clk = tdc.get_reference_clock_counter();
-
-            match (m1, m2, ref_clk) {
-                (Ok(m1_val), Ok(m2_val), Ok(ref_val)) => {
-                    defmt::info!("TDC7200: m1={}, m2={}, ref={}", m1_val, m2_val, ref_val);
-                    // TODO: Calculate actual flow from TDC measurements
-                    // For now, store raw values and mark measurement as done
-                    // The flow calculation requires calibration data from Options
-                    // and the physical formula: v = L²/(2*m1) * (1/m2 - 1/m1)
-                    // where L = distance between transducers
-                    app.lock(|app| {
-                        app.flow = 0.0; // Placeholder until calculation is implemented
-                    });
+        match results {
+            (Ok(m1_val), Ok(m2_val), Ok(ref_val)) => {
+                defmt::info!("TDC7200: m1={}, m2={}, ref={}", m1_val, m2_val, ref_val);
+                // m1 and m2 are the upstream and downstream TOF of the
+                // channel Process selected
+                // TODO: Calculate actual flow from TDC measurements
+                // The flow calculation requires calibration data from Options
+                // and the physical formula: v = L²/(2*m1) * (1/m2 - 1/m1)
+                // where L = distance between transducers
+                let samples_done = (&mut app, &mut options).lock(|app, options| {
+                    let channel = app.channel;
+                    let table = options.calib_table(channel).unwrap_or_default();
+                    let raw_flow =
+                        calibration::Calculator::new(calibration::MeterConfig::default())
+                            .get_raw_volume(&table, m1_val as f32, m2_val as f32);
+                    app.channel = (channel + 1) % 2;
+                    app.flow = 0.0; // Placeholder until calculation is implemented
+                    app.alarms.remove(MeterStatus::TDC_ERROR);
+                    app.record_measurement(channel, m1_val as i32 - m2_val as i32, raw_flow)
+                });
+                if samples_done {
+                    calibration_done::spawn().ok();
                 }
-                _ => {
-                    defmt::error!("TDC7200 read failed");
+            }
+            _ => {
+                defmt::error!("TDC7200 read failed");
+                log_event::spawn(EventCode::TdcBus, 7200).ok();
+            }
+        }
+    }
+
+    /// Save the auto-zero or auto-calibration `tdc7200_result` collected
+    /// the measurements for, and answer the shell if it asked. A Modbus
+    /// master sees the audit record, or a `CalibrationFailed` event.
+    #[task(priority = 1, shared = [serial, app, options, storage, audit, rtc])]
+    fn calibration_done(ctx: calibration_done::Context) {
+        let (mut serial, mut app, mut options, mut storage, mut audit, mut rtc) = (
+            ctx.shared.serial,
+            ctx.shared.app,
+            ctx.shared.options,
+            ctx.shared.storage,
+            ctx.shared.audit,
+            ctx.shared.rtc,
+        );
+        let calibrating = match app.lock(|app| app.calibrating.take()) {
+            Some(calibrating) => calibrating,
+            None => return,
+        };
+
+        let time = rtc.lock(|rtc| rtc.get_datetime()).as_utc().unix_timestamp() as u32;
+        let source = calibrating.source;
+        let saved = (&mut options, &mut storage, &mut audit).lock(|options, storage, audit| {
+            match calibrating.job {
+                calibration::Job::Zero => {
+                    let zero = calibrating.sampler.zero().map_err(|_| "no signal")?;
+                    save_options(options, storage, audit, time, source, |updated| {
+                        calibration::store_zero(updated, zero)
+                            .map_err(|_| "calibration out of range")
+                    })
+                    .map(|_| shell::calibration_saved(None))
+                }
+                calibration::Job::Point(point, vet) => {
+                    let data = calibrating
+                        .sampler
+                        .point(point, vet)
+                        .map_err(|_| "no signal")?;
+                    save_options(options, storage, audit, time, source, |updated| {
+                        calibration::store_point(updated, point, data)
+                            .map_err(|_| "calibration out of range")
+                    })
+                    .map(|_| shell::calibration_saved(Some(point)))
                 }
             }
         });
+
+        if let Err(msg) = saved {
+            defmt::error!("Calibration failed: {}", msg);
+            let point = match calibrating.job {
+                calibration::Job::Zero => 0,
+                calibration::Job::Point(point, _) => u32::from(point),
+            };
+            log_event::spawn(EventCode::CalibrationFailed, point).ok();
+        }
+        if source == audit::Source::Shell {
+            serial.lock(|serial| {
+                shell_reply(
+                    serial,
+                    saved
+                        .as_ref()
+                        .map(|reply| reply.as_str())
+                        .map_err(|msg| *msg),
+                )
+            });
+        }
     }
 
     #[idle(local = [iwdg])]
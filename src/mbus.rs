@@ -4,12 +4,15 @@
 //! Matches C++ mbus.cpp behavior — no request/response,
 //! just broadcasts meter data at configured intervals.
 
+use crate::history::FlowStats;
 use heapless::Vec;
+use time::OffsetDateTime;
 
 /// M-Bus frame buffer size
 const FRAME_BUF: usize = 128;
 
 /// Build an M-Bus RSP_U datagram with current meter data.
+/// `hour_stats` adds the min/max flow of the last stored hour.
 /// Returns the complete frame bytes ready to send.
 ///
 /// Frame structure (EN 13757-3):
@@ -20,6 +23,7 @@ pub fn build_datagram(
     total_volume: f32,
     flow_rate: f32,
     uptime_minutes: u32,
+    hour_stats: Option<FlowStats>,
 ) -> Vec<u8, FRAME_BUF> {
     let mut frame: Vec<u8, FRAME_BUF> = Vec::new();
 
@@ -68,6 +72,23 @@ pub fn build_datagram(
     frame.push(0x21).ok();
     push_le32(&mut frame, uptime_minutes);
 
+    if let Some(stats) = hour_stats.filter(|stats| !stats.is_empty()) {
+        // Maximum flow m³/h (DIF=0x15 32bit real, function max) and its time (VIF=0x6D type F)
+        frame.push(0x15).ok();
+        frame.push(0x3B).ok();
+        push_le32(&mut frame, stats.max.to_bits());
+        frame.push(0x14).ok();
+        frame.push(0x6D).ok();
+        push_le32(&mut frame, date_time_f(stats.max_at));
+        // Minimum flow m³/h (DIF=0x25 32bit real, function min) and its time
+        frame.push(0x25).ok();
+        frame.push(0x3B).ok();
+        push_le32(&mut frame, stats.min.to_bits());
+        frame.push(0x24).ok();
+        frame.push(0x6D).ok();
+        push_le32(&mut frame, date_time_f(stats.min_at));
+    }
+
     // Fix length fields L
     let data_len = frame.len() - 4; // minus 68 L L 68
     if data_len <= 255 {
@@ -96,6 +117,20 @@ fn dec_to_bcd32(mut dec: u32) -> u32 {
     result
 }
 
+/// Encode a Unix time as M-Bus date/time type F (EN 13757-3 annex A)
+fn date_time_f(time: u32) -> u32 {
+    let Ok(dt) = OffsetDateTime::from_unix_timestamp(time as i64) else {
+        return 0;
+    };
+    let year = (dt.year() - 2000).clamp(0, 127) as u32;
+    dt.minute() as u32
+        | (dt.hour() as u32) << 8
+        | (dt.day() as u32) << 16
+        | (year & 0x07) << 21
+        | (dt.month() as u32) << 24
+        | (year >> 3) << 28
+}
+
 fn push_le32(buf: &mut Vec<u8, FRAME_BUF>, val: u32) {
     buf.push(val as u8).ok();
     buf.push((val >> 8) as u8).ok();
@@ -121,7 +156,7 @@ mod tests {
 
     #[test]
     fn test_datagram_structure() {
-        let frame = build_datagram(1, 12345, 100.0_f32, 0.5_f32, 120);
+        let frame = build_datagram(1, 12345, 100.0_f32, 0.5_f32, 120, None);
         // Starts with 68 L L 68
         assert_eq!(frame[0], 0x68);
         assert_eq!(frame[3], 0x68);
@@ -137,7 +172,7 @@ mod tests {
 
     #[test]
     fn test_datagram_serial_bcd() {
-        let frame = build_datagram(1, 12345, 0.0_f32, 0.0_f32, 0);
+        let frame = build_datagram(1, 12345, 0.0_f32, 0.0_f32, 0, None);
         // Serial at bytes 7-10 (little-endian BCD)
         let serial = u32::from_le_bytes([frame[7], frame[8], frame[9], frame[10]]);
         assert_eq!(serial, 0x00012345);
@@ -145,7 +180,7 @@ mod tests {
 
     #[test]
     fn test_checksum_is_valid() {
-        let frame = build_datagram(5, 999, 42.0_f32, 1.5_f32, 60);
+        let frame = build_datagram(5, 999, 42.0_f32, 1.5_f32, 60, None);
         // Checksum = sum of bytes [4..n-2]
         let data_end = frame.len() - 2;
         let expected_checksum: u8 = frame[4..data_end]
@@ -153,4 +188,42 @@ mod tests {
             .fold(0u8, |acc, &b| acc.wrapping_add(b));
         assert_eq!(frame[data_end], expected_checksum);
     }
+
+    #[test]
+    fn test_date_time_f() {
+        // 2024-03-15 13:45:00 UTC
+        let f = date_time_f(1_710_510_300);
+        assert_eq!(f & 0x3F, 45);
+        assert_eq!((f >> 8) & 0x1F, 13);
+        assert_eq!((f >> 16) & 0x1F, 15);
+        assert_eq!((f >> 24) & 0x0F, 3);
+        assert_eq!(((f >> 21) & 0x07) | ((f >> 28) << 3), 24);
+    }
+
+    #[test]
+    fn test_datagram_flow_stats() {
+        let plain = build_datagram(1, 12345, 100.0_f32, 0.5_f32, 120, None);
+        let stats = FlowStats {
+            min: 0.25,
+            min_at: 1_710_510_300,
+            max: 4.5,
+            max_at: 1_710_511_200,
+        };
+        let frame = build_datagram(1, 12345, 100.0_f32, 0.5_f32, 120, Some(stats));
+        assert_eq!(frame.len(), plain.len() + 4 * 6);
+        assert_eq!(frame[1] as usize, frame.len() - 6);
+
+        let records = &frame[plain.len() - 2..frame.len() - 2];
+        assert_eq!(&records[..2], &[0x15, 0x3B]);
+        assert_eq!(&records[2..6], &4.5_f32.to_le_bytes());
+        assert_eq!(&records[6..8], &[0x14, 0x6D]);
+        assert_eq!(&records[8..12], &date_time_f(stats.max_at).to_le_bytes());
+        assert_eq!(&records[12..14], &[0x25, 0x3B]);
+        assert_eq!(&records[14..18], &0.25_f32.to_le_bytes());
+        assert_eq!(&records[18..20], &[0x24, 0x6D]);
+
+        // An hour without samples adds nothing
+        let empty = build_datagram(1, 12345, 100.0_f32, 0.5_f32, 120, Some(FlowStats::EMPTY));
+        assert_eq!(empty, plain);
+    }
 }
@@ -1,6 +1,7 @@
 //! Modbus RTU Slave Implementation
 //!
-//! This module implements a Modbus RTU slave protocol over serial communication.
+//! This module implements a Modbus RTU slave protocol over serial communication,
+//! and the Modbus ASCII framing selected by `Options::modbus_mode`.
 //! Supports reading configuration (Options) and history data (Hour/Day/Month).
 //!
 //! ## Register Map
@@ -19,10 +20,20 @@
 //!   - 0-1: Flow rate (f32)
 //!   - 2-3: Hour flow (f32)
 //!
-//! ### History Data (Function 0x17 - Read/Write Multiple Registers)
+//! ### History Data (Functions 0x03 / 0x10, or 0x17 - Read/Write Multiple Registers)
 //! - Hour History: Start address 0x1000
 //! - Day History: Start address 0x2000
 //! - Month History: Start address 0x3000
+//!
+//! 0x17 writes a selector and reads the window back in one transaction.
+//!
+//! ### Coils and Discrete Inputs (Functions 0x01 / 0x05 / 0x0F, 0x02)
+//! - Coils 0-3: commands, run once when written 1
+//! - Discrete inputs 0-4: status bits
+//!
+//! ### Device Identification (Function 0x2B / MEI 0x0E)
+//! - Vendor, product code and firmware version (basic), product name
+//!   (regular), serial number and sensor type (extended)
 
 #![allow(dead_code)]
 
@@ -37,9 +48,13 @@ pub enum FunctionCode {
     ReadInputRegisters = 0x04,
     WriteSingleCoil = 0x05,
     WriteSingleRegister = 0x06,
+    Diagnostics = 0x08,
+    GetCommEventCounter = 0x0B,
     WriteMultipleCoils = 0x0F,
     WriteMultipleRegisters = 0x10,
     ReadWriteMultipleRegisters = 0x17,
+    /// Encapsulated Interface Transport, only MEI 0x0E (device identification)
+    EncapsulatedInterface = 0x2B,
 }
 
 impl FunctionCode {
@@ -51,9 +66,12 @@ impl FunctionCode {
             0x04 => Some(Self::ReadInputRegisters),
             0x05 => Some(Self::WriteSingleCoil),
             0x06 => Some(Self::WriteSingleRegister),
+            0x08 => Some(Self::Diagnostics),
+            0x0B => Some(Self::GetCommEventCounter),
             0x0F => Some(Self::WriteMultipleCoils),
             0x10 => Some(Self::WriteMultipleRegisters),
             0x17 => Some(Self::ReadWriteMultipleRegisters),
+            0x2B => Some(Self::EncapsulatedInterface),
             _ => None,
         }
     }
@@ -86,6 +104,16 @@ pub struct ModbusRequest {
     pub start_address: u16,
     pub quantity: u16,
     pub write_data: Vec<u8, 256>,
+    /// Read part of 0x17, whose write part is in `start_address`,
+    /// `quantity` and `write_data`; zero for other functions
+    pub read_address: u16,
+    pub read_quantity: u16,
+    /// MEI type, Read Device ID code and object id of 0x2B; zero for
+    /// other functions. 0x08 has its sub-function in `start_address` and
+    /// its data in `write_data`.
+    pub mei_type: u8,
+    pub read_device_id: u8,
+    pub object_id: u8,
 }
 
 /// Modbus response
@@ -108,7 +136,7 @@ impl ModbusRtu {
     }
 
     /// Calculate CRC16 (Modbus)
-    fn calculate_crc(data: &[u8]) -> u16 {
+    pub(crate) fn calculate_crc(data: &[u8]) -> u16 {
         let mut crc: u16 = 0xFFFF;
         for byte in data {
             crc ^= *byte as u16;
@@ -125,31 +153,61 @@ impl ModbusRtu {
 
     /// Parse incoming Modbus RTU frame
     pub fn parse_request(&self, frame: &[u8]) -> Result<ModbusRequest, ModbusError> {
-        // Minimum frame: slave(1) + function(1) + data(4) + crc(2) = 8 bytes
-        if frame.len() < 8 {
+        // Shortest frame: slave(1) + function(1) + crc(2) = 4 bytes (0x0B)
+        if frame.len() < 4 {
             return Err(ModbusError::InvalidLength);
         }
 
-        // Check slave address
-        let slave_address = frame[0];
-        if slave_address != self.slave_address && slave_address != 0 {
-            return Err(ModbusError::InvalidSlaveAddress);
-        }
-
-        // Verify CRC
+        // Verify CRC first, so a corrupted frame counts as one whatever its address
         let received_crc = u16::from_le_bytes([frame[frame.len() - 2], frame[frame.len() - 1]]);
         let calculated_crc = Self::calculate_crc(&frame[..frame.len() - 2]);
         if received_crc != calculated_crc {
             return Err(ModbusError::InvalidCrc);
         }
 
+        self.parse_frame(&frame[..frame.len() - 2])
+    }
+
+    /// Parse incoming Modbus ASCII frame, ':' to CRLF
+    pub fn parse_ascii_request(&self, frame: &[u8]) -> Result<ModbusRequest, ModbusError> {
+        self.parse_frame(&ascii::decode(frame)?)
+    }
+
+    /// Parse slave address and PDU, the checksum checked and cut off
+    fn parse_frame(&self, frame: &[u8]) -> Result<ModbusRequest, ModbusError> {
+        if frame.len() < 2 {
+            return Err(ModbusError::InvalidLength);
+        }
+
+        // Check slave address
+        let slave_address = frame[0];
+        if slave_address != self.slave_address && slave_address != 0 {
+            return Err(ModbusError::InvalidSlaveAddress);
+        }
+
         // Parse function code
         let function_code = FunctionCode::from_u8(frame[1])
             .ok_or(ModbusError::Exception(ExceptionCode::IllegalFunction))?;
 
+        // MEI(1) + code(1) + object(1) for 0x2B, data(4) and the byte count
+        // for writes of many, data(8) and the byte count for 0x17
+        let min_len = match function_code {
+            FunctionCode::GetCommEventCounter => 2,
+            FunctionCode::EncapsulatedInterface => 5,
+            FunctionCode::WriteMultipleCoils | FunctionCode::WriteMultipleRegisters => 7,
+            FunctionCode::ReadWriteMultipleRegisters => 11,
+            _ => 6,
+        };
+        if frame.len() < min_len {
+            return Err(ModbusError::InvalidLength);
+        }
+
         // Parse data based on function code
         match function_code {
-            FunctionCode::ReadHoldingRegisters | FunctionCode::ReadInputRegisters => {
+            FunctionCode::ReadCoils
+            | FunctionCode::ReadDiscreteInputs
+            | FunctionCode::ReadHoldingRegisters
+            | FunctionCode::ReadInputRegisters => {
                 let start_address = u16::from_be_bytes([frame[2], frame[3]]);
                 let quantity = u16::from_be_bytes([frame[4], frame[5]]);
 
@@ -159,9 +217,14 @@ impl ModbusRtu {
                     start_address,
                     quantity,
                     write_data: Vec::new(),
+                    read_address: 0,
+                    read_quantity: 0,
+                    mei_type: 0,
+                    read_device_id: 0,
+                    object_id: 0,
                 })
             }
-            FunctionCode::WriteSingleRegister => {
+            FunctionCode::WriteSingleCoil | FunctionCode::WriteSingleRegister => {
                 let start_address = u16::from_be_bytes([frame[2], frame[3]]);
                 let mut write_data = Vec::new();
                 write_data.push(frame[4]).ok();
@@ -173,14 +236,19 @@ impl ModbusRtu {
                     start_address,
                     quantity: 1,
                     write_data,
+                    read_address: 0,
+                    read_quantity: 0,
+                    mei_type: 0,
+                    read_device_id: 0,
+                    object_id: 0,
                 })
             }
-            FunctionCode::WriteMultipleRegisters => {
+            FunctionCode::WriteMultipleCoils | FunctionCode::WriteMultipleRegisters => {
                 let start_address = u16::from_be_bytes([frame[2], frame[3]]);
                 let quantity = u16::from_be_bytes([frame[4], frame[5]]);
                 let byte_count = frame[6] as usize;
 
-                if frame.len() < 7 + byte_count + 2 {
+                if frame.len() < 7 + byte_count {
                     return Err(ModbusError::InvalidLength);
                 }
 
@@ -197,9 +265,86 @@ impl ModbusRtu {
                     start_address,
                     quantity,
                     write_data,
+                    read_address: 0,
+                    read_quantity: 0,
+                    mei_type: 0,
+                    read_device_id: 0,
+                    object_id: 0,
+                })
+            }
+            FunctionCode::ReadWriteMultipleRegisters => {
+                // read start, read quantity, write start, write quantity, byte count
+                let read_address = u16::from_be_bytes([frame[2], frame[3]]);
+                let read_quantity = u16::from_be_bytes([frame[4], frame[5]]);
+                let start_address = u16::from_be_bytes([frame[6], frame[7]]);
+                let quantity = u16::from_be_bytes([frame[8], frame[9]]);
+                let byte_count = frame[10] as usize;
+
+                if frame.len() < 11 + byte_count {
+                    return Err(ModbusError::InvalidLength);
+                }
+
+                let mut write_data = Vec::new();
+                write_data
+                    .extend_from_slice(&frame[11..11 + byte_count])
+                    .map_err(|_| ModbusError::BufferTooSmall)?;
+
+                Ok(ModbusRequest {
+                    slave_address,
+                    function_code,
+                    start_address,
+                    quantity,
+                    write_data,
+                    read_address,
+                    read_quantity,
+                    mei_type: 0,
+                    read_device_id: 0,
+                    object_id: 0,
                 })
             }
-            _ => Err(ModbusError::Exception(ExceptionCode::IllegalFunction)),
+            FunctionCode::Diagnostics => {
+                let mut write_data = Vec::new();
+                write_data
+                    .extend_from_slice(&frame[4..])
+                    .map_err(|_| ModbusError::BufferTooSmall)?;
+
+                Ok(ModbusRequest {
+                    slave_address,
+                    function_code,
+                    start_address: u16::from_be_bytes([frame[2], frame[3]]),
+                    quantity: 0,
+                    write_data,
+                    read_address: 0,
+                    read_quantity: 0,
+                    mei_type: 0,
+                    read_device_id: 0,
+                    object_id: 0,
+                })
+            }
+            FunctionCode::GetCommEventCounter => Ok(ModbusRequest {
+                slave_address,
+                function_code,
+                start_address: 0,
+                quantity: 0,
+                write_data: Vec::new(),
+                read_address: 0,
+                read_quantity: 0,
+                mei_type: 0,
+                read_device_id: 0,
+                object_id: 0,
+            }),
+            FunctionCode::EncapsulatedInterface => Ok(ModbusRequest {
+                slave_address,
+                function_code,
+                start_address: 0,
+                quantity: 0,
+                write_data: Vec::new(),
+                read_address: 0,
+                read_quantity: 0,
+                mei_type: frame[2],
+                read_device_id: frame[3],
+                object_id: frame[4],
+            }),
         }
     }
 
@@ -277,6 +422,363 @@ impl ModbusRtu {
     }
 }
 
+/// Bits `start..start + quantity` of `bits`, packed LSB first into whole
+/// bytes as 0x01 / 0x02 answer them. The caller keeps the range inside `bits`.
+pub fn pack_bits(bits: u32, start: u16, quantity: u16) -> Vec<u8, 4> {
+    let mask = ((1_u64 << quantity) - 1) as u32;
+    let value = (bits >> start) & mask;
+    let bytes = usize::from(quantity).div_ceil(8);
+    Vec::from_slice(&value.to_le_bytes()[..bytes]).unwrap()
+}
+
+/// The `quantity` bits of a 0x0F request, moved up to `start`. `None` if
+/// `data` is not the right length or the range does not fit in a `u32`.
+pub fn unpack_bits(data: &[u8], start: u16, quantity: u16) -> Option<u32> {
+    if quantity == 0 || u32::from(start) + u32::from(quantity) > 32 {
+        return None;
+    }
+    if data.len() != usize::from(quantity).div_ceil(8) {
+        return None;
+    }
+    let mut bytes = [0_u8; 4];
+    bytes[..data.len()].copy_from_slice(data);
+    let mask = ((1_u64 << quantity) - 1) as u32;
+    Some((u32::from_le_bytes(bytes) & mask) << start)
+}
+
+/// Framing on the wire, chosen by `Options::modbus_mode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Binary frames with a CRC, ended by a silent interval
+    Rtu,
+    /// Hex digits between ':' and CRLF, with an LRC
+    Ascii,
+}
+
+impl Framing {
+    /// `modbus_mode` 1 is ASCII, anything else RTU
+    pub fn from_mode(mode: u8) -> Self {
+        match mode {
+            1 => Framing::Ascii,
+            _ => Framing::Rtu,
+        }
+    }
+
+    /// Send a frame built by `ModbusRtu`: as it is for RTU, re-encoded for ASCII
+    pub fn write(self, frame: &[u8], write: impl FnMut(u8)) {
+        match self {
+            Framing::Rtu => frame.iter().copied().for_each(write),
+            Framing::Ascii => ascii::encode(frame, write),
+        }
+    }
+}
+
+/// Length of an RTU request with its CRC, once enough of it is in to tell.
+/// `None` also for the requests only silence ends: a Return Query Data echo
+/// of any length, and functions or MEI types this slave does not know.
+fn rtu_request_len(frame: &[u8]) -> Option<usize> {
+    let function_code = FunctionCode::from_u8(*frame.get(1)?)?;
+    match function_code {
+        FunctionCode::GetCommEventCounter => Some(4),
+        FunctionCode::EncapsulatedInterface => (*frame.get(2)? == 0x0E).then_some(7),
+        FunctionCode::Diagnostics => (frame.get(2..4)? != [0, 0]).then_some(8),
+        FunctionCode::WriteMultipleCoils | FunctionCode::WriteMultipleRegisters => {
+            Some(9 + usize::from(*frame.get(6)?))
+        }
+        FunctionCode::ReadWriteMultipleRegisters => Some(13 + usize::from(*frame.get(10)?)),
+        _ => Some(8),
+    }
+}
+
+/// What a byte or a silence completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Received {
+    Nothing,
+    /// A request is waiting in `Receiver::take_frame()`
+    Frame,
+    /// A shell line is waiting in `Receiver::take_line()`
+    Line,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RxState {
+    Idle,
+    /// One byte in: the next one tells an RTU request from shell text
+    Started,
+    Rtu,
+    Ascii,
+    Line,
+    /// A request waits to be taken; bytes until then are dropped
+    Frame,
+}
+
+/// Splits what USART1 receives into Modbus requests and shell lines.
+///
+/// RTU requests end at the length their function code gives, or else at
+/// `RTU_SILENCE_MS` of silence. Their second byte is a function code, so a
+/// slave address or function code that happens to be printable does not
+/// make them shell text. ASCII requests run from ':' to LF. Other printable
+/// text up to CR or LF is a shell line.
+pub struct Receiver {
+    framing: Framing,
+    state: RxState,
+    frame: Vec<u8, { ascii::MAX_FRAME }>,
+    line: Vec<u8, { Receiver::LINE_LEN }>,
+    lines: heapless::Deque<Vec<u8, { Receiver::LINE_LEN }>, 4>,
+}
+
+impl Receiver {
+    pub const LINE_LEN: usize = 80;
+    /// Silence (ms) that ends an RTU request, at least 3.5 characters at
+    /// 9600 bps and two ticks, so a tick between two bytes does not
+    pub const RTU_SILENCE_MS: u64 = 5;
+
+    pub fn new(framing: Framing) -> Self {
+        Self {
+            framing,
+            state: RxState::Idle,
+            frame: Vec::new(),
+            line: Vec::new(),
+            lines: heapless::Deque::new(),
+        }
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Switch framing; a request partly received is dropped
+    pub fn set_framing(&mut self, framing: Framing) {
+        if framing != self.framing {
+            self.framing = framing;
+            if self.receiving() {
+                self.state = RxState::Idle;
+                self.frame.clear();
+            }
+        }
+    }
+
+    /// A request is partly received, so `silence()` is due
+    pub fn receiving(&self) -> bool {
+        matches!(self.state, RxState::Started | RxState::Rtu | RxState::Ascii)
+    }
+
+    /// Take the next byte
+    pub fn push(&mut self, byte: u8) -> Received {
+        let text = byte.is_ascii() && byte >= b' ';
+        let eol = byte == b'\r' || byte == b'\n';
+        match self.state {
+            RxState::Frame => Received::Nothing,
+            RxState::Idle => self.start(byte),
+            RxState::Started => {
+                let first = self.frame[0];
+                if FunctionCode::from_u8(byte).is_none()
+                    && first.is_ascii()
+                    && first >= b' '
+                    && (text || eol)
+                {
+                    self.frame.clear();
+                    self.state = RxState::Line;
+                    self.line.clear();
+                    self.line.push(first).ok();
+                    return self.push(byte);
+                }
+                self.state = RxState::Rtu;
+                self.push_rtu(byte)
+            }
+            RxState::Rtu => self.push_rtu(byte),
+            RxState::Ascii => {
+                if self.frame.push(byte).is_err() {
+                    self.state = RxState::Idle;
+                    self.frame.clear();
+                    Received::Nothing
+                } else if byte == b'\n' {
+                    self.state = RxState::Frame;
+                    Received::Frame
+                } else {
+                    Received::Nothing
+                }
+            }
+            RxState::Line if eol => {
+                self.state = RxState::Idle;
+                let line = core::mem::take(&mut self.line);
+                // A full queue drops the oldest line
+                if self.lines.is_full() {
+                    self.lines.pop_front();
+                }
+                self.lines.push_back(line).ok();
+                Received::Line
+            }
+            RxState::Line if self.framing == Framing::Ascii && byte == b':' => {
+                self.line.clear();
+                self.start(byte)
+            }
+            RxState::Line if text => {
+                if self.line.push(byte).is_err() {
+                    // Overflow, start over
+                    self.line.clear();
+                }
+                Received::Nothing
+            }
+            RxState::Line => {
+                // Binary: not a shell line after all
+                self.line.clear();
+                self.start(byte)
+            }
+        }
+    }
+
+    /// No byte for `ms`: ends an RTU request, a shell line typed one
+    /// character at a time goes on, and a partial ASCII request is dropped
+    /// after `ascii::CHAR_TIMEOUT_MS`
+    pub fn silence(&mut self, ms: u64) -> Received {
+        match self.state {
+            RxState::Started if ms >= Self::RTU_SILENCE_MS => {
+                let first = self.frame[0];
+                self.frame.clear();
+                if first.is_ascii() && first >= b' ' {
+                    self.state = RxState::Line;
+                    self.line.clear();
+                    self.line.push(first).ok();
+                } else {
+                    self.state = RxState::Idle;
+                }
+                Received::Nothing
+            }
+            RxState::Rtu if ms >= Self::RTU_SILENCE_MS => {
+                self.state = RxState::Frame;
+                Received::Frame
+            }
+            RxState::Ascii if ms >= ascii::CHAR_TIMEOUT_MS => {
+                self.state = RxState::Idle;
+                self.frame.clear();
+                Received::Nothing
+            }
+            _ => Received::Nothing,
+        }
+    }
+
+    /// The request `Received::Frame` reported, as it came in
+    pub fn take_frame(&mut self) -> Option<Vec<u8, { ascii::MAX_FRAME }>> {
+        if self.state != RxState::Frame {
+            return None;
+        }
+        self.state = RxState::Idle;
+        Some(core::mem::take(&mut self.frame))
+    }
+
+    pub fn has_line(&self) -> bool {
+        !self.lines.is_empty()
+    }
+
+    /// The oldest shell line `Received::Line` reported, without CR or LF
+    pub fn take_line(&mut self) -> Option<Vec<u8, { Receiver::LINE_LEN }>> {
+        self.lines.pop_front()
+    }
+
+    fn start(&mut self, byte: u8) -> Received {
+        self.frame.clear();
+        match self.framing {
+            Framing::Ascii if byte == b':' => {
+                self.state = RxState::Ascii;
+                self.frame.push(byte).ok();
+            }
+            Framing::Rtu if byte != b'\r' && byte != b'\n' => {
+                self.state = RxState::Started;
+                self.frame.push(byte).ok();
+            }
+            Framing::Ascii if byte.is_ascii() && byte >= b' ' => {
+                self.state = RxState::Line;
+                self.line.clear();
+                self.line.push(byte).ok();
+            }
+            // Empty lines, and binary bytes with ASCII framing
+            _ => self.state = RxState::Idle,
+        }
+        Received::Nothing
+    }
+
+    fn push_rtu(&mut self, byte: u8) -> Received {
+        // Longer than any RTU frame: drop it, silence starts the next one
+        if self.frame.len() >= 256 || self.frame.push(byte).is_err() {
+            self.frame.clear();
+            return Received::Nothing;
+        }
+        if rtu_request_len(&self.frame) == Some(self.frame.len()) {
+            self.state = RxState::Frame;
+            Received::Frame
+        } else {
+            Received::Nothing
+        }
+    }
+}
+
+/// Modbus ASCII codec: ':', then slave address, PDU and LRC as pairs of hex
+/// digits, then CRLF. Requests decode to the bytes an RTU frame carries, so
+/// both framings share `ModbusRtu::parse_frame` and the response builders.
+pub mod ascii {
+    use super::ModbusError;
+    use heapless::Vec;
+
+    /// ':' + slave address, PDU of up to 253 bytes and LRC as hex + CRLF
+    pub const MAX_FRAME: usize = 1 + 2 * 255 + 2;
+    /// Longest silence inside a frame (ms); a frame left longer is dropped
+    pub const CHAR_TIMEOUT_MS: u64 = 1000;
+
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    /// Two's complement of the byte sum
+    pub fn lrc(bytes: &[u8]) -> u8 {
+        bytes
+            .iter()
+            .fold(0_u8, |sum, byte| sum.wrapping_add(*byte))
+            .wrapping_neg()
+    }
+
+    /// Slave address and PDU of a frame, the LRC checked and cut off.
+    /// Hex digits may be upper or lower case.
+    pub fn decode(frame: &[u8]) -> Result<Vec<u8, 256>, ModbusError> {
+        let hex = frame
+            .strip_prefix(b":")
+            .and_then(|rest| rest.strip_suffix(b"\r\n"))
+            .ok_or(ModbusError::InvalidLength)?;
+        // At least slave address, function code and LRC
+        if hex.len() % 2 != 0 || hex.len() < 6 {
+            return Err(ModbusError::InvalidLength);
+        }
+
+        let mut bytes = Vec::<u8, 256>::new();
+        for pair in hex.chunks(2) {
+            let byte = digit(pair[0])
+                .zip(digit(pair[1]))
+                .map(|(high, low)| high << 4 | low)
+                .ok_or(ModbusError::InvalidLength)?;
+            bytes.push(byte).map_err(|_| ModbusError::BufferTooSmall)?;
+        }
+        let received = bytes.pop().unwrap_or(0);
+        if received != lrc(&bytes) {
+            return Err(ModbusError::InvalidCrc);
+        }
+        Ok(bytes)
+    }
+
+    /// Write a frame built by `ModbusRtu` as ASCII: its CRC is replaced by the LRC
+    pub fn encode(rtu_frame: &[u8], mut write: impl FnMut(u8)) {
+        let body = &rtu_frame[..rtu_frame.len().saturating_sub(2)];
+        write(b':');
+        for &byte in body.iter().chain(&[lrc(body)]) {
+            write(HEX[usize::from(byte >> 4)]);
+            write(HEX[usize::from(byte & 0x0F)]);
+        }
+        write(b'\r');
+        write(b'\n');
+    }
+
+    fn digit(c: u8) -> Option<u8> {
+        (c as char).to_digit(16).map(|digit| digit as u8)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -355,6 +857,227 @@ mod tests {
         assert_eq!(request.write_data.len(), 4);
     }
 
+    #[test]
+    fn test_parse_read_write_multiple_registers() {
+        let modbus = ModbusRtu::new(0x01);
+        // Read 3 registers at 0x1002 after writing 2 registers at 0x1000
+        let body = [
+            0x01, 0x17, 0x10, 0x02, 0x00, 0x03, 0x10, 0x00, 0x00, 0x02, 0x04, 0x65, 0x00, 0x00,
+            0x10,
+        ];
+        let mut frame = Vec::<u8, 32>::new();
+        frame.extend_from_slice(&body).unwrap();
+        frame
+            .extend_from_slice(&ModbusRtu::calculate_crc(&body).to_le_bytes())
+            .unwrap();
+
+        let request = modbus.parse_request(&frame).unwrap();
+        assert_eq!(
+            request.function_code,
+            FunctionCode::ReadWriteMultipleRegisters
+        );
+        assert_eq!(request.read_address, 0x1002);
+        assert_eq!(request.read_quantity, 3);
+        assert_eq!(request.start_address, 0x1000);
+        assert_eq!(request.quantity, 2);
+        assert_eq!(request.write_data, [0x65, 0x00, 0x00, 0x10]);
+
+        // Byte count past the end of the frame
+        let mut short = frame.clone();
+        short[10] = 0x08;
+        let crc = ModbusRtu::calculate_crc(&short[..short.len() - 2]);
+        let len = short.len();
+        short[len - 2..].copy_from_slice(&crc.to_le_bytes());
+        assert!(matches!(
+            modbus.parse_request(&short),
+            Err(ModbusError::InvalidLength)
+        ));
+    }
+
+    #[test]
+    fn test_parse_write_multiple_coils() {
+        let modbus = ModbusRtu::new(0x01);
+        // Coils 0-9: 1,0,1,1,0,0,1,1 1,0
+        let body = [0x01, 0x0F, 0x00, 0x00, 0x00, 0x0A, 0x02, 0xCD, 0x01];
+        let mut frame = Vec::<u8, 16>::new();
+        frame.extend_from_slice(&body).unwrap();
+        frame
+            .extend_from_slice(&ModbusRtu::calculate_crc(&body).to_le_bytes())
+            .unwrap();
+
+        let request = modbus.parse_request(&frame).unwrap();
+        assert_eq!(request.function_code, FunctionCode::WriteMultipleCoils);
+        assert_eq!(request.start_address, 0x0000);
+        assert_eq!(request.quantity, 10);
+        assert_eq!(request.write_data, [0xCD, 0x01]);
+    }
+
+    #[test]
+    fn test_pack_bits() {
+        // LSB first, padded with zeros to a whole byte
+        assert_eq!(pack_bits(0b1011, 0, 4), [0x0B]);
+        assert_eq!(pack_bits(0b1011, 1, 3), [0x05]);
+        assert_eq!(pack_bits(0b1011, 3, 1), [0x01]);
+        assert_eq!(pack_bits(0x0001_ACDC, 2, 16), [0x37, 0x6B]);
+        assert_eq!(pack_bits(0x8000_0001, 0, 9), [0x01, 0x00]);
+        assert_eq!(pack_bits(u32::MAX, 0, 32), [0xFF; 4]);
+    }
+
+    #[test]
+    fn test_unpack_bits() {
+        assert_eq!(unpack_bits(&[0xCD, 0x01], 0, 10), Some(0x1CD));
+        // Padding bits past `quantity` are ignored
+        assert_eq!(unpack_bits(&[0xFF], 1, 2), Some(0b110));
+        assert_eq!(unpack_bits(&[0x01], 31, 1), Some(0x8000_0000));
+        for bits in [0_u32, 0b1, 0b1010, 0x3FF] {
+            let packed = pack_bits(bits << 3, 3, 10);
+            assert_eq!(unpack_bits(&packed, 3, 10), Some(bits << 3));
+        }
+
+        // Byte count must match the quantity
+        assert_eq!(unpack_bits(&[0xCD], 0, 10), None);
+        assert_eq!(unpack_bits(&[0xCD, 0x01, 0x00], 0, 10), None);
+        assert_eq!(unpack_bits(&[], 0, 0), None);
+        assert_eq!(unpack_bits(&[0x01], 32, 1), None);
+    }
+
+    #[test]
+    fn test_parse_read_device_identification() {
+        let modbus = ModbusRtu::new(0x01);
+        let body = [0x01, 0x2B, 0x0E, 0x04, 0x81];
+        let mut frame = Vec::<u8, 8>::new();
+        frame.extend_from_slice(&body).unwrap();
+        frame
+            .extend_from_slice(&ModbusRtu::calculate_crc(&body).to_le_bytes())
+            .unwrap();
+
+        let request = modbus.parse_request(&frame).unwrap();
+        assert_eq!(request.function_code, FunctionCode::EncapsulatedInterface);
+        assert_eq!(request.mei_type, 0x0E);
+        assert_eq!(request.read_device_id, 0x04);
+        assert_eq!(request.object_id, 0x81);
+
+        // Seven bytes are only enough for 0x2B and 0x0B
+        let body = [0x01, 0x03, 0x00, 0x00, 0x01];
+        let mut frame = Vec::<u8, 8>::new();
+        frame.extend_from_slice(&body).unwrap();
+        frame
+            .extend_from_slice(&ModbusRtu::calculate_crc(&body).to_le_bytes())
+            .unwrap();
+        assert!(matches!(
+            modbus.parse_request(&frame),
+            Err(ModbusError::InvalidLength)
+        ));
+    }
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let modbus = ModbusRtu::new(0x01);
+        let frame = |body: &[u8]| {
+            let mut frame = Vec::<u8, 16>::new();
+            frame.extend_from_slice(body).unwrap();
+            frame
+                .extend_from_slice(&ModbusRtu::calculate_crc(body).to_le_bytes())
+                .unwrap();
+            frame
+        };
+
+        // Return Query Data with four bytes to echo
+        let request = modbus
+            .parse_request(&frame(&[0x01, 0x08, 0x00, 0x00, 0xA5, 0x37, 0x12, 0x34]))
+            .unwrap();
+        assert_eq!(request.function_code, FunctionCode::Diagnostics);
+        assert_eq!(request.start_address, 0x0000);
+        assert_eq!(request.write_data, [0xA5, 0x37, 0x12, 0x34]);
+
+        let request = modbus.parse_request(&frame(&[0x01, 0x0B])).unwrap();
+        assert_eq!(request.function_code, FunctionCode::GetCommEventCounter);
+
+        // A bad CRC is reported before a foreign address
+        let mut corrupted = frame(&[0x02, 0x03, 0x00, 0x00, 0x00, 0x0A]);
+        corrupted[5] ^= 0x01;
+        assert!(matches!(
+            modbus.parse_request(&corrupted),
+            Err(ModbusError::InvalidCrc)
+        ));
+        assert!(matches!(
+            modbus.parse_request(&frame(&[0x02, 0x0B])),
+            Err(ModbusError::InvalidSlaveAddress)
+        ));
+    }
+
+    #[test]
+    fn test_ascii_lrc() {
+        // Example from the Modbus serial line spec
+        assert_eq!(ascii::lrc(&[0xF7, 0x03, 0x13, 0x89, 0x00, 0x0A]), 0x60);
+        assert_eq!(ascii::lrc(&[]), 0x00);
+    }
+
+    #[test]
+    fn test_parse_ascii_request() {
+        let modbus = ModbusRtu::new(0x01);
+        let request = modbus.parse_ascii_request(b":010300000002FA\r\n").unwrap();
+        assert_eq!(request.function_code, FunctionCode::ReadHoldingRegisters);
+        assert_eq!(request.start_address, 0x0000);
+        assert_eq!(request.quantity, 2);
+
+        // Lower case digits, and a write whose data is decoded too
+        let request = modbus
+            .parse_ascii_request(b":01100001000204000a0102db\r\n")
+            .unwrap();
+        assert_eq!(request.function_code, FunctionCode::WriteMultipleRegisters);
+        assert_eq!(request.write_data, [0x00, 0x0A, 0x01, 0x02]);
+
+        let parse = |frame: &[u8]| modbus.parse_ascii_request(frame);
+        assert!(matches!(
+            parse(b":010300000002FB\r\n"),
+            Err(ModbusError::InvalidCrc)
+        ));
+        assert!(matches!(
+            parse(b":020300000002F9\r\n"),
+            Err(ModbusError::InvalidSlaveAddress)
+        ));
+        for bad in [
+            &b"010300000002FA\r\n"[..],
+            b":010300000002FA",
+            b":010300000002FA\n",
+            b":01030000002FA\r\n",
+            b":01030000G002FA\r\n",
+            b":01FE\r\n",
+        ] {
+            assert!(matches!(parse(bad), Err(ModbusError::InvalidLength)));
+        }
+    }
+
+    #[test]
+    fn test_ascii_encode() {
+        let modbus = ModbusRtu::new(0x01);
+        let mut data = Vec::new();
+        data.extend_from_slice(&[0x04, 0x12, 0x34, 0xAB, 0xCD])
+            .unwrap();
+        let frame = modbus
+            .build_response(&ModbusResponse {
+                slave_address: 0x01,
+                function_code: 0x03,
+                data,
+            })
+            .unwrap();
+
+        let mut ascii = std::vec::Vec::new();
+        Framing::Ascii.write(&frame, |byte| ascii.push(byte));
+        assert_eq!(ascii, b":0103041234ABCD3A\r\n");
+        // What is sent decodes back to the frame without its CRC
+        assert_eq!(ascii::decode(&ascii).unwrap(), frame[..frame.len() - 2]);
+
+        let mut rtu = std::vec::Vec::new();
+        Framing::Rtu.write(&frame, |byte| rtu.push(byte));
+        assert_eq!(rtu, frame[..]);
+
+        assert_eq!(Framing::from_mode(0), Framing::Rtu);
+        assert_eq!(Framing::from_mode(1), Framing::Ascii);
+        assert_eq!(Framing::from_mode(7), Framing::Rtu);
+    }
+
     #[test]
     fn test_parse_read_input_registers() {
         let modbus = ModbusRtu::new(0x01);
@@ -388,7 +1111,7 @@ mod tests {
     #[test]
     fn test_parse_wrong_slave_address() {
         let modbus = ModbusRtu::new(0x01);
-        let frame = [0x02, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC4, 0x1E];
+        let frame = [0x02, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xFE];
 
         let result = modbus.parse_request(&frame);
         assert!(matches!(result, Err(ModbusError::InvalidSlaveAddress)));
@@ -452,4 +1175,130 @@ mod tests {
         );
         assert_eq!(FunctionCode::from_u8(0xFF), None);
     }
+
+    fn rtu_frame(pdu: &[u8]) -> Vec<u8, 256> {
+        let mut frame: Vec<u8, 256> = Vec::from_slice(pdu).unwrap();
+        let crc = ModbusRtu::calculate_crc(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes()).unwrap();
+        frame
+    }
+
+    /// Feed `bytes` without a pause, returning what each byte completed
+    fn feed(rx: &mut Receiver, bytes: &[u8]) -> Vec<Received, 256> {
+        bytes.iter().map(|&byte| rx.push(byte)).collect()
+    }
+
+    #[test]
+    fn test_receiver_device_identification() {
+        // '+' is printable, the request must still not become a shell line
+        let mut rx = Receiver::new(Framing::Rtu);
+        let request = rtu_frame(&[0x01, 0x2B, 0x0E, 0x01, 0x00]);
+        assert_eq!(request.len(), 7);
+        let got = feed(&mut rx, &request);
+        assert_eq!(got[6], Received::Frame);
+        assert!(got[..6].iter().all(|&r| r == Received::Nothing));
+        assert_eq!(rx.take_frame().unwrap().as_slice(), request.as_slice());
+        assert!(rx.take_line().is_none());
+        assert!(rx.take_frame().is_none());
+    }
+
+    #[test]
+    fn test_receiver_rtu_lengths() {
+        let mut rx = Receiver::new(Framing::Rtu);
+        for pdu in [
+            &[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A][..],
+            &[0x01, 0x08, 0x00, 0x01, 0x00, 0x00],
+            &[0x01, 0x10, 0x00, 0x10, 0x00, 0x02, 0x04, 1, 2, 3, 4],
+            &[0x01, 0x0F, 0x00, 0x00, 0x00, 0x03, 0x01, 0x05],
+            &[0x01, 0x17, 0, 0, 0, 1, 0, 0x10, 0, 1, 2, 0xAB, 0xCD],
+        ] {
+            let request = rtu_frame(pdu);
+            let got = feed(&mut rx, &request);
+            assert_eq!(got.last(), Some(&Received::Frame), "{:02X?}", pdu);
+            assert_eq!(rx.take_frame().unwrap().as_slice(), request.as_slice());
+        }
+        // A printable slave address and function code are still RTU
+        let request = rtu_frame(&[b'A', 0x03, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(feed(&mut rx, &request).last(), Some(&Received::Frame));
+        assert_eq!(rx.take_frame().unwrap().as_slice(), request.as_slice());
+    }
+
+    #[test]
+    fn test_receiver_rtu_silence() {
+        // An unknown function code only ends at silence
+        let mut rx = Receiver::new(Framing::Rtu);
+        let request = rtu_frame(&[0x01, 0x41, 0x00]);
+        assert!(feed(&mut rx, &request)
+            .iter()
+            .all(|&r| r == Received::Nothing));
+        assert!(rx.receiving());
+        assert_eq!(rx.silence(1), Received::Nothing);
+        assert_eq!(rx.silence(Receiver::RTU_SILENCE_MS), Received::Frame);
+        assert!(!rx.receiving());
+        // Bytes are dropped until the request is taken
+        assert_eq!(rx.push(0x01), Received::Nothing);
+        assert_eq!(rx.take_frame().unwrap().as_slice(), request.as_slice());
+
+        // A lone binary byte is noise
+        rx.push(0xFF);
+        assert_eq!(rx.silence(Receiver::RTU_SILENCE_MS), Received::Nothing);
+        assert!(!rx.receiving());
+        assert!(rx.take_frame().is_none());
+    }
+
+    #[test]
+    fn test_receiver_shell_lines() {
+        let mut rx = Receiver::new(Framing::Rtu);
+        let got = feed(&mut rx, b"status\r\n");
+        assert_eq!(got[6], Received::Line);
+        assert!(got.iter().filter(|&&r| r == Received::Line).count() == 1);
+        assert_eq!(rx.take_line().unwrap().as_slice(), b"status");
+        assert!(rx.take_frame().is_none());
+
+        // Typed one character at a time
+        for &byte in b"help" {
+            rx.push(byte);
+            rx.silence(200);
+        }
+        assert_eq!(rx.push(b'\r'), Received::Line);
+        assert_eq!(rx.take_line().unwrap().as_slice(), b"help");
+
+        // Pasted lines are queued
+        feed(&mut rx, b"config load\nname = x\nend\n");
+        assert_eq!(rx.take_line().unwrap().as_slice(), b"config load");
+        assert_eq!(rx.take_line().unwrap().as_slice(), b"name = x");
+        assert_eq!(rx.take_line().unwrap().as_slice(), b"end");
+        assert!(!rx.has_line());
+        assert!(rx.take_line().is_none());
+
+        // A request right after a line still frames
+        let request = rtu_frame(&[0x01, 0x04, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(feed(&mut rx, &request).last(), Some(&Received::Frame));
+        assert_eq!(rx.take_frame().unwrap().as_slice(), request.as_slice());
+    }
+
+    #[test]
+    fn test_receiver_ascii_framing() {
+        let mut rx = Receiver::new(Framing::Ascii);
+        let got = feed(&mut rx, b":010300000001FB\r\n");
+        assert_eq!(got.last(), Some(&Received::Frame));
+        assert_eq!(rx.take_frame().unwrap().as_slice(), b":010300000001FB\r\n");
+        assert_eq!(feed(&mut rx, b"version\r").last(), Some(&Received::Line));
+        assert_eq!(rx.take_line().unwrap().as_slice(), b"version");
+
+        // A partial request times out
+        feed(&mut rx, b":0103");
+        assert!(rx.receiving());
+        assert_eq!(rx.silence(ascii::CHAR_TIMEOUT_MS - 1), Received::Nothing);
+        assert!(rx.receiving());
+        rx.silence(ascii::CHAR_TIMEOUT_MS);
+        assert!(!rx.receiving());
+        assert!(rx.take_frame().is_none());
+
+        // Switching framing drops a partial request
+        feed(&mut rx, b":0103");
+        rx.set_framing(Framing::Rtu);
+        assert!(!rx.receiving());
+        assert_eq!(rx.framing(), Framing::Rtu);
+    }
 }
@@ -4,13 +4,18 @@
 
 #![allow(dead_code)]
 
-use crate::history::RingStorage;
+use crate::apps::{AppRequest, MeterStatus, FIRMWARE_VERSION};
+use crate::audit::{AuditLog, AuditRecord, Source};
+use crate::events::{Event, EventLog};
+use crate::history::{Bucketing, Entry, FlowStats, Record, RingStorage, Status};
 use crate::modbus::{
-    ExceptionCode, FunctionCode, ModbusError, ModbusRequest, ModbusResponse, ModbusRtu,
+    pack_bits, unpack_bits, ExceptionCode, Framing, FunctionCode, ModbusError, ModbusRequest,
+    ModbusResponse, ModbusRtu,
 };
-use crate::options::Options;
+use crate::options::{Keep, Options};
+use core::fmt::Write;
 use embedded_storage::Storage;
-use heapless::Vec;
+use heapless::{String, Vec};
 
 /// Register address ranges
 pub mod registers {
@@ -18,21 +23,230 @@ pub mod registers {
     pub const OPTIONS_START: u16 = 0x0000;
     pub const OPTIONS_END: u16 = 0x001F;
 
-    /// Current flow data (100-103): 4 registers = 8 bytes  
+    /// Current flow data (100-109): 5 registers = 10 bytes  
     pub const FLOW_RATE: u16 = 0x0064; // f32
     pub const HOUR_FLOW: u16 = 0x0066; // f32
     pub const DAY_FLOW: u16 = 0x0068; // f32
     pub const MONTH_FLOW: u16 = 0x006A; // f32
+    /// Flow since the last Reset Totals coil
+    pub const REST_TOTAL: u16 = 0x006C; // f32
+    pub const FLOW_END: u16 = 0x006D;
+
+    /// Restore factory defaults (FC 0x06 only). The value must be
+    /// `RESTORE_KEY`, or-ed with the flags of what else to reset;
+    /// serial number and calibration are kept otherwise.
+    pub const RESTORE_DEFAULTS: u16 = 0x0070;
+    pub const RESTORE_KEY: u16 = 0xDF00;
+    pub const RESTORE_CALIBRATION: u16 = 0x0001;
+    pub const RESTORE_SERIAL: u16 = 0x0002;
+
+    /// Coils (FC 0x01 / 0x05 / 0x0F): commands, run once when written 1.
+    /// A coil reads 1 until its command has been taken.
+    pub const COIL_RESET_TOTALS: u16 = 0;
+    pub const COIL_SAVE_OPTIONS: u16 = 1;
+    pub const COIL_SYSTEM_RESET: u16 = 2;
+    /// Measure the zero offsets of both channels; discrete input
+    /// `INPUT_CALIBRATING` reads 1 until they are saved
+    pub const COIL_AUTO_ZERO: u16 = 3;
+    pub const COIL_COUNT: u16 = 4;
+
+    /// Discrete inputs (FC 0x02): `MeterStatus` bits
+    pub const INPUT_TDC_ERROR: u16 = 0;
+    pub const INPUT_REVERSE_FLOW: u16 = 1;
+    pub const INPUT_LOW_BATTERY: u16 = 2;
+    pub const INPUT_OPTIONS_FALLBACK: u16 = 3;
+    pub const INPUT_CALIBRATING: u16 = 4;
+    pub const INPUT_COUNT: u16 = 5;
+
+    /// Request a coil runs
+    pub fn coil_request(coil: u16) -> Option<super::AppRequest> {
+        use super::AppRequest;
+        match coil {
+            COIL_RESET_TOTALS => Some(AppRequest::ResetTotals),
+            COIL_SAVE_OPTIONS => Some(AppRequest::SaveOptions),
+            COIL_SYSTEM_RESET => Some(AppRequest::SystemReset),
+            COIL_AUTO_ZERO => Some(AppRequest::AutoZero),
+            _ => None,
+        }
+    }
 
     /// History base addresses
     pub const HOUR_HISTORY_BASE: u16 = 0x1000;
     pub const DAY_HISTORY_BASE: u16 = 0x2000;
     pub const MONTH_HISTORY_BASE: u16 = 0x3000;
+    pub const PROFILE_HISTORY_BASE: u16 = 0x4000;
+
+    /// History window layout (offsets from each history base)
+    pub const HISTORY_SELECT_TIME: u16 = 0x0000; // u32, R/W
+    pub const HISTORY_SELECT_INDEX: u16 = 0x0002; // u16, R/W (0 = oldest record)
+    pub const HISTORY_STATUS: u16 = 0x0003; // u16, R
+    pub const HISTORY_COUNT: u16 = 0x0004; // u32, R
+    pub const HISTORY_FIRST_TIME: u16 = 0x0006; // u32, R
+    pub const HISTORY_LAST_TIME: u16 = 0x0008; // u32, R
+    pub const HISTORY_VALUE: u16 = 0x000A; // f32, R
+    pub const HISTORY_WRITE_COUNT: u16 = 0x000C; // u32, R (header updates, for EEPROM wear)
+    pub const HISTORY_MIN_FLOW: u16 = 0x000E; // f32, R (NaN if not kept)
+    pub const HISTORY_MIN_TIME: u16 = 0x0010; // u32, R
+    pub const HISTORY_MAX_FLOW: u16 = 0x0012; // f32, R (NaN if not kept)
+    pub const HISTORY_MAX_TIME: u16 = 0x0014; // u32, R
+    pub const HISTORY_WINDOW_LEN: u16 = 0x0016;
+
+    /// Record status values (HISTORY_STATUS)
+    pub const HISTORY_STATUS_OK: u16 = 0;
+    pub const HISTORY_STATUS_NO_RECORD: u16 = 1;
+    pub const HISTORY_STATUS_GAP_FILLED: u16 = 2;
+    pub const HISTORY_STATUS_ESTIMATED: u16 = 3;
+    pub const HISTORY_STATUS_CLOCK_ADJUSTED: u16 = 4;
+
+    /// Event log window: select an event by index, then read it back
+    pub const EVENT_LOG_BASE: u16 = 0x5000;
+    pub const EVENT_SELECT_INDEX: u16 = 0x0000; // u16, R/W (0 = newest event)
+    pub const EVENT_COUNT: u16 = 0x0001; // u16, R (events held)
+    pub const EVENT_SEQUENCE: u16 = 0x0002; // u32, R (events logged in total)
+    pub const EVENT_TIME: u16 = 0x0004; // u32, R
+    pub const EVENT_CODE: u16 = 0x0006; // u16, R (0 = no event)
+    pub const EVENT_PARAM: u16 = 0x0007; // u32, R
+    pub const EVENT_WINDOW_LEN: u16 = 0x0009;
+
+    /// Offset inside the event log window
+    pub fn event_window(address: u16) -> Option<u16> {
+        (EVENT_LOG_BASE..EVENT_LOG_BASE + EVENT_WINDOW_LEN)
+            .contains(&address)
+            .then(|| address - EVENT_LOG_BASE)
+    }
+
+    /// Audit trail window: select a change by index, then read it back
+    pub const AUDIT_LOG_BASE: u16 = 0x6000;
+    pub const AUDIT_SELECT_INDEX: u16 = 0x0000; // u16, R/W (0 = newest change)
+    pub const AUDIT_REMAINING: u16 = 0x0001; // u16, R (changes left before writes are refused)
+    pub const AUDIT_COUNTER: u16 = 0x0002; // u32, R (changes recorded)
+    pub const AUDIT_TIME: u16 = 0x0004; // u32, R
+    pub const AUDIT_FIELD: u16 = 0x0006; // u16, R (0 = no record)
+    pub const AUDIT_SOURCE: u16 = 0x0007; // u16, R (1 = Modbus, 2 = shell, 3 = UI)
+    pub const AUDIT_OLD: u16 = 0x0008; // u32, R (raw field value)
+    pub const AUDIT_NEW: u16 = 0x000A; // u32, R (raw field value)
+    pub const AUDIT_WINDOW_LEN: u16 = 0x000C;
+
+    /// Offset inside the audit trail window
+    pub fn audit_window(address: u16) -> Option<u16> {
+        (AUDIT_LOG_BASE..AUDIT_LOG_BASE + AUDIT_WINDOW_LEN)
+            .contains(&address)
+            .then(|| address - AUDIT_LOG_BASE)
+    }
+
+    /// Map an address to (history index, offset inside the window).
+    /// History index: 0 = hour, 1 = day, 2 = month, 3 = load profile.
+    pub fn history_window(address: u16) -> Option<(usize, u16)> {
+        let bases = [
+            HOUR_HISTORY_BASE,
+            DAY_HISTORY_BASE,
+            MONTH_HISTORY_BASE,
+            PROFILE_HISTORY_BASE,
+        ];
+        bases.iter().enumerate().find_map(|(idx, &base)| {
+            (base..base + HISTORY_WINDOW_LEN)
+                .contains(&address)
+                .then(|| (idx, address - base))
+        })
+    }
+}
+
+/// Read Device Identification (FC 0x2B / MEI 0x0E)
+pub mod device_id {
+    pub const MEI_TYPE: u8 = 0x0E;
+
+    /// Read Device ID codes: stream access to a category, or one object
+    pub const BASIC: u8 = 0x01;
+    pub const REGULAR: u8 = 0x02;
+    pub const EXTENDED: u8 = 0x03;
+    pub const INDIVIDUAL: u8 = 0x04;
+    /// Extended identification, stream and individual access
+    pub const CONFORMITY_LEVEL: u8 = 0x83;
+
+    /// Basic objects (0x00-0x02)
+    pub const VENDOR_NAME: u8 = 0x00;
+    pub const PRODUCT_CODE: u8 = 0x01;
+    pub const MAJOR_MINOR_REVISION: u8 = 0x02;
+    /// Regular objects (0x03-0x7F)
+    pub const PRODUCT_NAME: u8 = 0x04;
+    /// Extended objects (0x80-0xFF)
+    pub const SERIAL_NUMBER: u8 = 0x80;
+    pub const SENSOR_TYPE: u8 = 0x81;
+
+    /// Every object, in id order
+    pub const OBJECTS: [u8; 6] = [
+        VENDOR_NAME,
+        PRODUCT_CODE,
+        MAJOR_MINOR_REVISION,
+        PRODUCT_NAME,
+        SERIAL_NUMBER,
+        SENSOR_TYPE,
+    ];
+
+    pub const VENDOR: &str = "ELK";
+    pub const PRODUCT: &str = "uFlowmeter";
+    pub const PRODUCT_DESCRIPTION: &str = "Ultrasonic flow meter";
+    /// `sensor_type` names, in `FACTORY_DEFAULTS` order
+    pub const SENSOR_TYPES: [&str; 5] = ["DN40", "DN50", "DN65", "DN80", "DN100"];
+
+    /// Last object id of the category a stream access code reads
+    pub fn last_object(code: u8) -> u8 {
+        match code {
+            BASIC => 0x02,
+            REGULAR => 0x7F,
+            _ => 0xFF,
+        }
+    }
+}
+
+/// Diagnostics sub-functions (FC 0x08)
+pub mod diagnostics {
+    /// Echo the request data
+    pub const RETURN_QUERY_DATA: u16 = 0x0000;
+    pub const CLEAR_COUNTERS: u16 = 0x000A;
+    /// Counters, read with data 0x0000
+    pub const BUS_MESSAGE_COUNT: u16 = 0x000B;
+    pub const BUS_COMMUNICATION_ERROR_COUNT: u16 = 0x000C;
+    pub const BUS_EXCEPTION_ERROR_COUNT: u16 = 0x000D;
+    pub const SERVER_MESSAGE_COUNT: u16 = 0x000E;
+}
+
+/// Communication counters since power-up or the last clear, wrapping at 16 bits
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CommCounters {
+    /// Frames seen on the bus, for any address and with bad CRCs too
+    pub bus_messages: u16,
+    /// Frames with a bad CRC, or too short for their function
+    pub crc_errors: u16,
+    /// Exception responses sent
+    pub exceptions: u16,
+    /// Frames addressed to this meter, broadcasts included
+    pub slave_messages: u16,
+    /// Requests completed without an exception, as 0x0B reports them;
+    /// 0x0B itself is not counted
+    pub events: u16,
 }
 
 /// Modbus slave handler
 pub struct ModbusHandler {
     modbus: ModbusRtu,
+    /// How requests arrive; responses are built as RTU and sent with `Framing::write`
+    framing: Framing,
+    /// Selected record timestamp per history window (hour, day, month, profile)
+    history_select: [u32; 4],
+    /// Selected event, 0 = newest
+    event_select: u32,
+    /// Selected audit record, 0 = newest
+    audit_select: u32,
+    /// First register of the last Options write not yet taken
+    config_written: Option<u16>,
+    /// Coils written 1 whose commands were not taken yet, bit n = coil n
+    coils: u32,
+    /// Discrete inputs as of the last `set_status`
+    status: MeterStatus,
+    /// `REST_TOTAL` as of the last `set_rest_total`
+    rest_total: f32,
+    counters: CommCounters,
 }
 
 impl ModbusHandler {
@@ -40,13 +254,60 @@ impl ModbusHandler {
     pub fn new(slave_address: u8) -> Self {
         Self {
             modbus: ModbusRtu::new(slave_address),
+            framing: Framing::Rtu,
+            history_select: [0; 4],
+            event_select: 0,
+            audit_select: 0,
+            config_written: None,
+            coils: 0,
+            status: MeterStatus::empty(),
+            rest_total: 0.0,
+            counters: CommCounters::default(),
         }
     }
 
+    /// Framing of the requests, follows `Options::modbus_mode`
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Select RTU or ASCII framing
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// First register of the last Options write since the previous call
+    pub fn take_config_write(&mut self) -> Option<u16> {
+        self.config_written.take()
+    }
+
+    /// Communication counters, also read by 0x08 / 0x0B
+    pub fn counters(&self) -> CommCounters {
+        self.counters
+    }
+
+    /// Status answered on the discrete inputs
+    pub fn set_status(&mut self, status: MeterStatus) {
+        self.status = status;
+    }
+
+    /// Resettable total answered at `REST_TOTAL`
+    pub fn set_rest_total(&mut self, rest_total: f32) {
+        self.rest_total = rest_total;
+    }
+
+    /// Requests of the coils written since the previous call, in coil order
+    pub fn take_commands(&mut self) -> impl Iterator<Item = AppRequest> {
+        let coils = core::mem::take(&mut self.coils);
+        (0..registers::COIL_COUNT)
+            .filter(move |coil| coils & 1 << coil != 0)
+            .filter_map(registers::coil_request)
+    }
+
     /// Process Modbus request and generate response
     #[allow(clippy::too_many_arguments)]
     pub fn handle_request<S, E>(
-        &self,
+        &mut self,
         frame: &[u8],
         options: &mut Options,
         storage: &mut S,
@@ -54,67 +315,231 @@ impl ModbusHandler {
         hour_flow: f32,
         day_flow: f32,
         month_flow: f32,
-        _hour_history: &mut dyn HistoryAccess<S, E>,
-        _day_history: &mut dyn HistoryAccess<S, E>,
-        _month_history: &mut dyn HistoryAccess<S, E>,
+        hour_history: &mut dyn HistoryAccess<S, E>,
+        day_history: &mut dyn HistoryAccess<S, E>,
+        month_history: &mut dyn HistoryAccess<S, E>,
+        profile_history: &mut dyn HistoryAccess<S, E>,
+        events: &mut dyn EventAccess<S>,
+        audit: &mut dyn AuditAccess<S>,
     ) -> Result<Vec<u8, 256>, ModbusError>
     where
         S: Storage,
         crate::options::Error<E>: From<S::Error>,
     {
+        let counters = &mut self.counters;
+        counters.bus_messages = counters.bus_messages.wrapping_add(1);
+
         // Parse request
-        let request = match self.modbus.parse_request(frame) {
+        let parsed = match self.framing {
+            Framing::Rtu => self.modbus.parse_request(frame),
+            Framing::Ascii => self.modbus.parse_ascii_request(frame),
+        };
+        let request = match parsed {
             Ok(req) => req,
             Err(ModbusError::InvalidSlaveAddress) => {
                 // Not for us, ignore
                 return Err(ModbusError::InvalidSlaveAddress);
             }
-            Err(e) => return Err(e),
+            Err(e @ (ModbusError::InvalidCrc | ModbusError::InvalidLength)) => {
+                counters.crc_errors = counters.crc_errors.wrapping_add(1);
+                return Err(e);
+            }
+            Err(e) => {
+                counters.slave_messages = counters.slave_messages.wrapping_add(1);
+                return Err(e);
+            }
         };
+        counters.slave_messages = counters.slave_messages.wrapping_add(1);
+
+        let mut histories: [&mut dyn HistoryAccess<S, E>; 4] =
+            [hour_history, day_history, month_history, profile_history];
 
         // Handle request
-        match request.function_code {
+        let response = match request.function_code {
+            FunctionCode::Diagnostics => self.handle_diagnostics(&request),
+            FunctionCode::GetCommEventCounter => self.handle_get_comm_event_counter(&request),
+            FunctionCode::ReadCoils | FunctionCode::ReadDiscreteInputs => {
+                self.handle_read_bits(&request)
+            }
+            FunctionCode::WriteSingleCoil => self.handle_write_single_coil(&request),
+            FunctionCode::WriteMultipleCoils => self.handle_write_multiple_coils(&request),
+            FunctionCode::EncapsulatedInterface => {
+                self.handle_read_device_identification(&request, options)
+            }
             FunctionCode::ReadHoldingRegisters => self.handle_read_holding_registers(
-                &request, options, flow_rate, hour_flow, day_flow, month_flow,
+                &request,
+                options,
+                storage,
+                flow_rate,
+                hour_flow,
+                day_flow,
+                month_flow,
+                &mut histories,
+                events,
+                audit,
             ),
             FunctionCode::ReadInputRegisters => self
                 .handle_read_input_registers(&request, flow_rate, hour_flow, day_flow, month_flow),
-            FunctionCode::WriteSingleRegister => {
-                self.handle_write_single_register(&request, options, storage)
-            }
-            FunctionCode::WriteMultipleRegisters => {
-                self.handle_write_multiple_registers(&request, options, storage)
+            FunctionCode::WriteSingleRegister => self.handle_write_single_register(
+                &request,
+                options,
+                storage,
+                &mut histories,
+                events,
+                audit,
+            ),
+            FunctionCode::WriteMultipleRegisters => self.handle_write_multiple_registers(
+                &request,
+                options,
+                storage,
+                &mut histories,
+                events,
+                audit,
+            ),
+            FunctionCode::ReadWriteMultipleRegisters => self.handle_read_write_multiple_registers(
+                &request,
+                options,
+                storage,
+                flow_rate,
+                hour_flow,
+                day_flow,
+                month_flow,
+                &mut histories,
+                events,
+                audit,
+            ),
+        };
+
+        if let Ok(response) = &response {
+            let counters = &mut self.counters;
+            if response[1] & 0x80 != 0 {
+                counters.exceptions = counters.exceptions.wrapping_add(1);
+            } else if request.function_code != FunctionCode::GetCommEventCounter {
+                counters.events = counters.events.wrapping_add(1);
             }
+        }
+        response
+    }
+
+    /// Handle Diagnostics (0x08): echo, counters and clearing them
+    fn handle_diagnostics(&mut self, request: &ModbusRequest) -> Result<Vec<u8, 256>, ModbusError> {
+        use diagnostics::*;
+
+        let sub_function = request.start_address;
+        let counter = match sub_function {
+            RETURN_QUERY_DATA | CLEAR_COUNTERS => None,
+            BUS_MESSAGE_COUNT => Some(self.counters.bus_messages),
+            BUS_COMMUNICATION_ERROR_COUNT => Some(self.counters.crc_errors),
+            BUS_EXCEPTION_ERROR_COUNT => Some(self.counters.exceptions),
+            SERVER_MESSAGE_COUNT => Some(self.counters.slave_messages),
             _ => {
-                // Unsupported function
-                self.modbus.build_exception(
+                return self.modbus.build_exception(
                     request.slave_address,
                     request.function_code as u8,
                     ExceptionCode::IllegalFunction,
                 )
             }
+        };
+        if sub_function != RETURN_QUERY_DATA && request.write_data[..] != [0x00, 0x00] {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::IllegalDataValue,
+            );
+        }
+        if sub_function == CLEAR_COUNTERS {
+            self.counters = CommCounters::default();
+        }
+
+        // Sub-function, then the counter or the request data echoed
+        let mut data = Vec::new();
+        data.extend_from_slice(&sub_function.to_be_bytes())
+            .map_err(|_| ModbusError::BufferTooSmall)?;
+        match counter {
+            Some(counter) => data.extend_from_slice(&counter.to_be_bytes()),
+            None => data.extend_from_slice(&request.write_data),
+        }
+        .map_err(|_| ModbusError::BufferTooSmall)?;
+
+        let response = ModbusResponse {
+            slave_address: request.slave_address,
+            function_code: request.function_code as u8,
+            data,
+        };
+
+        self.modbus.build_response(&response)
+    }
+
+    /// Handle Get Comm Event Counter (0x0B): status word, never busy, and
+    /// the event counter
+    fn handle_get_comm_event_counter(
+        &self,
+        request: &ModbusRequest,
+    ) -> Result<Vec<u8, 256>, ModbusError> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&0x0000_u16.to_be_bytes())
+            .map_err(|_| ModbusError::BufferTooSmall)?;
+        data.extend_from_slice(&self.counters.events.to_be_bytes())
+            .map_err(|_| ModbusError::BufferTooSmall)?;
+
+        let response = ModbusResponse {
+            slave_address: request.slave_address,
+            function_code: request.function_code as u8,
+            data,
+        };
+
+        self.modbus.build_response(&response)
+    }
+
+    /// Check that `quantity` holding registers from `start` can be read
+    fn check_read_range(start: u16, quantity: u16) -> Result<(), ExceptionCode> {
+        if quantity == 0 || quantity > 125 {
+            return Err(ExceptionCode::IllegalDataValue);
+        }
+        let in_range = if start <= registers::OPTIONS_END {
+            let start_byte = (start - registers::OPTIONS_START) as usize * 2;
+            start_byte + quantity as usize * 2 <= core::mem::size_of::<Options>()
+        } else if (registers::FLOW_RATE..=registers::FLOW_END).contains(&start) {
+            start + quantity <= registers::FLOW_END + 1
+        } else if let Some((_, offset)) = registers::history_window(start) {
+            offset + quantity <= registers::HISTORY_WINDOW_LEN
+        } else if let Some(offset) = registers::event_window(start) {
+            offset + quantity <= registers::EVENT_WINDOW_LEN
+        } else if let Some(offset) = registers::audit_window(start) {
+            offset + quantity <= registers::AUDIT_WINDOW_LEN
+        } else {
+            false
+        };
+        if in_range {
+            Ok(())
+        } else {
+            Err(ExceptionCode::IllegalDataAddress)
         }
     }
 
     /// Handle Read Holding Registers (0x03)
-    fn handle_read_holding_registers(
+    #[allow(clippy::too_many_arguments)]
+    fn handle_read_holding_registers<S, E>(
         &self,
         request: &ModbusRequest,
         options: &Options,
+        storage: &mut S,
         flow_rate: f32,
         hour_flow: f32,
         day_flow: f32,
         month_flow: f32,
+        histories: &mut [&mut dyn HistoryAccess<S, E>; 4],
+        events: &mut dyn EventAccess<S>,
+        audit: &mut dyn AuditAccess<S>,
     ) -> Result<Vec<u8, 256>, ModbusError> {
         let start = request.start_address;
         let quantity = request.quantity;
 
-        // Check quantity
-        if quantity == 0 || quantity > 125 {
+        if let Err(code) = Self::check_read_range(start, quantity) {
             return self.modbus.build_exception(
                 request.slave_address,
                 request.function_code as u8,
-                ExceptionCode::IllegalDataValue,
+                code,
             );
         }
 
@@ -129,14 +554,6 @@ impl ModbusHandler {
             let start_byte = (start - registers::OPTIONS_START) as usize * 2;
             let end_byte = start_byte + (quantity as usize * 2);
 
-            if end_byte > options_bytes.len() {
-                return self.modbus.build_exception(
-                    request.slave_address,
-                    request.function_code as u8,
-                    ExceptionCode::IllegalDataAddress,
-                );
-            }
-
             for &byte in options_bytes
                 .iter()
                 .skip(start_byte)
@@ -145,17 +562,17 @@ impl ModbusHandler {
                 data.push(byte).map_err(|_| ModbusError::BufferTooSmall)?;
             }
         }
-        // Read current flow data (registers 100-103)
-        else if (registers::FLOW_RATE..registers::FLOW_RATE + 8).contains(&start) {
-            let mut values = Vec::<f32, 4>::new();
+        // Read current flow data (registers 100-109)
+        else if (registers::FLOW_RATE..=registers::FLOW_END).contains(&start) {
+            let mut values = Vec::<f32, 5>::new();
             values.push(flow_rate).ok();
             values.push(hour_flow).ok();
             values.push(day_flow).ok();
             values.push(month_flow).ok();
+            values.push(self.rest_total).ok();
 
-            let reg_index = (start - registers::FLOW_RATE) / 2;
             for i in 0..quantity {
-                let idx = (reg_index + i) as usize;
+                let idx = ((start + i - registers::FLOW_RATE) / 2) as usize;
                 if idx < values.len() {
                     let bytes = values[idx].to_be_bytes();
                     let offset = ((start + i - registers::FLOW_RATE) % 2) as usize * 2;
@@ -165,6 +582,60 @@ impl ModbusHandler {
                         .map_err(|_| ModbusError::BufferTooSmall)?;
                 }
             }
+        }
+        // Read history window (0x1000 / 0x2000 / 0x3000 / 0x4000)
+        else if let Some((idx, offset)) = registers::history_window(start) {
+            let window = match self.history_window_registers(storage, idx, &mut *histories[idx]) {
+                Ok(window) => window,
+                Err(_) => {
+                    return self.modbus.build_exception(
+                        request.slave_address,
+                        request.function_code as u8,
+                        ExceptionCode::ServerDeviceFailure,
+                    );
+                }
+            };
+
+            for reg in window.iter().skip(offset as usize).take(quantity as usize) {
+                data.extend_from_slice(&reg.to_be_bytes())
+                    .map_err(|_| ModbusError::BufferTooSmall)?;
+            }
+        }
+        // Read event log window (0x5000)
+        else if let Some(offset) = registers::event_window(start) {
+            let window = match self.event_window_registers(storage, events) {
+                Ok(window) => window,
+                Err(_) => {
+                    return self.modbus.build_exception(
+                        request.slave_address,
+                        request.function_code as u8,
+                        ExceptionCode::ServerDeviceFailure,
+                    );
+                }
+            };
+
+            for reg in window.iter().skip(offset as usize).take(quantity as usize) {
+                data.extend_from_slice(&reg.to_be_bytes())
+                    .map_err(|_| ModbusError::BufferTooSmall)?;
+            }
+        }
+        // Read audit trail window (0x6000)
+        else if let Some(offset) = registers::audit_window(start) {
+            let window = match self.audit_window_registers(storage, audit) {
+                Ok(window) => window,
+                Err(_) => {
+                    return self.modbus.build_exception(
+                        request.slave_address,
+                        request.function_code as u8,
+                        ExceptionCode::ServerDeviceFailure,
+                    );
+                }
+            };
+
+            for reg in window.iter().skip(offset as usize).take(quantity as usize) {
+                data.extend_from_slice(&reg.to_be_bytes())
+                    .map_err(|_| ModbusError::BufferTooSmall)?;
+            }
         } else {
             return self.modbus.build_exception(
                 request.slave_address,
@@ -182,54 +653,23 @@ impl ModbusHandler {
         self.modbus.build_response(&response)
     }
 
-    /// Handle Read Input Registers (0x04)
-    fn handle_read_input_registers(
-        &self,
-        request: &ModbusRequest,
-        flow_rate: f32,
-        hour_flow: f32,
-        day_flow: f32,
-        month_flow: f32,
-    ) -> Result<Vec<u8, 256>, ModbusError> {
-        // Input registers = read-only current values
+    /// Handle Read Coils (0x01) and Read Discrete Inputs (0x02)
+    fn handle_read_bits(&self, request: &ModbusRequest) -> Result<Vec<u8, 256>, ModbusError> {
+        let (bits, count) = match request.function_code {
+            FunctionCode::ReadCoils => (self.coils, registers::COIL_COUNT),
+            _ => (u32::from(self.status.bits()), registers::INPUT_COUNT),
+        };
         let start = request.start_address;
         let quantity = request.quantity;
 
-        if quantity == 0 || quantity > 125 {
+        if quantity == 0 || quantity > 2000 {
             return self.modbus.build_exception(
                 request.slave_address,
                 request.function_code as u8,
                 ExceptionCode::IllegalDataValue,
             );
         }
-
-        let mut data = Vec::new();
-        let byte_count = (quantity * 2) as u8;
-        data.push(byte_count)
-            .map_err(|_| ModbusError::BufferTooSmall)?;
-
-        // Registers 0-7: flow data (4 floats = 8 registers)
-        if (0..8).contains(&start) {
-            let mut values = Vec::<f32, 4>::new();
-            values.push(flow_rate).ok();
-            values.push(hour_flow).ok();
-            values.push(day_flow).ok();
-            values.push(month_flow).ok();
-
-            for i in 0..quantity {
-                let reg = start + i;
-                let float_idx = (reg / 2) as usize;
-                let byte_idx = (reg % 2) as usize;
-
-                if float_idx < values.len() {
-                    let bytes = values[float_idx].to_be_bytes();
-                    data.push(bytes[byte_idx * 2])
-                        .map_err(|_| ModbusError::BufferTooSmall)?;
-                    data.push(bytes[byte_idx * 2 + 1])
-                        .map_err(|_| ModbusError::BufferTooSmall)?;
-                }
-            }
-        } else {
+        if u32::from(start) + u32::from(quantity) > u32::from(count) {
             return self.modbus.build_exception(
                 request.slave_address,
                 request.function_code as u8,
@@ -237,6 +677,13 @@ impl ModbusHandler {
             );
         }
 
+        let packed = pack_bits(bits, start, quantity);
+        let mut data = Vec::new();
+        data.push(packed.len() as u8)
+            .map_err(|_| ModbusError::BufferTooSmall)?;
+        data.extend_from_slice(&packed)
+            .map_err(|_| ModbusError::BufferTooSmall)?;
+
         let response = ModbusResponse {
             slave_address: request.slave_address,
             function_code: request.function_code as u8,
@@ -246,58 +693,39 @@ impl ModbusHandler {
         self.modbus.build_response(&response)
     }
 
-    /// Handle Write Single Register (0x06)
-    fn handle_write_single_register<S, E>(
-        &self,
+    /// Handle Write Single Coil (0x05). 0xFF00 runs the command,
+    /// 0x0000 is accepted and does nothing.
+    fn handle_write_single_coil(
+        &mut self,
         request: &ModbusRequest,
-        options: &mut Options,
-        storage: &mut S,
-    ) -> Result<Vec<u8, 256>, ModbusError>
-    where
-        S: Storage,
-        crate::options::Error<E>: From<S::Error>,
-    {
+    ) -> Result<Vec<u8, 256>, ModbusError> {
         let address = request.start_address;
+        let value = u16::from_be_bytes([request.write_data[0], request.write_data[1]]);
 
-        // Only allow writes to Options registers
-        if address > registers::OPTIONS_END {
-            return self.modbus.build_exception(
-                request.slave_address,
-                request.function_code as u8,
-                ExceptionCode::IllegalDataAddress,
-            );
-        }
-
-        if request.write_data.len() != 2 {
+        if value != 0xFF00 && value != 0x0000 {
             return self.modbus.build_exception(
                 request.slave_address,
                 request.function_code as u8,
                 ExceptionCode::IllegalDataValue,
             );
         }
-
-        // Modify options
-        let mut options_bytes = options.into_bytes().to_vec();
-        let byte_offset = ((address - registers::OPTIONS_START) * 2) as usize;
-        options_bytes[byte_offset] = request.write_data[0];
-        options_bytes[byte_offset + 1] = request.write_data[1];
-
-        // Update options
-        *options = Options::from_bytes(options_bytes.as_slice().try_into().unwrap());
-
-        // Save to storage
-        if options.save(storage).is_err() {
+        if address >= registers::COIL_COUNT {
             return self.modbus.build_exception(
                 request.slave_address,
                 request.function_code as u8,
-                ExceptionCode::ServerDeviceFailure,
+                ExceptionCode::IllegalDataAddress,
             );
         }
+        if value == 0xFF00 {
+            self.coils |= 1 << address;
+        }
 
-        // Echo back the request as response
+        // Echo request
         let mut data = Vec::new();
-        data.extend_from_slice(&address.to_be_bytes()).ok();
-        data.extend_from_slice(&request.write_data).ok();
+        data.extend_from_slice(&address.to_be_bytes())
+            .map_err(|_| ModbusError::BufferTooSmall)?;
+        data.extend_from_slice(&value.to_be_bytes())
+            .map_err(|_| ModbusError::BufferTooSmall)?;
 
         let response = ModbusResponse {
             slave_address: request.slave_address,
@@ -308,60 +736,115 @@ impl ModbusHandler {
         self.modbus.build_response(&response)
     }
 
-    /// Handle Write Multiple Registers (0x10)
-    fn handle_write_multiple_registers<S, E>(
-        &self,
+    /// Handle Write Multiple Coils (0x0F). Coils written 0 are left as they are.
+    fn handle_write_multiple_coils(
+        &mut self,
         request: &ModbusRequest,
-        options: &mut Options,
-        storage: &mut S,
-    ) -> Result<Vec<u8, 256>, ModbusError>
-    where
-        S: Storage,
-        crate::options::Error<E>: From<S::Error>,
-    {
+    ) -> Result<Vec<u8, 256>, ModbusError> {
         let start = request.start_address;
         let quantity = request.quantity;
 
-        // Only allow writes to Options registers
-        if start + quantity - 1 > registers::OPTIONS_END {
+        if quantity == 0
+            || quantity > 0x07B0
+            || request.write_data.len() != usize::from(quantity).div_ceil(8)
+        {
             return self.modbus.build_exception(
                 request.slave_address,
                 request.function_code as u8,
-                ExceptionCode::IllegalDataAddress,
+                ExceptionCode::IllegalDataValue,
             );
         }
-
-        if request.write_data.len() != (quantity * 2) as usize {
+        if u32::from(start) + u32::from(quantity) > u32::from(registers::COIL_COUNT) {
             return self.modbus.build_exception(
                 request.slave_address,
                 request.function_code as u8,
-                ExceptionCode::IllegalDataValue,
+                ExceptionCode::IllegalDataAddress,
             );
         }
+        // Length and range are checked above
+        self.coils |= unpack_bits(&request.write_data, start, quantity).unwrap_or(0);
 
-        // Modify options
-        let mut options_bytes = options.into_bytes().to_vec();
-        let start_byte = ((start - registers::OPTIONS_START) * 2) as usize;
-        for (offset, &byte) in request.write_data.iter().enumerate() {
-            options_bytes[start_byte + offset] = byte;
-        }
+        let mut data = Vec::new();
+        data.extend_from_slice(&start.to_be_bytes())
+            .map_err(|_| ModbusError::BufferTooSmall)?;
+        data.extend_from_slice(&quantity.to_be_bytes())
+            .map_err(|_| ModbusError::BufferTooSmall)?;
 
-        // Update options
-        *options = Options::from_bytes(options_bytes.as_slice().try_into().unwrap());
+        let response = ModbusResponse {
+            slave_address: request.slave_address,
+            function_code: request.function_code as u8,
+            data,
+        };
 
-        // Save to storage
-        if options.save(storage).is_err() {
+        self.modbus.build_response(&response)
+    }
+
+    /// Handle Read Device Identification (0x2B / MEI 0x0E).
+    /// Stream access starts at `object_id`, or at the first object of the
+    /// category if there is no such object, and goes on in id order.
+    fn handle_read_device_identification(
+        &self,
+        request: &ModbusRequest,
+        options: &Options,
+    ) -> Result<Vec<u8, 256>, ModbusError> {
+        use device_id::*;
+
+        if request.mei_type != MEI_TYPE {
             return self.modbus.build_exception(
                 request.slave_address,
                 request.function_code as u8,
-                ExceptionCode::ServerDeviceFailure,
+                ExceptionCode::IllegalFunction,
             );
         }
+        let code = request.read_device_id;
+        let found = OBJECTS.iter().position(|&id| id == request.object_id);
+        let objects = match code {
+            BASIC | REGULAR | EXTENDED => {
+                let end = OBJECTS
+                    .iter()
+                    .take_while(|&&id| id <= last_object(code))
+                    .count();
+                let start = found.filter(|&index| index < end).unwrap_or(0);
+                &OBJECTS[start..end]
+            }
+            INDIVIDUAL => match found {
+                Some(index) => &OBJECTS[index..=index],
+                None => {
+                    return self.modbus.build_exception(
+                        request.slave_address,
+                        request.function_code as u8,
+                        ExceptionCode::IllegalDataAddress,
+                    )
+                }
+            },
+            _ => {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    ExceptionCode::IllegalDataValue,
+                )
+            }
+        };
 
-        // Build response: slave + func + start_addr + quantity
+        // MEI type, code, conformity level, more follows, next object id, object count
         let mut data = Vec::new();
-        data.extend_from_slice(&start.to_be_bytes()).ok();
-        data.extend_from_slice(&quantity.to_be_bytes()).ok();
+        data.extend_from_slice(&[MEI_TYPE, code, CONFORMITY_LEVEL, 0x00, 0x00, 0x00])
+            .map_err(|_| ModbusError::BufferTooSmall)?;
+        for &id in objects {
+            let value = Self::device_id_object(options, id);
+            // Leave room for slave address, function code and CRC
+            if data.len() + 2 + value.len() > data.capacity() - 4 {
+                data[3] = 0xFF;
+                data[4] = id;
+                break;
+            }
+            data.push(id).map_err(|_| ModbusError::BufferTooSmall)?;
+            data.push(value.len() as u8)
+                .map_err(|_| ModbusError::BufferTooSmall)?;
+            data.extend_from_slice(value.as_bytes())
+                .map_err(|_| ModbusError::BufferTooSmall)?;
+            data[5] += 1;
+        }
 
         let response = ModbusResponse {
             slave_address: request.slave_address,
@@ -372,250 +855,2067 @@ impl ModbusHandler {
         self.modbus.build_response(&response)
     }
 
-    /// Get Modbus RTU instance
-    pub fn modbus(&self) -> &ModbusRtu {
-        &self.modbus
+    /// Value of a device identification object
+    fn device_id_object(options: &Options, id: u8) -> String<32> {
+        let mut value = String::new();
+        match id {
+            device_id::VENDOR_NAME => value.push_str(device_id::VENDOR).ok(),
+            device_id::PRODUCT_CODE => value.push_str(device_id::PRODUCT).ok(),
+            device_id::MAJOR_MINOR_REVISION => value.push_str(FIRMWARE_VERSION).ok(),
+            device_id::PRODUCT_NAME => value.push_str(device_id::PRODUCT_DESCRIPTION).ok(),
+            device_id::SERIAL_NUMBER => write!(value, "{}", options.serial_number()).ok(),
+            device_id::SENSOR_TYPE => {
+                match device_id::SENSOR_TYPES.get(options.sensor_type() as usize) {
+                    Some(name) => value.push_str(name).ok(),
+                    None => write!(value, "{}", options.sensor_type()).ok(),
+                }
+            }
+            _ => None,
+        };
+        value
     }
 
-    /// Get mutable Modbus RTU instance
+    /// Handle Read Input Registers (0x04)
+    fn handle_read_input_registers(
+        &self,
+        request: &ModbusRequest,
+        flow_rate: f32,
+        hour_flow: f32,
+        day_flow: f32,
+        month_flow: f32,
+    ) -> Result<Vec<u8, 256>, ModbusError> {
+        // Input registers = read-only current values
+        let start = request.start_address;
+        let quantity = request.quantity;
+
+        if quantity == 0 || quantity > 125 {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::IllegalDataValue,
+            );
+        }
+
+        let mut data = Vec::new();
+        let byte_count = (quantity * 2) as u8;
+        data.push(byte_count)
+            .map_err(|_| ModbusError::BufferTooSmall)?;
+
+        // Registers 0-9: flow data and resettable total (5 floats = 10 registers)
+        if u32::from(start) + u32::from(quantity) <= 10 {
+            let mut values = Vec::<f32, 5>::new();
+            values.push(flow_rate).ok();
+            values.push(hour_flow).ok();
+            values.push(day_flow).ok();
+            values.push(month_flow).ok();
+            values.push(self.rest_total).ok();
+
+            for i in 0..quantity {
+                let reg = start + i;
+                let float_idx = (reg / 2) as usize;
+                let byte_idx = (reg % 2) as usize;
+
+                if float_idx < values.len() {
+                    let bytes = values[float_idx].to_be_bytes();
+                    data.push(bytes[byte_idx * 2])
+                        .map_err(|_| ModbusError::BufferTooSmall)?;
+                    data.push(bytes[byte_idx * 2 + 1])
+                        .map_err(|_| ModbusError::BufferTooSmall)?;
+                }
+            }
+        } else {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::IllegalDataAddress,
+            );
+        }
+
+        let response = ModbusResponse {
+            slave_address: request.slave_address,
+            function_code: request.function_code as u8,
+            data,
+        };
+
+        self.modbus.build_response(&response)
+    }
+
+    /// Handle Write Single Register (0x06)
+    fn handle_write_single_register<S, E>(
+        &mut self,
+        request: &ModbusRequest,
+        options: &mut Options,
+        storage: &mut S,
+        histories: &mut [&mut dyn HistoryAccess<S, E>; 4],
+        events: &mut dyn EventAccess<S>,
+        audit: &mut dyn AuditAccess<S>,
+    ) -> Result<Vec<u8, 256>, ModbusError>
+    where
+        S: Storage,
+        crate::options::Error<E>: From<S::Error>,
+    {
+        let address = request.start_address;
+
+        // Audit selector register
+        if let Some(offset) = registers::audit_window(address) {
+            if request.write_data.len() != 2 {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    ExceptionCode::IllegalDataValue,
+                );
+            }
+            let value = u16::from_be_bytes([request.write_data[0], request.write_data[1]]);
+            if let Err(e) = self.write_audit_selector(offset, &[value], audit) {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    e,
+                );
+            }
+
+            let mut data = Vec::new();
+            data.extend_from_slice(&address.to_be_bytes()).ok();
+            data.extend_from_slice(&request.write_data).ok();
+
+            let response = ModbusResponse {
+                slave_address: request.slave_address,
+                function_code: request.function_code as u8,
+                data,
+            };
+
+            return self.modbus.build_response(&response);
+        }
+
+        // Event selector register
+        if let Some(offset) = registers::event_window(address) {
+            if request.write_data.len() != 2 {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    ExceptionCode::IllegalDataValue,
+                );
+            }
+            let value = u16::from_be_bytes([request.write_data[0], request.write_data[1]]);
+            if let Err(e) = self.write_event_selector(offset, &[value], events) {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    e,
+                );
+            }
+
+            let mut data = Vec::new();
+            data.extend_from_slice(&address.to_be_bytes()).ok();
+            data.extend_from_slice(&request.write_data).ok();
+
+            let response = ModbusResponse {
+                slave_address: request.slave_address,
+                function_code: request.function_code as u8,
+                data,
+            };
+
+            return self.modbus.build_response(&response);
+        }
+
+        // History selector registers
+        if let Some((idx, offset)) = registers::history_window(address) {
+            if request.write_data.len() != 2 {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    ExceptionCode::IllegalDataValue,
+                );
+            }
+            let value = u16::from_be_bytes([request.write_data[0], request.write_data[1]]);
+            if let Err(e) = self.write_history_selector(idx, offset, &[value], &mut *histories[idx])
+            {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    e,
+                );
+            }
+
+            let mut data = Vec::new();
+            data.extend_from_slice(&address.to_be_bytes()).ok();
+            data.extend_from_slice(&request.write_data).ok();
+
+            let response = ModbusResponse {
+                slave_address: request.slave_address,
+                function_code: request.function_code as u8,
+                data,
+            };
+
+            return self.modbus.build_response(&response);
+        }
+
+        // Only allow writes to Options registers
+        if address > registers::OPTIONS_END && address != registers::RESTORE_DEFAULTS {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::IllegalDataAddress,
+            );
+        }
+
+        if request.write_data.len() != 2 {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::IllegalDataValue,
+            );
+        }
+
+        let updated = if address == registers::RESTORE_DEFAULTS {
+            let value = u16::from_be_bytes([request.write_data[0], request.write_data[1]]);
+            let flags = registers::RESTORE_CALIBRATION | registers::RESTORE_SERIAL;
+            if value & !flags != registers::RESTORE_KEY {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    ExceptionCode::IllegalDataValue,
+                );
+            }
+            let mut updated = *options;
+            updated.restore_defaults(Keep {
+                serial: value & registers::RESTORE_SERIAL == 0,
+                calibration: value & registers::RESTORE_CALIBRATION == 0,
+            });
+            updated
+        } else {
+            // Modify options
+            let mut options_bytes = options.into_bytes().to_vec();
+            let byte_offset = ((address - registers::OPTIONS_START) * 2) as usize;
+            options_bytes[byte_offset] = request.write_data[0];
+            options_bytes[byte_offset + 1] = request.write_data[1];
+            Options::from_bytes(options_bytes.as_slice().try_into().unwrap())
+        };
+
+        // Record metrological changes before they take effect
+        if let Err(e) = audit.record(storage, Source::Modbus, options, &updated) {
+            let code = match e {
+                crate::history::Error::Full => ExceptionCode::IllegalDataValue,
+                _ => ExceptionCode::ServerDeviceFailure,
+            };
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                code,
+            );
+        }
+
+        // Update options
+        *options = updated;
+
+        // Save to storage
+        if options.save(storage).is_err() {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::ServerDeviceFailure,
+            );
+        }
+        self.config_written = Some(address);
+
+        // Echo back the request as response
+        let mut data = Vec::new();
+        data.extend_from_slice(&address.to_be_bytes()).ok();
+        data.extend_from_slice(&request.write_data).ok();
+
+        let response = ModbusResponse {
+            slave_address: request.slave_address,
+            function_code: request.function_code as u8,
+            data,
+        };
+
+        self.modbus.build_response(&response)
+    }
+
+    /// Handle Write Multiple Registers (0x10)
+    fn handle_write_multiple_registers<S, E>(
+        &mut self,
+        request: &ModbusRequest,
+        options: &mut Options,
+        storage: &mut S,
+        histories: &mut [&mut dyn HistoryAccess<S, E>; 4],
+        events: &mut dyn EventAccess<S>,
+        audit: &mut dyn AuditAccess<S>,
+    ) -> Result<Vec<u8, 256>, ModbusError>
+    where
+        S: Storage,
+        crate::options::Error<E>: From<S::Error>,
+    {
+        let start = request.start_address;
+        let quantity = request.quantity;
+
+        // Audit selector register
+        if let Some(offset) = registers::audit_window(start) {
+            if quantity == 0
+                || quantity > registers::AUDIT_WINDOW_LEN
+                || request.write_data.len() != (quantity * 2) as usize
+            {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    ExceptionCode::IllegalDataValue,
+                );
+            }
+            let mut values = Vec::<u16, { registers::AUDIT_WINDOW_LEN as usize }>::new();
+            for chunk in request.write_data.chunks(2) {
+                values.push(u16::from_be_bytes([chunk[0], chunk[1]])).ok();
+            }
+            if let Err(e) = self.write_audit_selector(offset, &values, audit) {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    e,
+                );
+            }
+
+            let mut data = Vec::new();
+            data.extend_from_slice(&start.to_be_bytes()).ok();
+            data.extend_from_slice(&quantity.to_be_bytes()).ok();
+
+            let response = ModbusResponse {
+                slave_address: request.slave_address,
+                function_code: request.function_code as u8,
+                data,
+            };
+
+            return self.modbus.build_response(&response);
+        }
+
+        // Event selector register
+        if let Some(offset) = registers::event_window(start) {
+            if quantity == 0
+                || quantity > registers::EVENT_WINDOW_LEN
+                || request.write_data.len() != (quantity * 2) as usize
+            {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    ExceptionCode::IllegalDataValue,
+                );
+            }
+            let mut values = Vec::<u16, { registers::EVENT_WINDOW_LEN as usize }>::new();
+            for chunk in request.write_data.chunks(2) {
+                values.push(u16::from_be_bytes([chunk[0], chunk[1]])).ok();
+            }
+            if let Err(e) = self.write_event_selector(offset, &values, events) {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    e,
+                );
+            }
+
+            let mut data = Vec::new();
+            data.extend_from_slice(&start.to_be_bytes()).ok();
+            data.extend_from_slice(&quantity.to_be_bytes()).ok();
+
+            let response = ModbusResponse {
+                slave_address: request.slave_address,
+                function_code: request.function_code as u8,
+                data,
+            };
+
+            return self.modbus.build_response(&response);
+        }
+
+        // History selector registers
+        if let Some((idx, offset)) = registers::history_window(start) {
+            if quantity == 0
+                || quantity > registers::HISTORY_WINDOW_LEN
+                || request.write_data.len() != (quantity * 2) as usize
+            {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    ExceptionCode::IllegalDataValue,
+                );
+            }
+            let mut values = Vec::<u16, { registers::HISTORY_WINDOW_LEN as usize }>::new();
+            for chunk in request.write_data.chunks(2) {
+                values.push(u16::from_be_bytes([chunk[0], chunk[1]])).ok();
+            }
+            if let Err(e) = self.write_history_selector(idx, offset, &values, &mut *histories[idx])
+            {
+                return self.modbus.build_exception(
+                    request.slave_address,
+                    request.function_code as u8,
+                    e,
+                );
+            }
+
+            let mut data = Vec::new();
+            data.extend_from_slice(&start.to_be_bytes()).ok();
+            data.extend_from_slice(&quantity.to_be_bytes()).ok();
+
+            let response = ModbusResponse {
+                slave_address: request.slave_address,
+                function_code: request.function_code as u8,
+                data,
+            };
+
+            return self.modbus.build_response(&response);
+        }
+
+        // Only allow writes to Options registers
+        if u32::from(start) + u32::from(quantity) > u32::from(registers::OPTIONS_END) + 1 {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::IllegalDataAddress,
+            );
+        }
+
+        if quantity == 0 || request.write_data.len() != (quantity * 2) as usize {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::IllegalDataValue,
+            );
+        }
+
+        // Modify options
+        let mut options_bytes = options.into_bytes().to_vec();
+        let start_byte = ((start - registers::OPTIONS_START) * 2) as usize;
+        for (offset, &byte) in request.write_data.iter().enumerate() {
+            options_bytes[start_byte + offset] = byte;
+        }
+
+        // Record metrological changes before they take effect
+        let updated = Options::from_bytes(options_bytes.as_slice().try_into().unwrap());
+        if let Err(e) = audit.record(storage, Source::Modbus, options, &updated) {
+            let code = match e {
+                crate::history::Error::Full => ExceptionCode::IllegalDataValue,
+                _ => ExceptionCode::ServerDeviceFailure,
+            };
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                code,
+            );
+        }
+
+        // Update options
+        *options = updated;
+
+        // Save to storage
+        if options.save(storage).is_err() {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::ServerDeviceFailure,
+            );
+        }
+        self.config_written = Some(start);
+
+        // Build response: slave + func + start_addr + quantity
+        let mut data = Vec::new();
+        data.extend_from_slice(&start.to_be_bytes()).ok();
+        data.extend_from_slice(&quantity.to_be_bytes()).ok();
+
+        let response = ModbusResponse {
+            slave_address: request.slave_address,
+            function_code: request.function_code as u8,
+            data,
+        };
+
+        self.modbus.build_response(&response)
+    }
+
+    /// Handle Read/Write Multiple Registers (0x17).
+    /// The write is done first, so a selector written here picks the record
+    /// read back. An exception from either part answers the whole request.
+    #[allow(clippy::too_many_arguments)]
+    fn handle_read_write_multiple_registers<S, E>(
+        &mut self,
+        request: &ModbusRequest,
+        options: &mut Options,
+        storage: &mut S,
+        flow_rate: f32,
+        hour_flow: f32,
+        day_flow: f32,
+        month_flow: f32,
+        histories: &mut [&mut dyn HistoryAccess<S, E>; 4],
+        events: &mut dyn EventAccess<S>,
+        audit: &mut dyn AuditAccess<S>,
+    ) -> Result<Vec<u8, 256>, ModbusError>
+    where
+        S: Storage,
+        crate::options::Error<E>: From<S::Error>,
+    {
+        if request.quantity == 0 || request.quantity > 0x79 {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                ExceptionCode::IllegalDataValue,
+            );
+        }
+        // Refuse a bad read before anything is written
+        if let Err(code) = Self::check_read_range(request.read_address, request.read_quantity) {
+            return self.modbus.build_exception(
+                request.slave_address,
+                request.function_code as u8,
+                code,
+            );
+        }
+
+        // Both parts answer under the 0x17 function code of `request`
+        let written = self
+            .handle_write_multiple_registers(request, options, storage, histories, events, audit)?;
+        if written[1] & 0x80 != 0 {
+            return Ok(written);
+        }
+
+        let read = ModbusRequest {
+            slave_address: request.slave_address,
+            function_code: request.function_code,
+            start_address: request.read_address,
+            quantity: request.read_quantity,
+            write_data: Vec::new(),
+            read_address: 0,
+            read_quantity: 0,
+            mei_type: 0,
+            read_device_id: 0,
+            object_id: 0,
+        };
+        self.handle_read_holding_registers(
+            &read, options, storage, flow_rate, hour_flow, day_flow, month_flow, histories, events,
+            audit,
+        )
+    }
+
+    /// Build the register image of a history window.
+    /// The selected record is read back through `HistoryAccess::find`.
+    fn history_window_registers<S, E>(
+        &self,
+        storage: &mut S,
+        idx: usize,
+        history: &mut dyn HistoryAccess<S, E>,
+    ) -> Result<[u16; registers::HISTORY_WINDOW_LEN as usize], crate::history::Error> {
+        let selected = self.history_select[idx];
+        let (status, value) = match history.find(storage, selected)? {
+            Some(entry) => {
+                let status = match entry.status {
+                    Status::Valid => registers::HISTORY_STATUS_OK,
+                    Status::GapFilled => registers::HISTORY_STATUS_GAP_FILLED,
+                    Status::Estimated => registers::HISTORY_STATUS_ESTIMATED,
+                    Status::ClockAdjusted => registers::HISTORY_STATUS_CLOCK_ADJUSTED,
+                };
+                (status, entry.value)
+            }
+            None => (registers::HISTORY_STATUS_NO_RECORD, 0.0),
+        };
+        let count = history.count();
+        let first = if count > 0 {
+            history.first_timestamp()
+        } else {
+            0
+        };
+        let last = if count > 0 {
+            history.last_timestamp()
+        } else {
+            0
+        };
+        let index = history.index_of(selected).unwrap_or(0xFFFF);
+        let stats = history
+            .find_stats(storage, selected)?
+            .filter(|stats| !stats.is_empty())
+            .unwrap_or(FlowStats {
+                min: f32::NAN,
+                min_at: 0,
+                max: f32::NAN,
+                max_at: 0,
+            });
+
+        let mut window = [0_u16; registers::HISTORY_WINDOW_LEN as usize];
+        let mut put_u32 = |offset: u16, v: u32| {
+            window[offset as usize] = (v >> 16) as u16;
+            window[offset as usize + 1] = v as u16;
+        };
+        put_u32(registers::HISTORY_SELECT_TIME, selected);
+        put_u32(registers::HISTORY_COUNT, count);
+        put_u32(registers::HISTORY_FIRST_TIME, first);
+        put_u32(registers::HISTORY_LAST_TIME, last);
+        put_u32(registers::HISTORY_VALUE, value.to_bits());
+        put_u32(registers::HISTORY_WRITE_COUNT, history.write_count());
+        put_u32(registers::HISTORY_MIN_FLOW, stats.min.to_bits());
+        put_u32(registers::HISTORY_MIN_TIME, stats.min_at);
+        put_u32(registers::HISTORY_MAX_FLOW, stats.max.to_bits());
+        put_u32(registers::HISTORY_MAX_TIME, stats.max_at);
+        window[registers::HISTORY_SELECT_INDEX as usize] = index.min(0xFFFF) as u16;
+        window[registers::HISTORY_STATUS as usize] = status;
+        Ok(window)
+    }
+
+    /// Apply writes to the selector registers of a history window.
+    /// Only the timestamp (u32) and index (u16) selectors are writable.
+    fn write_history_selector<S, E>(
+        &mut self,
+        idx: usize,
+        offset: u16,
+        values: &[u16],
+        history: &mut dyn HistoryAccess<S, E>,
+    ) -> Result<(), ExceptionCode> {
+        if offset as usize + values.len() > registers::HISTORY_STATUS as usize {
+            return Err(ExceptionCode::IllegalDataAddress);
+        }
+        let mut selected = self.history_select[idx];
+        for (i, &value) in values.iter().enumerate() {
+            match offset + i as u16 {
+                0 => selected = (selected & 0x0000_FFFF) | ((value as u32) << 16),
+                1 => selected = (selected & 0xFFFF_0000) | value as u32,
+                _ => {
+                    selected = history
+                        .timestamp_at(value as u32)
+                        .ok_or(ExceptionCode::IllegalDataValue)?;
+                }
+            }
+        }
+        self.history_select[idx] = selected;
+        Ok(())
+    }
+
+    /// Build the register image of the event log window
+    fn event_window_registers<S>(
+        &self,
+        storage: &mut S,
+        events: &mut dyn EventAccess<S>,
+    ) -> Result<[u16; registers::EVENT_WINDOW_LEN as usize], crate::history::Error> {
+        let event = events.get(storage, self.event_select)?;
+        let mut window = [0_u16; registers::EVENT_WINDOW_LEN as usize];
+        let mut put_u32 = |offset: u16, v: u32| {
+            window[offset as usize] = (v >> 16) as u16;
+            window[offset as usize + 1] = v as u16;
+        };
+        put_u32(registers::EVENT_SEQUENCE, events.sequence());
+        if let Some(event) = event {
+            put_u32(registers::EVENT_TIME, event.time);
+            put_u32(registers::EVENT_PARAM, event.param);
+        }
+        window[registers::EVENT_SELECT_INDEX as usize] = self.event_select as u16;
+        window[registers::EVENT_COUNT as usize] = events.count().min(0xFFFF) as u16;
+        window[registers::EVENT_CODE as usize] = event.map_or(0, |event| event.code.to_u16());
+        Ok(window)
+    }
+
+    /// Apply a write to the event selector; only the index is writable
+    fn write_event_selector<S>(
+        &mut self,
+        offset: u16,
+        values: &[u16],
+        events: &mut dyn EventAccess<S>,
+    ) -> Result<(), ExceptionCode> {
+        if offset != registers::EVENT_SELECT_INDEX || values.len() != 1 {
+            return Err(ExceptionCode::IllegalDataAddress);
+        }
+        if values[0] as u32 >= events.count() {
+            return Err(ExceptionCode::IllegalDataValue);
+        }
+        self.event_select = values[0] as u32;
+        Ok(())
+    }
+
+    /// Build the register image of the audit trail window
+    fn audit_window_registers<S>(
+        &self,
+        storage: &mut S,
+        audit: &mut dyn AuditAccess<S>,
+    ) -> Result<[u16; registers::AUDIT_WINDOW_LEN as usize], crate::history::Error> {
+        let record = audit.get(storage, self.audit_select)?;
+        let mut window = [0_u16; registers::AUDIT_WINDOW_LEN as usize];
+        let mut put_u32 = |offset: u16, v: u32| {
+            window[offset as usize] = (v >> 16) as u16;
+            window[offset as usize + 1] = v as u16;
+        };
+        put_u32(registers::AUDIT_COUNTER, audit.counter());
+        if let Some(record) = record {
+            put_u32(registers::AUDIT_TIME, record.time);
+            put_u32(registers::AUDIT_OLD, record.old);
+            put_u32(registers::AUDIT_NEW, record.new);
+        }
+        window[registers::AUDIT_SELECT_INDEX as usize] = self.audit_select as u16;
+        window[registers::AUDIT_REMAINING as usize] = audit.remaining().min(0xFFFF) as u16;
+        window[registers::AUDIT_FIELD as usize] =
+            record.map_or(0, |record| record.field.to_u8() as u16);
+        window[registers::AUDIT_SOURCE as usize] =
+            record.map_or(0, |record| record.source.to_u8() as u16);
+        Ok(window)
+    }
+
+    /// Apply a write to the audit selector; only the index is writable
+    fn write_audit_selector<S>(
+        &mut self,
+        offset: u16,
+        values: &[u16],
+        audit: &mut dyn AuditAccess<S>,
+    ) -> Result<(), ExceptionCode> {
+        if offset != registers::AUDIT_SELECT_INDEX || values.len() != 1 {
+            return Err(ExceptionCode::IllegalDataAddress);
+        }
+        if values[0] as u32 >= audit.counter() {
+            return Err(ExceptionCode::IllegalDataValue);
+        }
+        self.audit_select = values[0] as u32;
+        Ok(())
+    }
+
+    /// Get Modbus RTU instance
+    pub fn modbus(&self) -> &ModbusRtu {
+        &self.modbus
+    }
+
+    /// Get mutable Modbus RTU instance
     pub fn modbus_mut(&mut self) -> &mut ModbusRtu {
         &mut self.modbus
     }
-}
+}
+
+/// Trait for accessing history data (to avoid generic parameters in handler)
+pub trait HistoryAccess<S, E> {
+    fn find(
+        &mut self,
+        storage: &mut S,
+        time: u32,
+    ) -> Result<Option<Entry<f32>>, crate::history::Error>;
+    fn first_timestamp(&mut self) -> u32;
+    fn last_timestamp(&mut self) -> u32;
+    /// Number of stored records
+    fn count(&mut self) -> u32;
+    /// Timestamp of the record at `index` (0 = oldest)
+    fn timestamp_at(&mut self, index: u32) -> Option<u32>;
+    /// Index (0 = oldest) of the record stored for `time`
+    fn index_of(&mut self, time: u32) -> Option<u32>;
+    /// Header updates written so far (EEPROM wear estimate)
+    fn write_count(&self) -> u32;
+    /// Min/max flow of the period stored for `time`, for rings that keep them
+    fn find_stats(
+        &mut self,
+        _storage: &mut S,
+        _time: u32,
+    ) -> Result<Option<FlowStats>, crate::history::Error> {
+        Ok(None)
+    }
+}
+
+impl<
+        S: Storage,
+        E,
+        const OFFSET: usize,
+        const SIZE: i32,
+        const ELEMENT_SIZE: i32,
+        R: Record,
+        B: Bucketing,
+    > HistoryAccess<S, E> for RingStorage<OFFSET, SIZE, ELEMENT_SIZE, R, B>
+{
+    fn find(
+        &mut self,
+        storage: &mut S,
+        time: u32,
+    ) -> Result<Option<Entry<f32>>, crate::history::Error> {
+        Ok(RingStorage::find(self, storage, time)?.map(|entry| Entry {
+            value: entry.value.to_f32(),
+            status: entry.status,
+        }))
+    }
+
+    fn first_timestamp(&mut self) -> u32 {
+        RingStorage::first_stored_timestamp(self)
+    }
+
+    fn last_timestamp(&mut self) -> u32 {
+        RingStorage::last_stored_timestamp(self)
+    }
+
+    fn count(&mut self) -> u32 {
+        RingStorage::size(self)
+    }
+
+    fn timestamp_at(&mut self, index: u32) -> Option<u32> {
+        RingStorage::timestamp_at(self, index)
+    }
+
+    fn index_of(&mut self, time: u32) -> Option<u32> {
+        RingStorage::index_of(self, time)
+    }
+
+    fn write_count(&self) -> u32 {
+        RingStorage::write_count(self)
+    }
+}
+
+/// Trait for reading the event log (to avoid generic parameters in handler)
+pub trait EventAccess<S> {
+    /// Events held by the log
+    fn count(&self) -> u32;
+    /// Events logged in total
+    fn sequence(&self) -> u32;
+    /// Event `index` places back from the newest
+    fn get(&mut self, storage: &mut S, index: u32) -> Result<Option<Event>, crate::history::Error>;
+}
+
+impl<S: Storage, const OFFSET: usize, const SIZE: u32> EventAccess<S> for EventLog<OFFSET, SIZE> {
+    fn count(&self) -> u32 {
+        EventLog::len(self)
+    }
+
+    fn sequence(&self) -> u32 {
+        EventLog::sequence(self)
+    }
+
+    fn get(&mut self, storage: &mut S, index: u32) -> Result<Option<Event>, crate::history::Error> {
+        EventLog::get(self, storage, index)
+    }
+}
+
+/// Trait for the audit trail (to avoid generic parameters in handler)
+pub trait AuditAccess<S> {
+    /// Audit counter: changes recorded
+    fn counter(&self) -> u32;
+    /// Changes that can still be recorded
+    fn remaining(&self) -> u32;
+    /// Change `index` places back from the newest
+    fn get(
+        &mut self,
+        storage: &mut S,
+        index: u32,
+    ) -> Result<Option<AuditRecord>, crate::history::Error>;
+    /// Record the audited fields that differ between `old` and `new`
+    fn record(
+        &mut self,
+        storage: &mut S,
+        source: Source,
+        old: &Options,
+        new: &Options,
+    ) -> Result<(), crate::history::Error>;
+}
+
+/// Audit trail with the time its records are stamped with
+pub struct AuditAt<'a, A> {
+    pub log: &'a mut A,
+    pub time: u32,
+}
+
+impl<'a, S: Storage, const OFFSET: usize, const SIZE: u32> AuditAccess<S>
+    for AuditAt<'a, AuditLog<OFFSET, SIZE>>
+{
+    fn counter(&self) -> u32 {
+        self.log.counter()
+    }
+
+    fn remaining(&self) -> u32 {
+        self.log.remaining()
+    }
+
+    fn get(
+        &mut self,
+        storage: &mut S,
+        index: u32,
+    ) -> Result<Option<AuditRecord>, crate::history::Error> {
+        self.log.get(storage, index)
+    }
+
+    fn record(
+        &mut self,
+        storage: &mut S,
+        source: Source,
+        old: &Options,
+        new: &Options,
+    ) -> Result<(), crate::history::Error> {
+        self.log.record(storage, self.time, source, old, new)
+    }
+}
+
+/// History ring paired with the ring of min/max flow for the same periods.
+/// Window reads take the value from `history` and the extremes from `stats`.
+pub struct WithStats<'a, H, T> {
+    pub history: &'a mut H,
+    pub stats: &'a mut T,
+}
+
+impl<
+        'a,
+        S: Storage,
+        E,
+        H: HistoryAccess<S, E>,
+        const OFFSET: usize,
+        const SIZE: i32,
+        const ELEMENT_SIZE: i32,
+        B: Bucketing,
+    > HistoryAccess<S, E>
+    for WithStats<'a, H, RingStorage<OFFSET, SIZE, ELEMENT_SIZE, FlowStats, B>>
+{
+    fn find(
+        &mut self,
+        storage: &mut S,
+        time: u32,
+    ) -> Result<Option<Entry<f32>>, crate::history::Error> {
+        self.history.find(storage, time)
+    }
+
+    fn first_timestamp(&mut self) -> u32 {
+        self.history.first_timestamp()
+    }
+
+    fn last_timestamp(&mut self) -> u32 {
+        self.history.last_timestamp()
+    }
+
+    fn count(&mut self) -> u32 {
+        self.history.count()
+    }
+
+    fn timestamp_at(&mut self, index: u32) -> Option<u32> {
+        self.history.timestamp_at(index)
+    }
+
+    fn index_of(&mut self, time: u32) -> Option<u32> {
+        self.history.index_of(time)
+    }
+
+    fn write_count(&self) -> u32 {
+        self.history.write_count()
+    }
+
+    fn find_stats(
+        &mut self,
+        storage: &mut S,
+        time: u32,
+    ) -> Result<Option<FlowStats>, crate::history::Error> {
+        Ok(self
+            .stats
+            .find(storage, time)?
+            .filter(|entry| entry.status != Status::GapFilled)
+            .map(|entry| entry.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Interval;
+    use crate::modbus::{Received, Receiver};
+    use crate::options::Options;
+
+    // Mock storage for testing (options pages + history stat page)
+    struct MockStorage {
+        data: [u8; 8192],
+    }
+
+    impl MockStorage {
+        fn new() -> Self {
+            Self { data: [0xFF; 8192] }
+        }
+    }
+
+    impl embedded_storage::ReadStorage for MockStorage {
+        type Error = crate::options::Error<()>;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let start = offset as usize;
+            let end = start + bytes.len();
+            if end <= self.data.len() {
+                bytes.copy_from_slice(&self.data[start..end]);
+                Ok(())
+            } else {
+                Err(crate::options::Error::Storage)
+            }
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl embedded_storage::Storage for MockStorage {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let start = offset as usize;
+            let end = start + bytes.len();
+            if end <= self.data.len() {
+                self.data[start..end].copy_from_slice(bytes);
+                Ok(())
+            } else {
+                Err(crate::options::Error::Storage)
+            }
+        }
+    }
+
+    // Mock history for testing
+    struct MockHistory;
+
+    impl<S, E> HistoryAccess<S, E> for MockHistory {
+        fn find(
+            &mut self,
+            _storage: &mut S,
+            _time: u32,
+        ) -> Result<Option<Entry<f32>>, crate::history::Error> {
+            Ok(None)
+        }
+
+        fn first_timestamp(&mut self) -> u32 {
+            0
+        }
+
+        fn last_timestamp(&mut self) -> u32 {
+            0
+        }
+
+        fn count(&mut self) -> u32 {
+            0
+        }
+
+        fn timestamp_at(&mut self, _index: u32) -> Option<u32> {
+            None
+        }
+
+        fn index_of(&mut self, _time: u32) -> Option<u32> {
+            None
+        }
+
+        fn write_count(&self) -> u32 {
+            0
+        }
+    }
 
-/// Trait for accessing history data (to avoid generic parameters in handler)
-pub trait HistoryAccess<S, E> {
-    fn find(&mut self, storage: &mut S, time: u32) -> Result<Option<i32>, crate::history::Error>;
-    fn first_timestamp(&mut self) -> u32;
-    fn last_timestamp(&mut self) -> u32;
-}
+    type TestHistory = RingStorage<0, 24, 3600>;
+    type TestEvents = EventLog<2048, 16>;
+    type TestAudit = AuditLog<2304, 4>;
+
+    const T0: u32 = 1_700_000_000 - 1_700_000_000 % 3600;
+
+    fn test_history(storage: &mut MockStorage, values: &[i32]) -> TestHistory {
+        let mut history = TestHistory::new(storage).unwrap();
+        for (i, &v) in values.iter().enumerate() {
+            history.add(storage, v, T0 + i as u32 * 3600).unwrap();
+        }
+        history
+    }
+
+    /// Decode big-endian registers from a read response
+    fn registers(response: &[u8]) -> std::vec::Vec<u16> {
+        let count = response[2] as usize / 2;
+        (0..count)
+            .map(|i| u16::from_be_bytes([response[3 + i * 2], response[4 + i * 2]]))
+            .collect()
+    }
+
+    fn frame(bytes: &[u8]) -> std::vec::Vec<u8> {
+        let mut frame = bytes.to_vec();
+        frame.extend_from_slice(&ModbusRtu::calculate_crc(bytes).to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_read_holding_registers_options() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        // Set specific serial number for testing
+        options.set_serial_number(0x12345678);
+
+        // Read registers 1-2 (serial number: u32 = 2 registers)
+        let frame = [0x01, 0x03, 0x00, 0x01, 0x00, 0x02, 0x95, 0xCB];
+
+        let response = handler
+            .handle_request(
+                &frame,
+                &mut options,
+                &mut storage,
+                1.5,
+                10.0,
+                100.0,
+                1000.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
+
+        // Response: slave(1) + func(1) + byte_count(1) + data(4) + crc(2) = 9 bytes
+        assert_eq!(response[0], 0x01); // Slave address
+        assert_eq!(response[1], 0x03); // Function code
+        assert_eq!(response[2], 0x04); // Byte count (2 registers * 2 bytes)
+                                       // Serial number bytes (bitfield stores as little-endian)
+        assert_eq!(response[3], 0x78); // Low byte of serial
+        assert_eq!(response[4], 0x56);
+        assert_eq!(response[5], 0x34);
+        assert_eq!(response[6], 0x12); // High byte of serial
+        assert_eq!(response.len(), 9); // Total length with CRC
+    }
+
+    #[test]
+    fn test_read_holding_registers_flow_data() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        // Read flow_rate (registers 100-101: 0x0064-0x0065)
+        let frame = [0x01, 0x03, 0x00, 0x64, 0x00, 0x02, 0x85, 0xD4];
+
+        let response = handler
+            .handle_request(
+                &frame,
+                &mut options,
+                &mut storage,
+                1.5,
+                10.0,
+                100.0,
+                1000.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
+
+        assert_eq!(response[0], 0x01); // Slave address
+        assert_eq!(response[1], 0x03); // Function code
+        assert_eq!(response[2], 0x04); // Byte count
+                                       // Verify float value 1.5 in IEEE 754 format (big-endian)
+        let float_bytes = 1.5f32.to_be_bytes();
+        assert_eq!(response[3], float_bytes[0]);
+        assert_eq!(response[4], float_bytes[1]);
+        assert_eq!(response[5], float_bytes[2]);
+        assert_eq!(response[6], float_bytes[3]);
+    }
+
+    #[test]
+    fn test_read_input_registers() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        // Read first 4 registers (flow_rate and hour_flow)
+        let frame = [0x01, 0x04, 0x00, 0x00, 0x00, 0x04, 0xF1, 0xC9];
+
+        let response = handler
+            .handle_request(
+                &frame,
+                &mut options,
+                &mut storage,
+                2.5,
+                15.0,
+                150.0,
+                1500.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
+
+        assert_eq!(response[0], 0x01); // Slave address
+        assert_eq!(response[1], 0x04); // Function code
+        assert_eq!(response[2], 0x08); // Byte count (4 registers * 2 bytes)
+
+        // Verify flow_rate (2.5)
+        let flow_rate_bytes = 2.5f32.to_be_bytes();
+        assert_eq!(response[3], flow_rate_bytes[0]);
+        assert_eq!(response[4], flow_rate_bytes[1]);
+        assert_eq!(response[5], flow_rate_bytes[2]);
+        assert_eq!(response[6], flow_rate_bytes[3]);
+
+        // Verify hour_flow (15.0)
+        let hour_flow_bytes = 15.0f32.to_be_bytes();
+        assert_eq!(response[7], hour_flow_bytes[0]);
+        assert_eq!(response[8], hour_flow_bytes[1]);
+        assert_eq!(response[9], hour_flow_bytes[2]);
+        assert_eq!(response[10], hour_flow_bytes[3]);
+    }
+
+    #[test]
+    fn test_write_single_register() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        // Write register 0 (CRC field)
+        let frame = [0x01, 0x06, 0x00, 0x00, 0xAB, 0xCD, 0x37, 0x6F];
+
+        let response = handler
+            .handle_request(
+                &frame,
+                &mut options,
+                &mut storage,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
+
+        // Response should echo the request
+        assert_eq!(response[0], 0x01); // Slave address
+        assert_eq!(response[1], 0x06); // Function code
+        assert_eq!(response[2], 0x00); // Address high
+        assert_eq!(response[3], 0x00); // Address low
+        assert_eq!(response[4], 0xAB); // Value high
+        assert_eq!(response[5], 0xCD); // Value low
+
+        // Note: CRC is recalculated by options.save(), so we don't check the exact value
+    }
+
+    #[test]
+    fn test_write_multiple_registers() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        // Write 2 registers starting at register 0 (CRC and part of serial)
+        let frame = [
+            0x01, 0x10, 0x00, 0x00, 0x00, 0x02, 0x04, 0x12, 0x34, 0x56, 0x78, 0x88, 0x9B,
+        ];
+
+        let response = handler
+            .handle_request(
+                &frame,
+                &mut options,
+                &mut storage,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
+
+        // Response: slave + func + start_addr + quantity + crc
+        assert_eq!(response[0], 0x01); // Slave address
+        assert_eq!(response[1], 0x10); // Function code
+        assert_eq!(response[2], 0x00); // Start address high
+        assert_eq!(response[3], 0x00); // Start address low
+        assert_eq!(response[4], 0x00); // Quantity high
+        assert_eq!(response[5], 0x02); // Quantity low
+
+        // Note: CRC is recalculated by options.save(), so we don't check the exact value
+    }
+
+    #[test]
+    fn test_invalid_slave_address() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        // Request for slave 0x02 (not us)
+        let frame = [0x02, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xFE];
+
+        let result = handler.handle_request(
+            &frame,
+            &mut options,
+            &mut storage,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            &mut hour_history,
+            &mut day_history,
+            &mut month_history,
+            &mut profile_history,
+            &mut events,
+            &mut AuditAt {
+                log: &mut audit,
+                time: 0,
+            },
+        );
 
-impl<S: Storage, E, const OFFSET: usize, const SIZE: i32, const ELEMENT_SIZE: i32>
-    HistoryAccess<S, E> for RingStorage<OFFSET, SIZE, ELEMENT_SIZE>
-{
-    fn find(&mut self, storage: &mut S, time: u32) -> Result<Option<i32>, crate::history::Error> {
-        RingStorage::find(self, storage, time)
+        assert!(matches!(result, Err(ModbusError::InvalidSlaveAddress)));
     }
 
-    fn first_timestamp(&mut self) -> u32 {
-        RingStorage::first_stored_timestamp(self)
-    }
+    #[test]
+    fn test_illegal_data_address() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
 
-    fn last_timestamp(&mut self) -> u32 {
-        RingStorage::last_stored_timestamp(self)
-    }
-}
+        // Try to read from invalid address 0x7000
+        let request = frame(&[0x01, 0x03, 0x70, 0x00, 0x00, 0x01]);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::options::Options;
+        let response = handler
+            .handle_request(
+                &request,
+                &mut options,
+                &mut storage,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
 
-    // Mock storage for testing
-    struct MockStorage {
-        data: [u8; 4096],
+        // Should be an exception response
+        assert_eq!(response[0], 0x01); // Slave address
+        assert_eq!(response[1], 0x83); // Function code with error bit (0x03 | 0x80)
+        assert_eq!(response[2], 0x02); // Exception code: IllegalDataAddress
     }
 
-    impl MockStorage {
-        fn new() -> Self {
-            Self { data: [0xFF; 4096] }
-        }
-    }
+    #[test]
+    fn test_illegal_quantity() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
 
-    impl embedded_storage::ReadStorage for MockStorage {
-        type Error = crate::options::Error<()>;
+        // Try to read 0 registers (invalid)
+        let frame = [0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x45, 0xCA];
 
-        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
-            let start = offset as usize;
-            let end = start + bytes.len();
-            if end <= self.data.len() {
-                bytes.copy_from_slice(&self.data[start..end]);
-                Ok(())
-            } else {
-                Err(crate::options::Error::Storage)
-            }
-        }
+        let response = handler
+            .handle_request(
+                &frame,
+                &mut options,
+                &mut storage,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
 
-        fn capacity(&self) -> usize {
-            self.data.len()
-        }
+        // Should be an exception response
+        assert_eq!(response[0], 0x01); // Slave address
+        assert_eq!(response[1], 0x83); // Function code with error bit
+        assert_eq!(response[2], 0x03); // Exception code: IllegalDataValue
     }
 
-    impl embedded_storage::Storage for MockStorage {
-        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
-            let start = offset as usize;
-            let end = start + bytes.len();
-            if end <= self.data.len() {
-                self.data[start..end].copy_from_slice(bytes);
-                Ok(())
-            } else {
-                Err(crate::options::Error::Storage)
-            }
-        }
+    #[test]
+    fn test_register_range_edges() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        let requests = [
+            // Write of 2 registers from 0xFFFF runs past the address space
+            frame(&[0x01, 0x10, 0xFF, 0xFF, 0x00, 0x02, 0x04, 0, 0, 0, 0]),
+            // Write of no registers
+            frame(&[0x01, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            // Read of 4 registers from the resettable total runs past the flow block
+            frame(&[0x01, 0x03, 0x00, 0x6C, 0x00, 0x04]),
+            // Hour flow, read from its own first register
+            frame(&[0x01, 0x03, 0x00, 0x66, 0x00, 0x02]),
+        ];
+        let mut responses = requests.iter().map(|request| {
+            handler
+                .handle_request(
+                    request,
+                    &mut options,
+                    &mut storage,
+                    1.5,
+                    10.0,
+                    100.0,
+                    1000.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap()
+        });
+
+        assert_eq!(responses.next().unwrap()[1..3], [0x90, 0x02]);
+        assert_eq!(responses.next().unwrap()[1..3], [0x90, 0x03]);
+        assert_eq!(responses.next().unwrap()[1..3], [0x83, 0x02]);
+        assert_eq!(responses.next().unwrap()[3..7], 10.0f32.to_be_bytes());
+        assert_eq!(options.into_bytes(), Options::default().into_bytes());
     }
 
-    // Mock history for testing
-    struct MockHistory;
-
-    impl<S, E> HistoryAccess<S, E> for MockHistory {
-        fn find(
-            &mut self,
-            _storage: &mut S,
-            _time: u32,
-        ) -> Result<Option<i32>, crate::history::Error> {
-            Ok(None)
-        }
+    #[test]
+    fn test_history_window_empty() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = TestHistory::new(&mut storage).unwrap();
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
 
-        fn first_timestamp(&mut self) -> u32 {
-            0
-        }
+        // Read the whole hour history window
+        let request = frame(&[0x01, 0x03, 0x10, 0x00, 0x00, 0x0C]);
+        let response = handler
+            .handle_request(
+                &request,
+                &mut options,
+                &mut storage,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
 
-        fn last_timestamp(&mut self) -> u32 {
-            0
-        }
+        assert_eq!(response[1], 0x03);
+        let regs = registers(&response);
+        assert_eq!(regs.len(), 12);
+        assert_eq!(regs[3], registers::HISTORY_STATUS_NO_RECORD);
+        assert_eq!(&regs[4..6], &[0, 0]); // record count
     }
 
     #[test]
-    fn test_read_holding_registers_options() {
-        let handler = ModbusHandler::new(0x01);
+    fn test_history_window_select_by_timestamp() {
+        let mut handler = ModbusHandler::new(0x01);
         let mut options = Options::default();
         let mut storage = MockStorage::new();
-        let mut hour_history = MockHistory;
+        let mut hour_history = test_history(&mut storage, &[10, 20, 30]);
         let mut day_history = MockHistory;
         let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+        let last = T0 + 2 * 3600;
+
+        // Write selector timestamp (2 registers at 0x1000)
+        let ts = last.to_be_bytes();
+        let request = frame(&[
+            0x01, 0x10, 0x10, 0x00, 0x00, 0x02, 0x04, ts[0], ts[1], ts[2], ts[3],
+        ]);
+        let response = handler
+            .handle_request(
+                &request,
+                &mut options,
+                &mut storage,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
+        assert_eq!(response[1], 0x10);
 
-        // Set specific serial number for testing
-        options.set_serial_number(0x12345678);
+        // Read the window back
+        let request = frame(&[0x01, 0x03, 0x10, 0x00, 0x00, 0x0E]);
+        let response = handler
+            .handle_request(
+                &request,
+                &mut options,
+                &mut storage,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
+        let regs = registers(&response);
+        let u32_at = |i: usize| ((regs[i] as u32) << 16) | regs[i + 1] as u32;
+
+        assert_eq!(u32_at(0), last);
+        assert_eq!(regs[2], 2); // index of the newest record
+        assert_eq!(regs[3], registers::HISTORY_STATUS_OK);
+        assert_eq!(u32_at(4), 3); // record count
+        assert_eq!(u32_at(6), T0);
+        assert_eq!(u32_at(8), last);
+        assert_eq!(f32::from_bits(u32_at(10)), 30.0);
+        assert_eq!(u32_at(12), 3); // one header update per record
+    }
 
-        // Read registers 1-2 (serial number: u32 = 2 registers)
-        let frame = [0x01, 0x03, 0x00, 0x01, 0x00, 0x02, 0x95, 0xCB];
+    #[test]
+    fn test_history_window_select_by_index() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = test_history(&mut storage, &[10, 20, 30]);
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        // Select record index 2 via Write Single Register (0x1002)
+        let request = frame(&[0x01, 0x06, 0x10, 0x02, 0x00, 0x02]);
+        handler
+            .handle_request(
+                &request,
+                &mut options,
+                &mut storage,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                &mut hour_history,
+                &mut day_history,
+                &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
+            )
+            .unwrap();
 
+        // Read selected timestamp, index, status
+        let request = frame(&[0x01, 0x03, 0x10, 0x00, 0x00, 0x04]);
         let response = handler
             .handle_request(
-                &frame,
+                &request,
                 &mut options,
                 &mut storage,
-                1.5,
-                10.0,
-                100.0,
-                1000.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
                 &mut hour_history,
                 &mut day_history,
                 &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
             )
             .unwrap();
+        let regs = registers(&response);
+        assert_eq!(((regs[0] as u32) << 16) | regs[1] as u32, T0 + 2 * 3600);
+        assert_eq!(regs[2], 2);
+        assert_eq!(regs[3], registers::HISTORY_STATUS_OK);
+    }
 
-        // Response: slave(1) + func(1) + byte_count(1) + data(4) + crc(2) = 9 bytes
-        assert_eq!(response[0], 0x01); // Slave address
-        assert_eq!(response[1], 0x03); // Function code
-        assert_eq!(response[2], 0x04); // Byte count (2 registers * 2 bytes)
-                                       // Serial number bytes (bitfield stores as little-endian)
-        assert_eq!(response[3], 0x78); // Low byte of serial
-        assert_eq!(response[4], 0x56);
-        assert_eq!(response[5], 0x34);
-        assert_eq!(response[6], 0x12); // High byte of serial
-        assert_eq!(response.len(), 9); // Total length with CRC
+    #[test]
+    fn test_read_write_multiple_registers() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = test_history(&mut storage, &[10, 20, 30]);
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        let mut request = |options: &mut Options, request: &[u8]| {
+            handler
+                .handle_request(
+                    &frame(request),
+                    options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap()
+        };
+
+        // Select index 1 and read the window back in one request
+        let response = request(
+            &mut options,
+            &[
+                0x01, 0x17, 0x10, 0x00, 0x00, 0x0C, 0x10, 0x02, 0x00, 0x01, 0x02, 0x00, 0x01,
+            ],
+        );
+        assert_eq!(response[1], 0x17);
+        assert_eq!(response[2], 24);
+        let regs = registers(&response);
+        assert_eq!(((regs[0] as u32) << 16) | regs[1] as u32, T0 + 3600);
+        assert_eq!(regs[2], 1);
+        assert_eq!(regs[3], registers::HISTORY_STATUS_OK);
+        let value = f32::from_bits(((regs[10] as u32) << 16) | regs[11] as u32);
+        assert_eq!(value, 20.0);
+
+        // Options written and read back in the same transaction
+        let response = request(
+            &mut options,
+            &[
+                0x01, 0x17, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x02, 0x11, 0x22,
+            ],
+        );
+        assert_eq!(registers(&response), [0x1122]);
+
+        // A failed write answers with a 0x17 exception and reads nothing
+        let response = request(
+            &mut options,
+            &[
+                0x01, 0x17, 0x10, 0x00, 0x00, 0x01, 0x10, 0x02, 0x00, 0x01, 0x02, 0x00, 0x09,
+            ],
+        );
+        assert_eq!(response[1..3], [0x97, 0x03]);
+        let response = request(
+            &mut options,
+            &[
+                0x01, 0x17, 0x10, 0x00, 0x00, 0x01, 0x70, 0x00, 0x00, 0x01, 0x02, 0x00, 0x01,
+            ],
+        );
+        assert_eq!(response[1..3], [0x97, 0x02]);
+        // Write quantity out of range
+        let response = request(
+            &mut options,
+            &[
+                0x01, 0x17, 0x10, 0x00, 0x00, 0x01, 0x10, 0x02, 0x00, 0x00, 0x00,
+            ],
+        );
+        assert_eq!(response[1..3], [0x97, 0x03]);
+        // Read quantity out of range, after the write
+        let response = request(
+            &mut options,
+            &[
+                0x01, 0x17, 0x10, 0x00, 0x00, 0x00, 0x10, 0x02, 0x00, 0x01, 0x02, 0x00, 0x02,
+            ],
+        );
+        assert_eq!(response[1..3], [0x97, 0x03]);
+    }
+
+    #[test]
+    fn test_read_write_bad_read_writes_nothing() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+        let before = options.into_bytes();
+
+        // Register 1 = 0x1122, then read 0 registers / an unmapped address
+        for (request, exception) in [
+            (
+                [
+                    0x01, 0x17, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x02, 0x11, 0x22,
+                ],
+                0x03,
+            ),
+            (
+                [
+                    0x01, 0x17, 0x0F, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x02, 0x11, 0x22,
+                ],
+                0x02,
+            ),
+            (
+                [
+                    0x01, 0x17, 0x00, 0x1F, 0x00, 0x7D, 0x00, 0x01, 0x00, 0x01, 0x02, 0x11, 0x22,
+                ],
+                0x02,
+            ),
+        ] {
+            let response = handler
+                .handle_request(
+                    &frame(&request),
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap();
+            assert_eq!(response[1..3], [0x97, exception]);
+        }
+        assert_eq!(options.into_bytes(), before);
+        assert_eq!(handler.take_config_write(), None);
+        assert_eq!(audit.counter(), 0);
+    }
+
+    #[test]
+    fn test_history_window_gap_filled_status() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+        let mut hour_history = TestHistory::new(&mut storage).unwrap();
+        hour_history.add(&mut storage, 10, T0).unwrap();
+        // Meter was not running for the two hours in between
+        hour_history.add(&mut storage, 40, T0 + 3 * 3600).unwrap();
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+
+        let mut status_of = |index: u8| {
+            let request = frame(&[0x01, 0x06, 0x10, 0x02, 0x00, index]);
+            handler
+                .handle_request(
+                    &request,
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap();
+            let request = frame(&[0x01, 0x03, 0x10, 0x03, 0x00, 0x01]);
+            let response = handler
+                .handle_request(
+                    &request,
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap();
+            registers(&response)[0]
+        };
+
+        assert_eq!(status_of(0), registers::HISTORY_STATUS_OK);
+        assert_eq!(status_of(1), registers::HISTORY_STATUS_GAP_FILLED);
+        assert_eq!(status_of(2), registers::HISTORY_STATUS_GAP_FILLED);
+        assert_eq!(status_of(3), registers::HISTORY_STATUS_OK);
     }
 
     #[test]
-    fn test_read_holding_registers_flow_data() {
-        let handler = ModbusHandler::new(0x01);
+    fn test_history_window_load_profile() {
+        let mut handler = ModbusHandler::new(0x01);
         let mut options = Options::default();
         let mut storage = MockStorage::new();
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
         let mut hour_history = MockHistory;
         let mut day_history = MockHistory;
         let mut month_history = MockHistory;
+        let mut profile_history = RingStorage::<0, 96, 900, f32, Interval>::new(&mut storage)
+            .unwrap()
+            .with_bucketing(Interval::minutes(15));
+        for (i, value) in [1.5, 2.5, 3.5].iter().enumerate() {
+            profile_history
+                .add(&mut storage, *value, T0 + i as u32 * 900)
+                .unwrap();
+        }
 
-        // Read flow_rate (registers 100-101: 0x0064-0x0065)
-        let frame = [0x01, 0x03, 0x00, 0x64, 0x00, 0x02, 0x85, 0xD4];
+        // Select the second record by index, then read the window
+        let mut request_window = |request: &[u8]| {
+            handler
+                .handle_request(
+                    &frame(request),
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap()
+        };
+        request_window(&[0x01, 0x06, 0x40, 0x02, 0x00, 0x01]);
+        let response = request_window(&[0x01, 0x03, 0x40, 0x00, 0x00, 0x0E]);
+        let regs = registers(&response);
+        let u32_at = |i: usize| ((regs[i] as u32) << 16) | regs[i + 1] as u32;
+
+        assert_eq!(u32_at(0), T0 + 900);
+        assert_eq!(regs[3], registers::HISTORY_STATUS_OK);
+        assert_eq!(u32_at(4), 3);
+        assert_eq!(u32_at(6), T0);
+        assert_eq!(u32_at(8), T0 + 1800);
+        assert_eq!(f32::from_bits(u32_at(10)), 2.5);
+    }
 
-        let response = handler
-            .handle_request(
-                &frame,
-                &mut options,
-                &mut storage,
-                1.5,
-                10.0,
-                100.0,
-                1000.0,
-                &mut hour_history,
-                &mut day_history,
-                &mut month_history,
+    #[test]
+    fn test_history_window_flow_stats() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+        let mut hour_sums = test_history(&mut storage, &[10, 20, 30]);
+        let mut hour_stats = RingStorage::<512, 24, 3600, FlowStats>::new(&mut storage).unwrap();
+        let peak = FlowStats {
+            min: 0.25,
+            min_at: T0 + 2 * 3600 + 600,
+            max: 4.5,
+            max_at: T0 + 2 * 3600 + 1800,
+        };
+        hour_stats.add(&mut storage, FlowStats::EMPTY, T0).unwrap();
+        // No stats kept for the hour in between
+        hour_stats.add(&mut storage, peak, T0 + 2 * 3600).unwrap();
+        let mut hour_history = WithStats {
+            history: &mut hour_sums,
+            stats: &mut hour_stats,
+        };
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+
+        let mut stats_of = |index: u8| {
+            let mut request = |request: &[u8]| {
+                handler
+                    .handle_request(
+                        &frame(request),
+                        &mut options,
+                        &mut storage,
+                        0.0,
+                        0.0,
+                        0.0,
+                        0.0,
+                        &mut hour_history,
+                        &mut day_history,
+                        &mut month_history,
+                        &mut profile_history,
+                        &mut events,
+                        &mut AuditAt {
+                            log: &mut audit,
+                            time: 0,
+                        },
+                    )
+                    .unwrap()
+            };
+            request(&[0x01, 0x06, 0x10, 0x02, 0x00, index]);
+            let regs = registers(&request(&[0x01, 0x03, 0x10, 0x0A, 0x00, 0x0C]));
+            let u32_at = |i: usize| ((regs[i] as u32) << 16) | regs[i + 1] as u32;
+            (
+                f32::from_bits(u32_at(0)),
+                f32::from_bits(u32_at(4)),
+                u32_at(6),
+                f32::from_bits(u32_at(8)),
+                u32_at(10),
             )
-            .unwrap();
+        };
 
-        assert_eq!(response[0], 0x01); // Slave address
-        assert_eq!(response[1], 0x03); // Function code
-        assert_eq!(response[2], 0x04); // Byte count
-                                       // Verify float value 1.5 in IEEE 754 format (big-endian)
-        let float_bytes = 1.5f32.to_be_bytes();
-        assert_eq!(response[3], float_bytes[0]);
-        assert_eq!(response[4], float_bytes[1]);
-        assert_eq!(response[5], float_bytes[2]);
-        assert_eq!(response[6], float_bytes[3]);
+        assert_eq!(
+            stats_of(2),
+            (30.0, 0.25, T0 + 2 * 3600 + 600, 4.5, T0 + 2 * 3600 + 1800)
+        );
+        let (value, min, min_at, max, max_at) = stats_of(1);
+        assert_eq!(value, 20.0);
+        assert!(min.is_nan() && max.is_nan());
+        assert_eq!((min_at, max_at), (0, 0));
+        // Period without samples
+        let (value, min, _, max, _) = stats_of(0);
+        assert_eq!(value, 10.0);
+        assert!(min.is_nan() && max.is_nan());
     }
 
     #[test]
-    fn test_read_input_registers() {
-        let handler = ModbusHandler::new(0x01);
+    fn test_history_window_index_out_of_range() {
+        let mut handler = ModbusHandler::new(0x01);
         let mut options = Options::default();
         let mut storage = MockStorage::new();
-        let mut hour_history = MockHistory;
+        let mut hour_history = test_history(&mut storage, &[10]);
         let mut day_history = MockHistory;
         let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
 
-        // Read first 4 registers (flow_rate and hour_flow)
-        let frame = [0x01, 0x04, 0x00, 0x00, 0x00, 0x04, 0xF1, 0xC9];
-
+        let request = frame(&[0x01, 0x06, 0x10, 0x02, 0x00, 0x05]);
         let response = handler
             .handle_request(
-                &frame,
+                &request,
                 &mut options,
                 &mut storage,
-                2.5,
-                15.0,
-                150.0,
-                1500.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
                 &mut hour_history,
                 &mut day_history,
                 &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
             )
             .unwrap();
 
-        assert_eq!(response[0], 0x01); // Slave address
-        assert_eq!(response[1], 0x04); // Function code
-        assert_eq!(response[2], 0x08); // Byte count (4 registers * 2 bytes)
-
-        // Verify flow_rate (2.5)
-        let flow_rate_bytes = 2.5f32.to_be_bytes();
-        assert_eq!(response[3], flow_rate_bytes[0]);
-        assert_eq!(response[4], flow_rate_bytes[1]);
-        assert_eq!(response[5], flow_rate_bytes[2]);
-        assert_eq!(response[6], flow_rate_bytes[3]);
-
-        // Verify hour_flow (15.0)
-        let hour_flow_bytes = 15.0f32.to_be_bytes();
-        assert_eq!(response[7], hour_flow_bytes[0]);
-        assert_eq!(response[8], hour_flow_bytes[1]);
-        assert_eq!(response[9], hour_flow_bytes[2]);
-        assert_eq!(response[10], hour_flow_bytes[3]);
+        assert_eq!(response[1], 0x86);
+        assert_eq!(response[2], 0x03); // IllegalDataValue
     }
 
     #[test]
-    fn test_write_single_register() {
-        let handler = ModbusHandler::new(0x01);
+    fn test_history_window_read_only_registers() {
+        let mut handler = ModbusHandler::new(0x01);
         let mut options = Options::default();
         let mut storage = MockStorage::new();
         let mut hour_history = MockHistory;
         let mut day_history = MockHistory;
         let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
 
-        // Write register 0 (CRC field)
-        let frame = [0x01, 0x06, 0x00, 0x00, 0xAB, 0xCD, 0x37, 0x6F];
-
+        // Record count register (0x2004) is read-only
+        let request = frame(&[0x01, 0x06, 0x20, 0x04, 0x00, 0x01]);
         let response = handler
             .handle_request(
-                &frame,
+                &request,
                 &mut options,
                 &mut storage,
                 0.0,
@@ -625,37 +2925,36 @@ mod tests {
                 &mut hour_history,
                 &mut day_history,
                 &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
             )
             .unwrap();
 
-        // Response should echo the request
-        assert_eq!(response[0], 0x01); // Slave address
-        assert_eq!(response[1], 0x06); // Function code
-        assert_eq!(response[2], 0x00); // Address high
-        assert_eq!(response[3], 0x00); // Address low
-        assert_eq!(response[4], 0xAB); // Value high
-        assert_eq!(response[5], 0xCD); // Value low
-
-        // Note: CRC is recalculated by options.save(), so we don't check the exact value
+        assert_eq!(response[1], 0x86);
+        assert_eq!(response[2], 0x02); // IllegalDataAddress
     }
 
     #[test]
-    fn test_write_multiple_registers() {
-        let handler = ModbusHandler::new(0x01);
+    fn test_history_window_read_past_end() {
+        let mut handler = ModbusHandler::new(0x01);
         let mut options = Options::default();
         let mut storage = MockStorage::new();
         let mut hour_history = MockHistory;
         let mut day_history = MockHistory;
         let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
 
-        // Write 2 registers starting at register 0 (CRC and part of serial)
-        let frame = [
-            0x01, 0x10, 0x00, 0x00, 0x00, 0x02, 0x04, 0x12, 0x34, 0x56, 0x78, 0x88, 0x9B,
-        ];
-
+        // 0x3014 + 4 registers runs past the month window
+        let request = frame(&[0x01, 0x03, 0x30, 0x14, 0x00, 0x04]);
         let response = handler
             .handle_request(
-                &frame,
+                &request,
                 &mut options,
                 &mut storage,
                 0.0,
@@ -665,96 +2964,557 @@ mod tests {
                 &mut hour_history,
                 &mut day_history,
                 &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
             )
             .unwrap();
 
-        // Response: slave + func + start_addr + quantity + crc
-        assert_eq!(response[0], 0x01); // Slave address
-        assert_eq!(response[1], 0x10); // Function code
-        assert_eq!(response[2], 0x00); // Start address high
-        assert_eq!(response[3], 0x00); // Start address low
-        assert_eq!(response[4], 0x00); // Quantity high
-        assert_eq!(response[5], 0x02); // Quantity low
+        assert_eq!(response[1], 0x83);
+        assert_eq!(response[2], 0x02); // IllegalDataAddress
+    }
 
-        // Note: CRC is recalculated by options.save(), so we don't check the exact value
+    #[test]
+    fn test_event_log_window() {
+        use crate::events::EventCode;
+
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+        events
+            .append(
+                &mut storage,
+                Event {
+                    time: T0,
+                    code: EventCode::PowerUp,
+                    param: 0x0C,
+                },
+            )
+            .unwrap();
+        events
+            .append(
+                &mut storage,
+                Event {
+                    time: T0 + 60,
+                    code: EventCode::TdcTimeout,
+                    param: 0,
+                },
+            )
+            .unwrap();
+
+        let mut request = |request: &[u8]| {
+            handler
+                .handle_request(
+                    &frame(request),
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap()
+        };
+        let regs = registers(&request(&[0x01, 0x03, 0x50, 0x00, 0x00, 0x09]));
+        assert_eq!(
+            regs,
+            [0, 2, 0, 2, (T0 >> 16) as u16, (T0 + 60) as u16, 9, 0, 0]
+        );
+
+        // Select the older event
+        let response = request(&[0x01, 0x06, 0x50, 0x00, 0x00, 0x01]);
+        assert_eq!(response[1], 0x06);
+        let regs = registers(&request(&[0x01, 0x03, 0x50, 0x04, 0x00, 0x05]));
+        assert_eq!(regs, [(T0 >> 16) as u16, T0 as u16, 1, 0, 0x0C]);
+
+        // Past the oldest event
+        let response = request(&[0x01, 0x06, 0x50, 0x00, 0x00, 0x02]);
+        assert_eq!(response[1], 0x86);
+        assert_eq!(response[2], 0x03); // IllegalDataValue
+
+        // Only the index is writable
+        let response = request(&[0x01, 0x06, 0x50, 0x06, 0x00, 0x01]);
+        assert_eq!(response[1], 0x86);
+        assert_eq!(response[2], 0x02); // IllegalDataAddress
     }
 
     #[test]
-    fn test_invalid_slave_address() {
-        let handler = ModbusHandler::new(0x01);
+    fn test_config_write_is_reported() {
+        let mut handler = ModbusHandler::new(0x01);
         let mut options = Options::default();
         let mut storage = MockStorage::new();
         let mut hour_history = MockHistory;
         let mut day_history = MockHistory;
         let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        let mut request = |request: &[u8]| {
+            handler
+                .handle_request(
+                    &frame(request),
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap();
+        };
+        request(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]);
+        request(&[0x01, 0x06, 0x00, 0x03, 0x00, 0x07]);
+        assert_eq!(handler.take_config_write(), Some(0x0003));
+        assert_eq!(handler.take_config_write(), None);
+    }
 
-        // Request for slave 0x02 (not us)
-        let frame = [0x02, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC4, 0x1E];
+    #[test]
+    fn test_coils_and_discrete_inputs() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        let mut request = |handler: &mut ModbusHandler, request: &[u8]| {
+            handler
+                .handle_request(
+                    &frame(request),
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap()
+        };
 
-        let result = handler.handle_request(
-            &frame,
-            &mut options,
-            &mut storage,
-            0.0,
-            0.0,
-            0.0,
-            0.0,
-            &mut hour_history,
-            &mut day_history,
-            &mut month_history,
+        // Discrete inputs follow the status
+        handler.set_status(
+            MeterStatus::TDC_ERROR | MeterStatus::OPTIONS_FALLBACK | MeterStatus::CALIBRATING,
+        );
+        let response = request(&mut handler, &[0x01, 0x02, 0x00, 0x00, 0x00, 0x05]);
+        assert_eq!(response[1..4], [0x02, 0x01, 0b11001]);
+        let response = request(&mut handler, &[0x01, 0x02, 0x00, 0x01, 0x00, 0x03]);
+        assert_eq!(response[1..4], [0x02, 0x01, 0b100]);
+        let response = request(&mut handler, &[0x01, 0x02, 0x00, 0x02, 0x00, 0x04]);
+        assert_eq!(response[1..3], [0x82, 0x02]); // IllegalDataAddress
+
+        // Single coil: echoed, read back until taken
+        let response = request(&mut handler, &[0x01, 0x05, 0x00, 0x01, 0xFF, 0x00]);
+        assert_eq!(response[1..6], [0x05, 0x00, 0x01, 0xFF, 0x00]);
+        let response = request(&mut handler, &[0x01, 0x01, 0x00, 0x00, 0x00, 0x03]);
+        assert_eq!(response[1..4], [0x01, 0x01, 0b010]);
+        assert!(handler.take_commands().eq([AppRequest::SaveOptions]));
+        assert_eq!(handler.take_commands().count(), 0);
+        let response = request(&mut handler, &[0x01, 0x01, 0x00, 0x00, 0x00, 0x03]);
+        assert_eq!(response[1..4], [0x01, 0x01, 0x00]);
+
+        // 0x0000 does nothing, other values are refused
+        request(&mut handler, &[0x01, 0x05, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(handler.take_commands().count(), 0);
+        let response = request(&mut handler, &[0x01, 0x05, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(response[1..3], [0x85, 0x03]); // IllegalDataValue
+        let response = request(&mut handler, &[0x01, 0x05, 0x00, 0x04, 0xFF, 0x00]);
+        assert_eq!(response[1..3], [0x85, 0x02]); // IllegalDataAddress
+
+        // Auto-zero
+        request(&mut handler, &[0x01, 0x05, 0x00, 0x03, 0xFF, 0x00]);
+        assert!(handler.take_commands().eq([AppRequest::AutoZero]));
+
+        // Multiple coils: reset and system reset, in coil order
+        let response = request(
+            &mut handler,
+            &[0x01, 0x0F, 0x00, 0x00, 0x00, 0x03, 0x01, 0x05],
+        );
+        assert_eq!(response[1..6], [0x0F, 0x00, 0x00, 0x00, 0x03]);
+        assert!(handler
+            .take_commands()
+            .eq([AppRequest::ResetTotals, AppRequest::SystemReset]));
+        request(
+            &mut handler,
+            &[0x01, 0x0F, 0x00, 0x01, 0x00, 0x01, 0x01, 0x01],
         );
+        assert!(handler.take_commands().eq([AppRequest::SaveOptions]));
 
-        assert!(matches!(result, Err(ModbusError::InvalidSlaveAddress)));
+        // Past the last coil, or a byte count that does not match
+        let response = request(
+            &mut handler,
+            &[0x01, 0x0F, 0x00, 0x01, 0x00, 0x04, 0x01, 0x0F],
+        );
+        assert_eq!(response[1..3], [0x8F, 0x02]);
+        let response = request(
+            &mut handler,
+            &[0x01, 0x0F, 0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00],
+        );
+        assert_eq!(response[1..3], [0x8F, 0x03]);
+        assert_eq!(handler.take_commands().count(), 0);
     }
 
     #[test]
-    fn test_illegal_data_address() {
-        let handler = ModbusHandler::new(0x01);
+    fn test_reset_totals_coil() {
+        let mut handler = ModbusHandler::new(0x01);
         let mut options = Options::default();
         let mut storage = MockStorage::new();
         let mut hour_history = MockHistory;
         let mut day_history = MockHistory;
         let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+        let mut app = crate::apps::App::new();
+        app.total_flow = 250.0;
+
+        // As modbus_poll: totals set before the request, commands run after it
+        let mut request = |app: &mut crate::apps::App, request: &[u8]| {
+            handler.set_rest_total(app.rest_total());
+            let response = handler
+                .handle_request(
+                    &frame(request),
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap();
+            for command in handler.take_commands() {
+                if command == AppRequest::ResetTotals {
+                    app.reset_totals();
+                }
+            }
+            response
+        };
+        let rest_total = |response: &[u8]| f32::from_be_bytes(response[3..7].try_into().unwrap());
+
+        let read_holding = [0x01, 0x03, 0x00, 0x6C, 0x00, 0x02];
+        let read_input = [0x01, 0x04, 0x00, 0x08, 0x00, 0x02];
+        assert_eq!(rest_total(&request(&mut app, &read_holding)), 250.0);
+
+        request(&mut app, &[0x01, 0x05, 0x00, 0x00, 0xFF, 0x00]);
+        assert_eq!(rest_total(&request(&mut app, &read_holding)), 0.0);
+        app.total_flow += 12.5;
+        assert_eq!(rest_total(&request(&mut app, &read_holding)), 12.5);
+        assert_eq!(rest_total(&request(&mut app, &read_input)), 12.5);
+        assert_eq!(app.total_flow, 262.5);
+    }
+
+    #[test]
+    fn test_read_device_identification() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::factory(3);
+        options.set_serial_number(120034);
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        let mut request = |request: &[u8]| {
+            let response = handler
+                .handle_request(
+                    &frame(request),
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .unwrap();
+            response[1..response.len() - 2].to_vec()
+        };
+        // (id, value) pairs after the 7-byte header
+        let objects = |response: &[u8]| {
+            let mut objects = std::vec::Vec::new();
+            let mut rest = &response[7..];
+            while let [id, len, tail @ ..] = rest {
+                let value = std::string::String::from_utf8(tail[..*len as usize].to_vec()).unwrap();
+                objects.push((*id, value));
+                rest = &tail[*len as usize..];
+            }
+            objects
+        };
+
+        // Basic stream
+        let response = request(&[0x01, 0x2B, 0x0E, 0x01, 0x00]);
+        assert_eq!(response[..7], [0x2B, 0x0E, 0x01, 0x83, 0x00, 0x00, 3]);
+        assert_eq!(
+            objects(&response),
+            [
+                (0x00, "ELK".into()),
+                (0x01, "uFlowmeter".into()),
+                (0x02, FIRMWARE_VERSION.into()),
+            ]
+        );
+
+        // Regular stream adds the product name
+        let response = request(&[0x01, 0x2B, 0x0E, 0x02, 0x00]);
+        assert_eq!(response[6], 4);
+        assert_eq!(
+            objects(&response)[3],
+            (0x04, "Ultrasonic flow meter".into())
+        );
+
+        // Extended stream from the product name, and from an unknown id
+        let response = request(&[0x01, 0x2B, 0x0E, 0x03, 0x04]);
+        assert_eq!(
+            objects(&response),
+            [
+                (0x04, "Ultrasonic flow meter".into()),
+                (0x80, "120034".into()),
+                (0x81, "DN80".into()),
+            ]
+        );
+        let response = request(&[0x01, 0x2B, 0x0E, 0x03, 0x50]);
+        assert_eq!(response[6], 6);
+        // An extended start id is outside the basic category
+        let response = request(&[0x01, 0x2B, 0x0E, 0x01, 0x80]);
+        assert_eq!(objects(&response)[0].0, 0x00);
+
+        // Individual access
+        let response = request(&[0x01, 0x2B, 0x0E, 0x04, 0x80]);
+        assert_eq!(response[..7], [0x2B, 0x0E, 0x04, 0x83, 0x00, 0x00, 1]);
+        assert_eq!(objects(&response), [(0x80, "120034".into())]);
+        let response = request(&[0x01, 0x2B, 0x0E, 0x04, 0x03]);
+        assert_eq!(response[..2], [0xAB, 0x02]); // IllegalDataAddress
+
+        // Bad Read Device ID code, other MEI types
+        let response = request(&[0x01, 0x2B, 0x0E, 0x05, 0x00]);
+        assert_eq!(response[..2], [0xAB, 0x03]);
+        let response = request(&[0x01, 0x2B, 0x0D, 0x01, 0x00]);
+        assert_eq!(response[..2], [0xAB, 0x01]);
+    }
 
-        // Try to read from invalid address 0x1000
-        let frame = [0x01, 0x03, 0x10, 0x00, 0x00, 0x01, 0x80, 0xCA];
+    #[test]
+    fn test_comm_event_counter_received() {
+        // A 4-byte 0x0B request is complete without waiting for 8 bytes
+        let mut rx = Receiver::new(Framing::Rtu);
+        let request = frame(&[0x01, 0x0B]);
+        assert_eq!(request.len(), 4);
+        for &byte in &request[..3] {
+            assert_eq!(rx.push(byte), Received::Nothing);
+        }
+        assert_eq!(rx.push(request[3]), Received::Frame);
+        let received = rx.take_frame().unwrap();
+        assert_eq!(received.as_slice(), request.as_slice());
 
+        let mut handler = ModbusHandler::new(0x01);
         let response = handler
             .handle_request(
-                &frame,
-                &mut options,
-                &mut storage,
+                &received,
+                &mut Options::default(),
+                &mut MockStorage::new(),
                 0.0,
                 0.0,
                 0.0,
                 0.0,
-                &mut hour_history,
-                &mut day_history,
-                &mut month_history,
+                &mut MockHistory,
+                &mut MockHistory,
+                &mut MockHistory,
+                &mut MockHistory,
+                &mut TestEvents::new_empty(),
+                &mut AuditAt {
+                    log: &mut TestAudit::new_empty(),
+                    time: 0,
+                },
             )
             .unwrap();
+        assert_eq!(
+            response[1..response.len() - 2],
+            [0x0B, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
 
-        // Should be an exception response
-        assert_eq!(response[0], 0x01); // Slave address
-        assert_eq!(response[1], 0x83); // Function code with error bit (0x03 | 0x80)
-        assert_eq!(response[2], 0x02); // Exception code: IllegalDataAddress
+    #[test]
+    fn test_diagnostics_counters() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        let mut request = |handler: &mut ModbusHandler, frame: &[u8]| {
+            handler
+                .handle_request(
+                    frame,
+                    &mut options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: 0,
+                    },
+                )
+                .map(|response| response[1..response.len() - 2].to_vec())
+        };
+
+        request(&mut handler, &frame(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02])).unwrap();
+        // Another slave, a corrupted frame, an exception
+        assert!(request(&mut handler, &frame(&[0x02, 0x03, 0x00, 0x00, 0x00, 0x02])).is_err());
+        let mut corrupted = frame(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]);
+        corrupted[3] ^= 0x01;
+        assert!(request(&mut handler, &corrupted).is_err());
+        let response = request(&mut handler, &frame(&[0x01, 0x05, 0x00, 0x00, 0x12, 0x34]));
+        assert_eq!(response.unwrap(), [0x85, 0x03]);
+        assert_eq!(
+            handler.counters(),
+            CommCounters {
+                bus_messages: 4,
+                crc_errors: 1,
+                exceptions: 1,
+                slave_messages: 2,
+                events: 1,
+            }
+        );
+
+        // Get Comm Event Counter: not busy, one good request, itself not counted
+        let response = request(&mut handler, &frame(&[0x01, 0x0B])).unwrap();
+        assert_eq!(response, [0x0B, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(handler.counters().events, 1);
+
+        // Counters, each request counted before it is answered
+        let mut diagnostics = |handler: &mut ModbusHandler, sub_function: u8, data: [u8; 2]| {
+            request(
+                handler,
+                &frame(&[0x01, 0x08, 0x00, sub_function, data[0], data[1]]),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            diagnostics(&mut handler, 0x0B, [0, 0]),
+            [0x08, 0x00, 0x0B, 0x00, 6]
+        );
+        assert_eq!(
+            diagnostics(&mut handler, 0x0C, [0, 0]),
+            [0x08, 0x00, 0x0C, 0x00, 1]
+        );
+        assert_eq!(
+            diagnostics(&mut handler, 0x0D, [0, 0]),
+            [0x08, 0x00, 0x0D, 0x00, 1]
+        );
+        assert_eq!(
+            diagnostics(&mut handler, 0x0E, [0, 0]),
+            [0x08, 0x00, 0x0E, 0x00, 7]
+        );
+        assert_eq!(
+            diagnostics(&mut handler, 0x00, [0x12, 0x34]),
+            [0x08, 0x00, 0x00, 0x12, 0x34]
+        );
+
+        // Counters take no data; unsupported sub-functions
+        assert_eq!(diagnostics(&mut handler, 0x0B, [0, 1]), [0x88, 0x03]);
+        assert_eq!(diagnostics(&mut handler, 0x01, [0, 0]), [0x88, 0x01]);
+        assert_eq!(handler.counters().exceptions, 3);
+
+        // Clear: echoed, then only the clear itself is left
+        assert_eq!(
+            diagnostics(&mut handler, 0x0A, [0, 0]),
+            [0x08, 0x00, 0x0A, 0x00, 0x00]
+        );
+        assert_eq!(
+            handler.counters(),
+            CommCounters {
+                events: 1,
+                ..CommCounters::default()
+            }
+        );
     }
 
     #[test]
-    fn test_illegal_quantity() {
-        let handler = ModbusHandler::new(0x01);
+    fn test_ascii_framing() {
+        let mut handler = ModbusHandler::new(0x01);
         let mut options = Options::default();
         let mut storage = MockStorage::new();
         let mut hour_history = MockHistory;
         let mut day_history = MockHistory;
         let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
 
-        // Try to read 0 registers (invalid)
-        let frame = [0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x45, 0xCA];
+        options.set_serial_number(0x12345678);
+        handler.set_framing(Framing::from_mode(1));
+        assert_eq!(handler.framing(), Framing::Ascii);
 
-        let response = handler
-            .handle_request(
-                &frame,
+        let mut request = |handler: &mut ModbusHandler, frame: &[u8]| {
+            handler.handle_request(
+                frame,
                 &mut options,
                 &mut storage,
                 0.0,
@@ -764,12 +3524,213 @@ mod tests {
                 &mut hour_history,
                 &mut day_history,
                 &mut month_history,
+                &mut profile_history,
+                &mut events,
+                &mut AuditAt {
+                    log: &mut audit,
+                    time: 0,
+                },
             )
-            .unwrap();
+        };
 
-        // Should be an exception response
-        assert_eq!(response[0], 0x01); // Slave address
-        assert_eq!(response[1], 0x83); // Function code with error bit
-        assert_eq!(response[2], 0x03); // Exception code: IllegalDataValue
+        // Serial number, answered as ASCII by the handler's framing
+        let response = request(&mut handler, b":010300010002F9\r\n").unwrap();
+        let mut sent = std::vec::Vec::new();
+        handler.framing().write(&response, |byte| sent.push(byte));
+        assert_eq!(sent, b":01030478563412E4\r\n");
+
+        // A bad LRC counts as a communication error; RTU frames are not understood
+        assert!(matches!(
+            request(&mut handler, b":010300010002F8\r\n"),
+            Err(ModbusError::InvalidCrc)
+        ));
+        assert!(request(&mut handler, &frame(&[0x01, 0x03, 0x00, 0x01, 0x00, 0x02])).is_err());
+        assert_eq!(handler.counters().crc_errors, 2);
+
+        handler.set_framing(Framing::Rtu);
+        assert!(request(&mut handler, &frame(&[0x01, 0x03, 0x00, 0x01, 0x00, 0x02])).is_ok());
+    }
+
+    #[test]
+    fn test_restore_defaults() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::factory(2);
+        options.set_serial_number(1234);
+        options.set_slave_address(9);
+        options.set_k11(1.1_f32.to_bits());
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        let mut request = |options: &mut Options, request: &[u8]| {
+            handler
+                .handle_request(
+                    &frame(request),
+                    options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: T0,
+                    },
+                )
+                .unwrap()
+        };
+
+        // Without the key nothing happens
+        for value in [[0x00, 0x00], [0xDF, 0x04], [0x12, 0x00]] {
+            let response = request(&mut options, &[0x01, 0x06, 0x00, 0x70, value[0], value[1]]);
+            assert_eq!(response[1..3], [0x86, 0x03]);
+        }
+        assert_eq!(options.slave_address(), 9);
+        // Read-back is not supported
+        let response = request(&mut options, &[0x01, 0x03, 0x00, 0x70, 0x00, 0x01]);
+        assert_eq!(response[1], 0x83);
+
+        let response = request(&mut options, &[0x01, 0x06, 0x00, 0x70, 0xDF, 0x00]);
+        assert_eq!(response[1], 0x06);
+        assert_eq!(options.slave_address(), 1);
+        assert_eq!(options.sensor_type(), 2);
+        assert_eq!(options.serial_number(), 1234);
+        assert_eq!(options.k11(), 1.1_f32.to_bits());
+
+        // Explicitly reset calibration, recorded in the audit trail
+        let response = request(&mut options, &[0x01, 0x06, 0x00, 0x70, 0xDF, 0x01]);
+        assert_eq!(response[1], 0x06);
+        assert_eq!(options.k11(), 0);
+        assert_eq!(options.serial_number(), 1234);
+        let regs = registers(&request(
+            &mut options,
+            &[0x01, 0x03, 0x60, 0x00, 0x00, 0x0C],
+        ));
+        assert_eq!(regs[6], crate::audit::AuditField::K11.to_u8() as u16);
+
+        let response = request(&mut options, &[0x01, 0x06, 0x00, 0x70, 0xDF, 0x02]);
+        assert_eq!(response[1], 0x06);
+        assert_eq!(options.serial_number(), 0);
+        let saved = Options::load(&mut storage).unwrap();
+        // Everything but the CRC field
+        assert_eq!(
+            saved.into_bytes()[2..],
+            Options::factory(2).into_bytes()[2..]
+        );
+    }
+
+    #[test]
+    fn test_metrology_writes_are_audited() {
+        let mut handler = ModbusHandler::new(0x01);
+        let mut options = Options::default();
+        let mut storage = MockStorage::new();
+        let mut hour_history = MockHistory;
+        let mut day_history = MockHistory;
+        let mut month_history = MockHistory;
+        let mut profile_history = MockHistory;
+        let mut events = TestEvents::new_empty();
+        let mut audit = TestAudit::new_empty();
+
+        let mut request = |options: &mut Options, request: &[u8]| {
+            handler
+                .handle_request(
+                    &frame(request),
+                    options,
+                    &mut storage,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                    &mut hour_history,
+                    &mut day_history,
+                    &mut month_history,
+                    &mut profile_history,
+                    &mut events,
+                    &mut AuditAt {
+                        log: &mut audit,
+                        time: T0,
+                    },
+                )
+                .unwrap()
+        };
+
+        // Serial number is not audited
+        let response = request(&mut options, &[0x01, 0x06, 0x00, 0x01, 0x12, 0x34]);
+        assert_eq!(response[1], 0x06);
+        let regs = registers(&request(
+            &mut options,
+            &[0x01, 0x03, 0x60, 0x00, 0x00, 0x0C],
+        ));
+        assert_eq!(regs[..4], [0, 4, 0, 0]);
+
+        // Register 0x0E holds the middle bytes of zero1
+        let response = request(&mut options, &[0x01, 0x06, 0x00, 0x0E, 0x12, 0x34]);
+        assert_eq!(response[1], 0x06);
+        let zero1 = options.zero1();
+        assert_ne!(zero1, 0);
+        let regs = registers(&request(
+            &mut options,
+            &[0x01, 0x03, 0x60, 0x00, 0x00, 0x0C],
+        ));
+        assert_eq!(
+            regs,
+            [
+                0,
+                3,
+                0,
+                1,
+                (T0 >> 16) as u16,
+                T0 as u16,
+                crate::audit::AuditField::Zero1.to_u8() as u16,
+                1, // Modbus
+                0,
+                0,
+                (zero1 >> 16) as u16,
+                zero1 as u16,
+            ]
+        );
+
+        // Fill the log, then metrology writes are refused and options kept
+        for value in 1..4_u8 {
+            let response = request(&mut options, &[0x01, 0x06, 0x00, 0x0E, 0x00, value]);
+            assert_eq!(response[1], 0x06);
+        }
+        let kept = options.zero1();
+        let response = request(&mut options, &[0x01, 0x06, 0x00, 0x0E, 0x55, 0x55]);
+        assert_eq!(response[1], 0x86);
+        assert_eq!(response[2], 0x03); // IllegalDataValue
+        assert_eq!(options.zero1(), kept);
+        let response = request(
+            &mut options,
+            &[
+                0x01, 0x10, 0x00, 0x0D, 0x00, 0x02, 0x04, 0x00, 0x01, 0x00, 0x00,
+            ],
+        );
+        assert_eq!(response[1], 0x90);
+        assert_eq!(options.zero1(), kept);
+
+        // Other options stay writable
+        let response = request(&mut options, &[0x01, 0x06, 0x00, 0x01, 0x56, 0x78]);
+        assert_eq!(response[1], 0x06);
+
+        // Older records can be selected, not past the first one
+        let response = request(&mut options, &[0x01, 0x06, 0x60, 0x00, 0x00, 0x03]);
+        assert_eq!(response[1], 0x06);
+        let regs = registers(&request(
+            &mut options,
+            &[0x01, 0x03, 0x60, 0x02, 0x00, 0x02],
+        ));
+        assert_eq!(regs, [0, 4]);
+        let response = request(&mut options, &[0x01, 0x06, 0x60, 0x00, 0x00, 0x04]);
+        assert_eq!(response[1], 0x86);
     }
 }
@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use crate::calibration::{CalibData, CalibTable, OutOfRange};
+use crate::history::Interval;
 use embedded_storage::Storage;
 use modular_bitfield::prelude::*;
 
@@ -37,6 +39,75 @@ impl CommType {
     }
 }
 
+/// Factory configuration of one sensor type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FactoryDefaults {
+    /// TDC1000 CONFIG_0..CLOCK_RATE
+    pub tdc1000_regs: [u8; 10],
+    /// TDC7200 CONFIG1..CLOCK_CNTR_STOP_MASK_L
+    pub tdc7200_regs: [u8; 10],
+    pub slave_address: u8,
+    pub comm_type: CommType,
+}
+
+impl FactoryDefaults {
+    /// Defaults for a sensor type (index into the ДУ40..ДУ100 list);
+    /// unknown types get the ДУ40 defaults
+    pub fn for_sensor(sensor_type: u8) -> &'static Self {
+        FACTORY_DEFAULTS
+            .get(sensor_type as usize)
+            .unwrap_or(&FACTORY_DEFAULTS[0])
+    }
+
+    const fn new(tof_timeout: u8, clock_overflow: u16) -> Self {
+        let [overflow_h, overflow_l] = clock_overflow.to_be_bytes();
+        Self {
+            // 31 pulses at f/16; the echo timeout grows with the pipe
+            tdc1000_regs: [
+                0x7F,
+                0x40,
+                0x00,
+                0x0B,
+                0x1F,
+                0x00,
+                0x00,
+                0x00,
+                tof_timeout,
+                0x00,
+            ],
+            tdc7200_regs: [
+                0x00, 0x40, 0x00, 0x07, 0xFF, 0xFF, overflow_h, overflow_l, 0x00, 0x00,
+            ],
+            slave_address: 1,
+            comm_type: CommType::ModBus,
+        }
+    }
+}
+
+/// Per sensor type, in `sensor_type` order: ДУ40, ДУ50, ДУ65, ДУ80, ДУ100
+pub const FACTORY_DEFAULTS: [FactoryDefaults; 5] = [
+    FactoryDefaults::new(0x19, 0x0400),
+    FactoryDefaults::new(0x19, 0x0500),
+    FactoryDefaults::new(0x1A, 0x0680),
+    FactoryDefaults::new(0x1A, 0x0800),
+    FactoryDefaults::new(0x1B, 0x0A00),
+];
+
+/// What `Options::restore_defaults` keeps
+#[cfg_attr(not(test), derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keep {
+    pub serial: bool,
+    pub calibration: bool,
+}
+
+impl Keep {
+    pub const ALL: Self = Self {
+        serial: true,
+        calibration: true,
+    };
+}
+
 #[bitfield]
 #[derive(Debug, Clone, Copy)]
 pub struct Options {
@@ -69,6 +140,10 @@ pub struct Options {
     pub slave_address: B8,
     pub comm_type: B8,
     pub modbus_mode: B8,
+    /// Load-profile interval in minutes (0 = default)
+    pub profile_interval: B8,
+    /// Load-profile depth in records (0 = whole ring)
+    pub profile_depth: B16,
 }
 
 #[cfg_attr(not(test), derive(defmt::Format))]
@@ -76,9 +151,19 @@ pub struct Options {
 pub enum Error<E> {
     Storage,
     WrongCrc,
+    /// Page written by firmware with a newer layout
+    Layout(u16),
     Spi(E),
 }
 
+/// The in-memory test EEPROM fails with `()`
+#[cfg(test)]
+impl From<()> for Error<()> {
+    fn from(_: ()) -> Self {
+        Error::Storage
+    }
+}
+
 #[cfg(not(test))]
 impl From<microchip_eeprom_25lcxx::Error<hal::spi::Error, core::convert::Infallible>>
     for Error<hal::spi::Error>
@@ -94,65 +179,661 @@ impl From<microchip_eeprom_25lcxx::Error<hal::spi::Error, core::convert::Infalli
     }
 }
 
+/// One of the two options pages; saves alternate between them
+#[cfg_attr(not(test), derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    Primary,
+    Secondary,
+}
+
+impl Page {
+    pub fn other(self) -> Self {
+        match self {
+            Page::Primary => Page::Secondary,
+            Page::Secondary => Page::Primary,
+        }
+    }
+}
+
+/// What `load_page` found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loaded {
+    /// Page holding the newest valid options
+    pub page: Page,
+    /// Save sequence number of that page (0 for pages from older firmware)
+    pub sequence: u32,
+    /// The other page failed its CRC or has a newer layout;
+    /// the next `save` rewrites it
+    pub repair: bool,
+}
+
 impl Default for Options {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Layout version stamped on every options page by `save`
+pub const LAYOUT_VERSION: u16 = 2;
+
 impl Options {
     const SIZE: usize = 1024;
     const OFFSET_PRIMARY: u32 = 0;
     const OFFSET_SECONDARY: u32 = 1024;
+    /// Layout version, in the last two bytes of the page. Pages written
+    /// before versioning read 0 there, as `save` zero-fills the page.
+    const LAYOUT_OFFSET: usize = Self::SIZE - 2;
+    /// Save sequence number, u32 before the layout version
+    const SEQUENCE_OFFSET: usize = Self::LAYOUT_OFFSET - 4;
+
+    /// Upgrade steps: `MIGRATIONS[n - 1]` turns a version `n` page into version `n + 1`
+    const MIGRATIONS: [fn(&mut [u8; Self::SIZE]); LAYOUT_VERSION as usize - 1] = [Self::migrate_v1];
+
+    const PROFILE_INTERVAL_DEFAULT: u8 = 15;
+
+    /// Factory options for a sensor type
+    pub fn factory(sensor_type: u8) -> Self {
+        let defaults = FactoryDefaults::for_sensor(sensor_type);
+        let regs = |image: &[u8; 10]| {
+            let mut bytes = [0_u8; 16];
+            bytes[..10].copy_from_slice(image);
+            u128::from_le_bytes(bytes)
+        };
+        let mut options = Self::new();
+        options.set_sensor_type(sensor_type);
+        options.set_tdc1000_regs(regs(&defaults.tdc1000_regs));
+        options.set_tdc7200_regs(regs(&defaults.tdc7200_regs));
+        options.set_slave_address(defaults.slave_address);
+        options.set_comm_type(defaults.comm_type.as_u8());
+        options
+    }
+
+    /// Reset to the factory options of the current sensor type, keeping
+    /// the serial number and calibration as asked
+    pub fn restore_defaults(&mut self, keep: Keep) {
+        let mut options = Self::factory(self.sensor_type());
+        if keep.serial {
+            options.set_serial_number(self.serial_number());
+        }
+        if keep.calibration {
+            for channel in 0..2 {
+                options.copy_calibration(self, channel);
+            }
+        }
+        *self = options;
+    }
+
+    fn copy_calibration(&mut self, from: &Self, channel: usize) {
+        if channel == 0 {
+            self.set_zero1(from.zero1());
+            self.set_v11(from.v11());
+            self.set_v12(from.v12());
+            self.set_v13(from.v13());
+            self.set_k11(from.k11());
+            self.set_k12(from.k12());
+            self.set_k13(from.k13());
+        } else {
+            self.set_zero2(from.zero2());
+            self.set_v21(from.v21());
+            self.set_v22(from.v22());
+            self.set_v23(from.v23());
+            self.set_k21(from.k21());
+            self.set_k22(from.k22());
+            self.set_k23(from.k23());
+        }
+    }
+
+    /// Load-profile interval; 15 minutes unless set to a divisor of an hour
+    pub fn load_profile_interval(&self) -> Interval {
+        let minutes = match self.profile_interval() {
+            minutes @ (1 | 2 | 3 | 4 | 5 | 6 | 10 | 12 | 15 | 20 | 30 | 60) => minutes,
+            _ => Self::PROFILE_INTERVAL_DEFAULT,
+        };
+        Interval::minutes(minutes as u32)
+    }
+
+    /// Load-profile depth in records; unset keeps as many as the ring holds
+    pub fn load_profile_depth(&self) -> u32 {
+        match self.profile_depth() {
+            0 => u32::MAX,
+            depth => depth as u32,
+        }
+    }
+
+    /// Version 1 is every unstamped page: the C++ layout up to
+    /// `modbus_mode`, followed by the load-profile fields on firmware that
+    /// has them. Those read as "default" when zero, so they carry over as
+    /// they are. The C++ firmware kept the zero offsets as whole nanoseconds
+    /// in an i32; version 2 keeps them as f32 like the rest of the calibration.
+    fn migrate_v1(page: &mut [u8; Self::SIZE]) {
+        const LEN: usize = core::mem::size_of::<Options>();
+        let mut bytes = [0u8; LEN];
+        bytes.copy_from_slice(&page[..LEN]);
+        let mut options = Self::from_bytes(bytes);
+        options.set_zero1((options.zero1() as i32 as f32).to_bits());
+        options.set_zero2((options.zero2() as i32 as f32).to_bits());
+        page[..LEN].copy_from_slice(&options.into_bytes());
+    }
 
     pub fn load<S, E>(storage: &mut S) -> Result<Self, Error<E>>
     where
         S: Storage,
         Error<E>: From<S::Error>,
     {
-        assert!(core::mem::size_of::<Options>() < Self::SIZE);
+        Self::load_page(storage).map(|(opt, _)| opt)
+    }
+
+    /// Load the newest valid page and report which one it was.
+    /// With equal sequence numbers (both pages from older firmware) the
+    /// primary page wins.
+    pub fn load_page<S, E>(storage: &mut S) -> Result<(Self, Loaded), Error<E>>
+    where
+        S: Storage,
+        Error<E>: From<S::Error>,
+    {
+        let primary = Self::read_page(storage, Page::Primary);
+        let secondary = Self::read_page(storage, Page::Secondary);
+        match (primary, secondary) {
+            (Ok((opt, sequence)), Ok((other, other_sequence))) => {
+                if other_sequence > sequence {
+                    Ok((other, Self::loaded(Page::Secondary, other_sequence, false)))
+                } else {
+                    Ok((opt, Self::loaded(Page::Primary, sequence, false)))
+                }
+            }
+            (Ok((opt, sequence)), Err(_e)) => {
+                #[cfg(not(test))]
+                defmt::warn!("Secondary options page unusable");
+                Ok((opt, Self::loaded(Page::Primary, sequence, true)))
+            }
+            (Err(_e), Ok((opt, sequence))) => {
+                #[cfg(not(test))]
+                defmt::warn!("Primary options page unusable");
+                Ok((opt, Self::loaded(Page::Secondary, sequence, true)))
+            }
+            (Err(_), Err(e)) => Err(e),
+        }
+    }
+
+    fn loaded(page: Page, sequence: u32, repair: bool) -> Loaded {
+        Loaded {
+            page,
+            sequence,
+            repair,
+        }
+    }
+
+    /// Read one page, check its CRC and migrate it to the current layout.
+    /// Returns the options and the page's save sequence number.
+    fn read_page<S, E>(storage: &mut S, page: Page) -> Result<(Self, u32), Error<E>>
+    where
+        S: Storage,
+        Error<E>: From<S::Error>,
+    {
+        assert!(core::mem::size_of::<Options>() < Self::SEQUENCE_OFFSET);
         let mut data = [0; Self::SIZE];
-        storage.read(Self::OFFSET_PRIMARY, &mut data)?;
+        storage.read(Self::page_offset(page), &mut data)?;
         #[cfg(not(test))]
         defmt::info!("data: {:x}", data);
         let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&data[2..]);
+        if crc != u16::from_le_bytes([data[0], data[1]]) {
+            return Err(Error::WrongCrc);
+        }
+
+        let version =
+            match u16::from_le_bytes([data[Self::LAYOUT_OFFSET], data[Self::LAYOUT_OFFSET + 1]]) {
+                0 => 1,
+                version => version,
+            };
+        if version > LAYOUT_VERSION {
+            return Err(Error::Layout(version));
+        }
+        let mut sequence = [0_u8; 4];
+        sequence.copy_from_slice(&data[Self::SEQUENCE_OFFSET..Self::LAYOUT_OFFSET]);
+        for migrate in &Self::MIGRATIONS[version as usize - 1..] {
+            migrate(&mut data);
+        }
+
         let mut bytes = [0u8; core::mem::size_of::<Options>()];
         bytes.copy_from_slice(&data[0..core::mem::size_of::<Options>()]);
-        let mut opt = Self { bytes };
-        if crc != opt.crc() {
-            #[cfg(not(test))]
-            defmt::warn!("Wrong CRC on primary page {:x} != {:x}", crc, opt.crc());
-            storage.read(Self::OFFSET_SECONDARY, &mut data)?;
-            let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&data[2..]);
-            let mut bytes = [0u8; core::mem::size_of::<Options>()];
-            bytes.copy_from_slice(&data[0..core::mem::size_of::<Options>()]);
-            opt = Self { bytes };
-            if crc != opt.crc() {
-                #[cfg(not(test))]
-                defmt::error!("Wrong CRC on secondary page {:x} != {:x}", crc, opt.crc());
-                return Err(Error::WrongCrc);
-            }
-        }
-        Ok(opt)
+        Ok((Self { bytes }, u32::from_le_bytes(sequence)))
     }
 
-    pub fn save<S, E>(&mut self, storage: &mut S) -> Result<(), Error<E>>
+    /// Write the options over the older (or unusable) page with the next
+    /// sequence number, so the newest valid page survives a power cut
+    /// during the write. Returns the page written.
+    pub fn save<S, E>(&mut self, storage: &mut S) -> Result<Page, Error<E>>
     where
         S: Storage,
         Error<E>: From<S::Error>,
     {
-        assert!(core::mem::size_of::<Options>() < Self::SIZE);
+        let (page, sequence) = match Self::load_page(storage) {
+            Ok((_, loaded)) => (loaded.page.other(), loaded.sequence.wrapping_add(1)),
+            Err(_) => (Page::Primary, 1),
+        };
+
+        assert!(core::mem::size_of::<Options>() < Self::SEQUENCE_OFFSET);
         let mut data = [0_u8; Self::SIZE];
         let src = self.into_bytes();
         data[..src.len()].copy_from_slice(&src);
+        data[Self::SEQUENCE_OFFSET..Self::LAYOUT_OFFSET].copy_from_slice(&sequence.to_le_bytes());
+        data[Self::LAYOUT_OFFSET..].copy_from_slice(&LAYOUT_VERSION.to_le_bytes());
         let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&data[2..]);
         self.set_crc(crc);
         let src = self.into_bytes();
         data[..src.len()].copy_from_slice(&src);
-        storage.write(Self::OFFSET_PRIMARY, &data)?;
-        storage.write(Self::OFFSET_SECONDARY, &data)?;
+        storage.write(Self::page_offset(page), &data)?;
         #[cfg(not(test))]
         defmt::info!("data: {:x}", data);
+        Ok(page)
+    }
+
+    /// Calibration of `channel` (0 or 1). The values are kept as f32 bits.
+    pub fn calib_table(&self, channel: usize) -> Result<CalibTable, OutOfRange> {
+        let (zero, v, k) = match channel {
+            0 => (
+                self.zero1(),
+                [self.v11(), self.v12(), self.v13()],
+                [self.k11(), self.k12(), self.k13()],
+            ),
+            1 => (
+                self.zero2(),
+                [self.v21(), self.v22(), self.v23()],
+                [self.k21(), self.k22(), self.k23()],
+            ),
+            _ => return Err(OutOfRange),
+        };
+        let mut table = CalibTable {
+            dtof0: f32::from_bits(zero),
+            ..Default::default()
+        };
+        for (point, (v, k)) in table.data.iter_mut().zip(v.into_iter().zip(k)) {
+            *point = CalibData {
+                v: f32::from_bits(v),
+                k: f32::from_bits(k),
+            };
+        }
+        Ok(table)
+    }
+
+    /// Replace the calibration of `channel` (0 or 1) if `table` is valid
+    pub fn set_calib_table(
+        &mut self,
+        channel: usize,
+        table: &CalibTable,
+    ) -> Result<(), OutOfRange> {
+        if !table.is_valid() {
+            return Err(OutOfRange);
+        }
+        let [p1, p2, p3] = table.data;
+        match channel {
+            0 => {
+                self.set_zero1(table.dtof0.to_bits());
+                self.set_v11(p1.v.to_bits());
+                self.set_v12(p2.v.to_bits());
+                self.set_v13(p3.v.to_bits());
+                self.set_k11(p1.k.to_bits());
+                self.set_k12(p2.k.to_bits());
+                self.set_k13(p3.k.to_bits());
+            }
+            1 => {
+                self.set_zero2(table.dtof0.to_bits());
+                self.set_v21(p1.v.to_bits());
+                self.set_v22(p2.v.to_bits());
+                self.set_v23(p3.v.to_bits());
+                self.set_k21(p1.k.to_bits());
+                self.set_k22(p2.k.to_bits());
+                self.set_k23(p3.k.to_bits());
+            }
+            _ => return Err(OutOfRange),
+        }
         Ok(())
     }
+
+    fn page_offset(page: Page) -> u32 {
+        match page {
+            Page::Primary => Self::OFFSET_PRIMARY,
+            Page::Secondary => Self::OFFSET_SECONDARY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_storage::MemStorage;
+
+    /// Storage holding both option pages
+    fn with_pages(primary: &[u8; Options::SIZE], secondary: &[u8; Options::SIZE]) -> MemStorage {
+        let mut storage = MemStorage::new();
+        storage.data[..Options::SIZE].copy_from_slice(primary);
+        storage.data[Options::SIZE..2 * Options::SIZE].copy_from_slice(secondary);
+        storage
+    }
+
+    /// A zero-filled page holding `fields` at their byte offsets, with a valid CRC
+    fn page(fields: &[(usize, &[u8])]) -> [u8; Options::SIZE] {
+        let mut page = [0_u8; Options::SIZE];
+        for (offset, bytes) in fields {
+            page[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&page[2..]);
+        page[..2].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    /// Version 1 as first shipped: the C++ layout, 111 bytes up to `modbus_mode`
+    fn v1_fixture() -> [u8; Options::SIZE] {
+        page(&[
+            (2, &1234_u32.to_le_bytes()),    // serial_number
+            (6, &[3]),                       // sensor_type
+            (27, &(-100_i32).to_le_bytes()), // zero1, whole ns
+            (35, &4.0_f32.to_le_bytes()),    // v11
+            (55, &8.0_f32.to_le_bytes()),    // v23
+            (59, &1.5_f32.to_le_bytes()),    // k11
+            (79, &0.5_f32.to_le_bytes()),    // k23
+            (108, &[7]),                     // slave_address
+            (109, &[2]),                     // comm_type
+        ])
+    }
+
+    /// Version 1 with the load-profile fields, still unstamped
+    fn v1_profile_fixture() -> [u8; Options::SIZE] {
+        page(&[
+            (2, &1234_u32.to_le_bytes()),
+            (6, &[3]),
+            (27, &(-100_i32).to_le_bytes()),
+            (35, &4.0_f32.to_le_bytes()),
+            (55, &8.0_f32.to_le_bytes()),
+            (59, &1.5_f32.to_le_bytes()),
+            (79, &0.5_f32.to_le_bytes()),
+            (108, &[7]),
+            (109, &[2]),
+            (111, &[30]),                  // profile_interval
+            (112, &960_u16.to_le_bytes()), // profile_depth
+        ])
+    }
+
+    /// Version 2: version 1 with the layout stamp and an f32 zero offset
+    fn v2_fixture() -> [u8; Options::SIZE] {
+        page(&[
+            (2, &1234_u32.to_le_bytes()),
+            (6, &[3]),
+            (27, &(-100_f32).to_le_bytes()),
+            (35, &4.0_f32.to_le_bytes()),
+            (55, &8.0_f32.to_le_bytes()),
+            (59, &1.5_f32.to_le_bytes()),
+            (79, &0.5_f32.to_le_bytes()),
+            (108, &[7]),
+            (109, &[2]),
+            (111, &[30]),
+            (112, &960_u16.to_le_bytes()),
+            (Options::LAYOUT_OFFSET, &2_u16.to_le_bytes()),
+        ])
+    }
+
+    fn assert_common_fields(opt: &Options) {
+        assert_eq!(opt.serial_number(), 1234);
+        assert_eq!(opt.sensor_type(), 3);
+        assert_eq!(f32::from_bits(opt.zero1()), -100.0);
+        assert_eq!(f32::from_bits(opt.zero2()), 0.0);
+        assert_eq!(f32::from_bits(opt.k11()), 1.5);
+        assert_eq!(f32::from_bits(opt.k23()), 0.5);
+        for channel in 0..2 {
+            assert!(opt.calib_table(channel).unwrap().is_valid());
+        }
+        assert_eq!(opt.calib_table(0).unwrap().dtof0, -100.0);
+        assert_eq!(opt.calib_table(1).unwrap().data[2].v, 8.0);
+        assert_eq!(opt.slave_address(), 7);
+        assert_eq!(opt.comm_type(), 2);
+    }
+
+    #[test]
+    fn test_load_v1_layout() {
+        let mut storage = with_pages(&v1_fixture(), &v1_fixture());
+        let (opt, loaded) = Options::load_page(&mut storage).unwrap();
+        assert_eq!(loaded.page, Page::Primary);
+        assert_eq!(loaded.sequence, 0);
+        assert!(!loaded.repair);
+        assert_common_fields(&opt);
+        // Fields added later take their defaults
+        assert_eq!(opt.load_profile_interval(), Interval::minutes(15));
+        assert_eq!(opt.load_profile_depth(), u32::MAX);
+    }
+
+    #[test]
+    fn test_load_v1_layout_with_load_profile() {
+        let mut storage = with_pages(&v1_profile_fixture(), &v1_profile_fixture());
+        let opt = Options::load(&mut storage).unwrap();
+        assert_common_fields(&opt);
+        assert_eq!(opt.load_profile_interval(), Interval::minutes(30));
+        assert_eq!(opt.load_profile_depth(), 960);
+    }
+
+    #[test]
+    fn test_load_v2_layout() {
+        let mut storage = with_pages(&v2_fixture(), &v2_fixture());
+        let opt = Options::load(&mut storage).unwrap();
+        assert_common_fields(&opt);
+        assert_eq!(opt.load_profile_interval(), Interval::minutes(30));
+        assert_eq!(opt.load_profile_depth(), 960);
+    }
+
+    #[test]
+    fn test_save_stamps_current_layout() {
+        let mut storage = with_pages(&v1_fixture(), &v1_fixture());
+        let mut opt = Options::load(&mut storage).unwrap();
+        assert_eq!(opt.save(&mut storage).unwrap(), Page::Secondary);
+        let stamp = &storage.data[Options::SIZE + Options::LAYOUT_OFFSET..2 * Options::SIZE];
+        assert_eq!(stamp, LAYOUT_VERSION.to_le_bytes());
+        let reloaded = Options::load(&mut storage).unwrap();
+        assert_eq!(reloaded.into_bytes(), opt.into_bytes());
+    }
+
+    #[test]
+    fn test_newer_layout_is_refused() {
+        let future = page(&[(Options::LAYOUT_OFFSET, &(LAYOUT_VERSION + 1).to_le_bytes())]);
+        let mut storage = with_pages(&future, &future);
+        assert!(matches!(
+            Options::load(&mut storage),
+            Err(Error::Layout(version)) if version == LAYOUT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_bad_primary_falls_back_to_secondary() {
+        let mut primary = v2_fixture();
+        primary[10] ^= 0xFF;
+        let mut storage = with_pages(&primary, &v1_fixture());
+        let (opt, loaded) = Options::load_page(&mut storage).unwrap();
+        assert_eq!(loaded.page, Page::Secondary);
+        assert!(loaded.repair);
+        assert_common_fields(&opt);
+
+        // The next save goes over the bad page
+        let mut opt = opt;
+        assert_eq!(opt.save(&mut storage).unwrap(), Page::Primary);
+        let (_, loaded) = Options::load_page(&mut storage).unwrap();
+        assert_eq!(loaded.page, Page::Primary);
+        assert_eq!(loaded.sequence, 1);
+        assert!(!loaded.repair);
+
+        let mut storage = with_pages(&primary, &primary);
+        assert!(matches!(Options::load(&mut storage), Err(Error::WrongCrc)));
+    }
+
+    #[test]
+    fn test_newest_page_wins() {
+        let mut older = v2_fixture();
+        let mut newer = v2_fixture();
+        for (page, sequence, slave) in [(&mut older, 4_u32, 7), (&mut newer, 5, 9)] {
+            page[108] = slave;
+            page[Options::SEQUENCE_OFFSET..Options::LAYOUT_OFFSET]
+                .copy_from_slice(&sequence.to_le_bytes());
+            let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&page[2..]);
+            page[..2].copy_from_slice(&crc.to_le_bytes());
+        }
+        for (primary, secondary, page) in [
+            (&older, &newer, Page::Secondary),
+            (&newer, &older, Page::Primary),
+        ] {
+            let mut storage = with_pages(primary, secondary);
+            let (opt, loaded) = Options::load_page(&mut storage).unwrap();
+            assert_eq!(loaded.page, page);
+            assert_eq!(loaded.sequence, 5);
+            assert_eq!(opt.slave_address(), 9);
+        }
+    }
+
+    #[test]
+    fn test_saves_alternate_pages() {
+        let mut storage = MemStorage::new();
+        let mut opt = Options::default();
+        for n in 1..=6_u32 {
+            opt.set_slave_address(n as u8);
+            let written = opt.save(&mut storage).unwrap();
+            let expected = if n % 2 == 1 {
+                Page::Primary
+            } else {
+                Page::Secondary
+            };
+            assert_eq!(written, expected);
+            let (reloaded, loaded) = Options::load_page(&mut storage).unwrap();
+            assert_eq!(loaded.page, written);
+            assert_eq!(loaded.sequence, n);
+            assert_eq!(reloaded.slave_address(), n as u8);
+        }
+    }
+
+    #[test]
+    fn test_power_cut_during_save_keeps_previous_options() {
+        let mut image = MemStorage::new();
+        let mut opt = Options::default();
+        opt.set_slave_address(7);
+        opt.save(&mut image).unwrap();
+        opt.save(&mut image).unwrap();
+        for cut in (0..Options::SIZE).step_by(37) {
+            let mut storage = image.clone();
+            storage.written = 0;
+            storage.power_cut_after = Some(cut);
+            let mut updated = opt;
+            updated.set_slave_address(9);
+            assert!(updated.save(&mut storage).is_err());
+            storage.power_cut_after = None;
+
+            let (reloaded, loaded) = Options::load_page(&mut storage).unwrap();
+            assert_eq!(reloaded.slave_address(), 7, "cut at {}", cut);
+            assert_eq!(loaded.sequence, 2);
+        }
+    }
+
+    #[test]
+    fn test_calib_table_round_trip() {
+        let table = CalibTable {
+            dtof0: -1.25,
+            data: [
+                CalibData { v: 1.5, k: 1.02 },
+                CalibData { v: 8.0, k: 0.98 },
+                CalibData { v: 40.0, k: 1.01 },
+            ],
+        };
+        let mut opt = Options::default();
+        opt.set_calib_table(1, &table).unwrap();
+        assert_eq!(opt.zero2(), (-1.25_f32).to_bits());
+        assert_eq!(opt.k23(), 1.01_f32.to_bits());
+        assert_eq!(opt.zero1(), 0);
+
+        let mut storage = MemStorage::new();
+        opt.save(&mut storage).unwrap();
+        let loaded = Options::load(&mut storage).unwrap().calib_table(1).unwrap();
+        assert_eq!(loaded.dtof0, table.dtof0);
+        for (loaded, point) in loaded.data.iter().zip(table.data) {
+            assert_eq!((loaded.v, loaded.k), (point.v, point.k));
+        }
+    }
+
+    #[test]
+    fn test_invalid_calib_table_is_refused() {
+        let mut opt = Options::default();
+        let mut table = opt.calib_table(0).unwrap();
+        table.data[0] = CalibData { v: 10.0, k: 5.0 };
+        assert_eq!(opt.set_calib_table(0, &table), Err(OutOfRange));
+        assert_eq!(
+            opt.set_calib_table(2, &CalibTable::default()),
+            Err(OutOfRange)
+        );
+        assert!(matches!(opt.calib_table(2), Err(OutOfRange)));
+        assert_eq!(opt.k11(), 0);
+    }
+
+    #[test]
+    fn test_factory_defaults_per_sensor_type() {
+        for (sensor_type, defaults) in FACTORY_DEFAULTS.iter().enumerate() {
+            let opt = Options::factory(sensor_type as u8);
+            assert_eq!(opt.sensor_type(), sensor_type as u8);
+            assert_eq!(opt.slave_address(), defaults.slave_address);
+            assert_eq!(CommType::from_u8(opt.comm_type()), defaults.comm_type);
+            let tdc1000 = opt.tdc1000_regs().to_le_bytes();
+            assert_eq!(tdc1000[..10], defaults.tdc1000_regs);
+            let tdc7200 = opt.tdc7200_regs().to_le_bytes();
+            assert_eq!(tdc7200[..10], defaults.tdc7200_regs);
+        }
+        assert_ne!(FACTORY_DEFAULTS[0], FACTORY_DEFAULTS[4]);
+        assert_eq!(FactoryDefaults::for_sensor(200), &FACTORY_DEFAULTS[0]);
+    }
+
+    #[test]
+    fn test_restore_defaults_keeps_serial_and_calibration() {
+        let calibrated = {
+            let mut opt = Options::factory(3);
+            opt.set_serial_number(1234);
+            opt.set_slave_address(42);
+            opt.set_enable_negative(1);
+            opt.set_tdc1000_regs(0x55);
+            opt.set_calib_table(
+                0,
+                &CalibTable {
+                    dtof0: 0.5,
+                    data: [CalibData { v: 10.0, k: 1.1 }; 3],
+                },
+            )
+            .ok();
+            opt.set_k21(1.05_f32.to_bits());
+            opt
+        };
+
+        let mut opt = calibrated;
+        opt.restore_defaults(Keep::ALL);
+        let mut expected = Options::factory(3);
+        assert_eq!(opt.serial_number(), 1234);
+        assert_eq!(opt.k21(), 1.05_f32.to_bits());
+        assert_eq!(opt.zero1(), calibrated.zero1());
+        assert_eq!(opt.v13(), calibrated.v13());
+        assert_eq!(opt.slave_address(), 1);
+        assert_eq!(opt.enable_negative(), 0);
+        assert_eq!(opt.tdc1000_regs(), expected.tdc1000_regs());
+
+        // Everything, only the sensor type survives
+        let mut opt = calibrated;
+        opt.restore_defaults(Keep {
+            serial: false,
+            calibration: false,
+        });
+        assert_eq!(opt.into_bytes(), expected.into_bytes());
+
+        let mut opt = calibrated;
+        opt.restore_defaults(Keep {
+            serial: true,
+            calibration: false,
+        });
+        expected.set_serial_number(1234);
+        assert_eq!(opt.into_bytes(), expected.into_bytes());
+    }
+
+    #[test]
+    fn test_blank_eeprom_is_not_options() {
+        let mut storage = MemStorage::new();
+        assert!(Options::load(&mut storage).is_err());
+    }
 }
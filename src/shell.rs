@@ -7,14 +7,26 @@
 //! Commands (matching C++ version):
 //!   date get           — print current RTC time as unix timestamp
 //!   date set <N>       — set RTC time (unix timestamp)
-//!   zero               — trigger auto-zero calibration
-//!   calibrate <1-3> <lph> — calibration point
+//!   zero               — auto-zero calibration, saves the zero offsets
+//!   calibrate <1-3> <lph> — calibrate a point at a reference flow, saves it
 //!   set_serial <N>     — set device serial number
 //!   set_verbose <0|1>  — enable/disable verbose console output
 //!   get_settings       — dump TDC1000/TDC7200 register config
 //!   get_calibration    — dump calibration data
+//!   events [N]         — list the newest N events (default 10)
+//!   audit [N]          — list the newest N metrology changes (default 10)
+//!   defaults confirm [calibration] [serial]
+//!                      — restore factory defaults; serial number and
+//!                        calibration are kept unless named
+//!   config dump        — print the options as name=value lines and a CRC
+//!   config load        — take such lines back, ending with the crc= line
+//!   modbus stats       — print the Modbus communication counters
 //!   help               — list commands
 
+use crate::audit::AuditRecord;
+use crate::events::Event;
+use crate::modbus_handler::CommCounters;
+use crate::options::Keep;
 use heapless::String;
 use heapless::Vec;
 
@@ -30,6 +42,24 @@ pub enum ShellResult {
     NotAShellCommand,
     /// Command parse error
     Error(&'static str),
+    /// List the newest N events; the caller reads the log and prints `event_line`s
+    ReadEvents(u8),
+    /// List the newest N audit records; the caller reads the log and prints `audit_line`s
+    ReadAudit(u8),
+    /// Collect measurements for `calibration::auto_zero` and save the
+    /// zero offsets
+    AutoZero,
+    /// Collect measurements for `calibration::auto_calibrate` for point 1-3
+    /// at a reference flow in l/h and save the point
+    Calibrate(u8, u32),
+    /// Restore factory defaults, keeping what `Keep` says
+    RestoreDefaults(Keep),
+    /// Print `config_text::dump` of the options
+    DumpConfig,
+    /// Feed the following lines to a `config_text::ConfigLoad`
+    LoadConfig,
+    /// Print `modbus_stats` of the handler's counters
+    ModbusStats,
 }
 
 /// Process a line of text input as a shell command.
@@ -78,6 +108,21 @@ pub fn process_line(line: &[u8]) -> ShellResult {
     if eq(cmd, b"get_calibration") {
         return cmd_get_calibration();
     }
+    if eq(cmd, b"events") {
+        return cmd_events(&tokens[1..]);
+    }
+    if eq(cmd, b"audit") {
+        return cmd_audit(&tokens[1..]);
+    }
+    if eq(cmd, b"defaults") {
+        return cmd_defaults(&tokens[1..]);
+    }
+    if eq(cmd, b"config") {
+        return cmd_config(&tokens[1..]);
+    }
+    if eq(cmd, b"modbus") {
+        return cmd_modbus(&tokens[1..]);
+    }
 
     ShellResult::NotAShellCommand
 }
@@ -94,6 +139,11 @@ fn help_text() -> String<256> {
          set_verbose <0|1>\r\n\
          get_settings\r\n\
          get_calibration\r\n\
+         events [N]\r\n\
+         audit [N]\r\n\
+         defaults confirm [calibration] [serial]\r\n\
+         config dump|load\r\n\
+         modbus stats\r\n\
          help\r\n")
 }
 
@@ -127,8 +177,7 @@ fn cmd_zero(args: &[&[u8]]) -> ShellResult {
     if !args.is_empty() {
         return ShellResult::Error("Usage: zero");
     }
-    // Auto-zero calibration triggered
-    ShellResult::Ok(lit("Zero autocalibration in progress\r\n"))
+    ShellResult::AutoZero
 }
 
 fn cmd_calibrate(args: &[&[u8]]) -> ShellResult {
@@ -139,14 +188,10 @@ fn cmd_calibrate(args: &[&[u8]]) -> ShellResult {
         Some(n) if (1..=3).contains(&n) => n,
         _ => return ShellResult::Error("coef must be 1, 2, or 3"),
     };
-    let _lph = match parse_u32(args[1]) {
-        Some(v) => v,
-        None => return ShellResult::Error("invalid lph value"),
-    };
-    let mut out: String<256> = lit("Calibration K");
-    out.push_str(&fmt_u8(coef_no)).ok();
-    out.push_str(" in progress\r\n").ok();
-    ShellResult::Ok(out)
+    match parse_u32(args[1]) {
+        Some(lph) => ShellResult::Calibrate(coef_no, lph),
+        None => ShellResult::Error("invalid lph value"),
+    }
 }
 
 fn cmd_set_serial(args: &[&[u8]]) -> ShellResult {
@@ -183,6 +228,131 @@ fn cmd_get_calibration() -> ShellResult {
     ShellResult::Ok(lit("Dump calibration via Modbus\r\n"))
 }
 
+fn cmd_events(args: &[&[u8]]) -> ShellResult {
+    match args.first() {
+        None => ShellResult::ReadEvents(10),
+        Some(arg) => match parse_u8(arg) {
+            Some(count) if count > 0 => ShellResult::ReadEvents(count),
+            _ => ShellResult::Error("Usage: events [1-255]"),
+        },
+    }
+}
+
+/// One line of the `events` listing: `<index> <unix_ts> <code> <name> <param>`
+pub fn event_line(index: u32, event: &Event) -> String<64> {
+    let mut out: String<64> = String::new();
+    out.push_str(&fmt_u32(index)).ok();
+    out.push(' ').ok();
+    out.push_str(&fmt_u32(event.time)).ok();
+    out.push(' ').ok();
+    out.push_str(&fmt_u32(event.code.to_u16() as u32)).ok();
+    out.push(' ').ok();
+    out.push_str(event.code.name()).ok();
+    out.push(' ').ok();
+    out.push_str(&fmt_u32(event.param)).ok();
+    out.push_str("\r\n").ok();
+    out
+}
+
+fn cmd_audit(args: &[&[u8]]) -> ShellResult {
+    match args.first() {
+        None => ShellResult::ReadAudit(10),
+        Some(arg) => match parse_u8(arg) {
+            Some(count) if count > 0 => ShellResult::ReadAudit(count),
+            _ => ShellResult::Error("Usage: audit [1-255]"),
+        },
+    }
+}
+
+fn cmd_defaults(args: &[&[u8]]) -> ShellResult {
+    const USAGE: &str = "Usage: defaults confirm [calibration] [serial]";
+    // The confirmation word guards against a stray line wiping the setup
+    if args.first().map(|arg| eq(arg, b"confirm")) != Some(true) {
+        return ShellResult::Error(USAGE);
+    }
+    let mut keep = Keep::ALL;
+    for arg in &args[1..] {
+        if eq(arg, b"calibration") {
+            keep.calibration = false;
+        } else if eq(arg, b"serial") {
+            keep.serial = false;
+        } else {
+            return ShellResult::Error(USAGE);
+        }
+    }
+    ShellResult::RestoreDefaults(keep)
+}
+
+fn cmd_config(args: &[&[u8]]) -> ShellResult {
+    match args {
+        [arg] if eq(arg, b"dump") => ShellResult::DumpConfig,
+        [arg] if eq(arg, b"load") => ShellResult::LoadConfig,
+        _ => ShellResult::Error("Usage: config dump | config load"),
+    }
+}
+
+fn cmd_modbus(args: &[&[u8]]) -> ShellResult {
+    match args {
+        [arg] if eq(arg, b"stats") => ShellResult::ModbusStats,
+        _ => ShellResult::Error("Usage: modbus stats"),
+    }
+}
+
+/// Reply to `modbus stats`, one `<name> <count>` line per counter
+pub fn modbus_stats(counters: &CommCounters) -> String<256> {
+    let mut out: String<256> = String::new();
+    for (name, count) in [
+        ("bus_messages", counters.bus_messages),
+        ("crc_errors", counters.crc_errors),
+        ("exceptions", counters.exceptions),
+        ("slave_messages", counters.slave_messages),
+        ("events", counters.events),
+    ] {
+        out.push_str(name).ok();
+        out.push(' ').ok();
+        out.push_str(&fmt_u32(count as u32)).ok();
+        out.push_str("\r\n").ok();
+    }
+    out
+}
+
+/// Reply once a `zero` (`point` None) or `calibrate` result is saved
+pub fn calibration_saved(point: Option<u8>) -> String<256> {
+    match point {
+        None => lit("Zero saved\r\n"),
+        Some(point) => {
+            let mut out: String<256> = lit("Calibration K");
+            out.push_str(&fmt_u8(point)).ok();
+            out.push_str(" saved\r\n").ok();
+            out
+        }
+    }
+}
+
+/// Reply once `defaults` is saved
+pub fn defaults_restored() -> String<256> {
+    lit("Factory defaults restored\r\n")
+}
+
+/// One line of the `audit` listing: `<counter> <unix_ts> <field> <source> <old> <new>`,
+/// values as raw hex
+pub fn audit_line(record: &AuditRecord) -> String<64> {
+    let mut out: String<64> = String::new();
+    out.push_str(&fmt_u32(record.counter)).ok();
+    out.push(' ').ok();
+    out.push_str(&fmt_u32(record.time)).ok();
+    out.push(' ').ok();
+    out.push_str(record.field.name()).ok();
+    out.push(' ').ok();
+    out.push_str(record.source.name()).ok();
+    out.push(' ').ok();
+    out.push_str(&fmt_hex32(record.old)).ok();
+    out.push(' ').ok();
+    out.push_str(&fmt_hex32(record.new)).ok();
+    out.push_str("\r\n").ok();
+    out
+}
+
 // ─── Helpers ──────────────────────────────────────────────────────────
 
 // ─── Helpers ──────────────────────────────────────────────────────────
@@ -258,6 +428,21 @@ fn fmt_u32(mut v: u32) -> String<11> {
     String::try_from(s).unwrap_or_else(|_| String::new())
 }
 
+fn fmt_hex32(v: u32) -> String<10> {
+    let mut out: String<10> = String::new();
+    out.push_str("0x").ok();
+    for shift in (0..8).rev() {
+        let digit = ((v >> (shift * 4)) & 0xF) as u8;
+        let c = if digit < 10 {
+            b'0' + digit
+        } else {
+            b'A' + digit - 10
+        };
+        out.push(c as char).ok();
+    }
+    out
+}
+
 fn fmt_u8(v: u8) -> String<4> {
     let s = fmt_u32(v as u32);
     String::try_from(s.as_str()).unwrap_or_else(|_| String::new())
@@ -304,17 +489,91 @@ mod tests {
     #[test]
     fn test_zero() {
         match process_line(b"zero\r\n") {
-            ShellResult::Ok(s) => assert!(s.contains("autocalibration")),
-            _ => panic!("expected Ok"),
+            ShellResult::AutoZero => {}
+            _ => panic!("expected AutoZero"),
         }
     }
 
     #[test]
     fn test_calibrate() {
         match process_line(b"calibrate 1 1500\r\n") {
-            ShellResult::Ok(s) => assert!(s.contains("K1")),
-            _ => panic!("expected Ok"),
+            ShellResult::Calibrate(1, 1500) => {}
+            _ => panic!("expected Calibrate"),
+        }
+    }
+
+    #[test]
+    fn test_calibration_saved() {
+        assert_eq!(calibration_saved(None).as_str(), "Zero saved\r\n");
+        assert_eq!(
+            calibration_saved(Some(2)).as_str(),
+            "Calibration K2 saved\r\n"
+        );
+    }
+
+    #[test]
+    fn test_defaults() {
+        match process_line(b"defaults confirm\r\n") {
+            ShellResult::RestoreDefaults(keep) => assert_eq!(keep, Keep::ALL),
+            _ => panic!("expected RestoreDefaults"),
+        }
+        match process_line(b"defaults confirm serial calibration\r\n") {
+            ShellResult::RestoreDefaults(keep) => {
+                assert!(!keep.serial);
+                assert!(!keep.calibration);
+            }
+            _ => panic!("expected RestoreDefaults"),
+        }
+        for line in [
+            &b"defaults\r\n"[..],
+            b"defaults calibration\r\n",
+            b"defaults confirm everything\r\n",
+        ] {
+            match process_line(line) {
+                ShellResult::Error(_) => {}
+                _ => panic!("expected Error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        match process_line(b"config dump\r\n") {
+            ShellResult::DumpConfig => {}
+            _ => panic!("expected DumpConfig"),
+        }
+        match process_line(b"config load\r\n") {
+            ShellResult::LoadConfig => {}
+            _ => panic!("expected LoadConfig"),
+        }
+        match process_line(b"config save\r\n") {
+            ShellResult::Error(_) => {}
+            _ => panic!("expected Error"),
+        }
+    }
+
+    #[test]
+    fn test_modbus_stats() {
+        match process_line(b"modbus stats\r\n") {
+            ShellResult::ModbusStats => {}
+            _ => panic!("expected ModbusStats"),
         }
+        match process_line(b"modbus\r\n") {
+            ShellResult::Error(_) => {}
+            _ => panic!("expected Error"),
+        }
+        let counters = CommCounters {
+            bus_messages: 120,
+            crc_errors: 3,
+            exceptions: 0,
+            slave_messages: 65535,
+            events: 97,
+        };
+        assert_eq!(
+            modbus_stats(&counters).as_str(),
+            "bus_messages 120\r\ncrc_errors 3\r\nexceptions 0\r\n\
+             slave_messages 65535\r\nevents 97\r\n"
+        );
     }
 
     #[test]
@@ -341,6 +600,63 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_events() {
+        assert!(matches!(
+            process_line(b"events\r\n"),
+            ShellResult::ReadEvents(10)
+        ));
+        assert!(matches!(
+            process_line(b"events 3\r\n"),
+            ShellResult::ReadEvents(3)
+        ));
+        assert!(matches!(
+            process_line(b"events 0\r\n"),
+            ShellResult::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_event_line() {
+        let event = Event {
+            time: 1700000000,
+            code: crate::events::EventCode::TdcTimeout,
+            param: 0,
+        };
+        assert_eq!(
+            event_line(2, &event).as_str(),
+            "2 1700000000 9 tdc_timeout 0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_audit() {
+        assert!(matches!(
+            process_line(b"audit\r\n"),
+            ShellResult::ReadAudit(10)
+        ));
+        assert!(matches!(
+            process_line(b"audit 256\r\n"),
+            ShellResult::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_audit_line() {
+        let record = AuditRecord {
+            counter: 3,
+            time: 1700000000,
+            field: crate::audit::AuditField::K12,
+            source: crate::audit::Source::Modbus,
+            old: 0,
+            new: 0x3F80_0000,
+        };
+        assert_eq!(
+            audit_line(&record).as_str(),
+            "3 1700000000 k12 modbus 0x00000000 0x3F800000\r\n"
+        );
+    }
+
     #[test]
     fn test_unknown_command_is_not_shell() {
         match process_line(b"\x01\x03\x00\x00\x00\x0a") {
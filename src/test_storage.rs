@@ -0,0 +1,63 @@
+//! In-memory EEPROM shared by the storage tests
+
+use embedded_storage::{ReadStorage, Storage};
+use std::collections::BTreeMap;
+use std::vec::Vec;
+
+/// EEPROM page, the unit `page_writes` counts in
+pub const PAGE_SIZE: usize = 256;
+
+/// In-memory EEPROM (erased to 0xFF) that can lose power mid-write
+#[derive(Clone)]
+pub struct MemStorage {
+    pub data: Vec<u8>,
+    /// Bytes that still reach the array before the power goes away
+    pub power_cut_after: Option<usize>,
+    pub written: usize,
+    pub reads: usize,
+    /// Writes per page, counted on the page a write starts in
+    pub page_writes: BTreeMap<usize, u32>,
+}
+
+impl MemStorage {
+    pub fn new() -> Self {
+        Self {
+            data: std::vec![0xFF; 16 * 1024],
+            power_cut_after: None,
+            written: 0,
+            reads: 0,
+            page_writes: BTreeMap::new(),
+        }
+    }
+}
+
+impl ReadStorage for MemStorage {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        let start = offset as usize;
+        bytes.copy_from_slice(self.data.get(start..start + bytes.len()).ok_or(())?);
+        self.reads += 1;
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl Storage for MemStorage {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        let start = offset as usize;
+        *self.page_writes.entry(start / PAGE_SIZE).or_default() += 1;
+        let target = self.data.get_mut(start..start + bytes.len()).ok_or(())?;
+        for (cell, byte) in target.iter_mut().zip(bytes) {
+            if self.power_cut_after == Some(self.written) {
+                return Err(());
+            }
+            *cell = *byte;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+#![allow(dead_code)]
+
+//! Totalizer checkpoints
+//!
+//! The running totals live in RAM (`App`) and would be lost on a reset. They
+//! are checkpointed to EEPROM every `CHECKPOINT_INTERVAL` seconds and whenever
+//! a period closes, and restored in `init`, so a reset loses at most one
+//! interval of volume. Checkpoints rotate through `SLOTS` slots, one per
+//! EEPROM page, so each page sees 1/SLOTS of the writes. Every slot carries a
+//! sequence number and a CRC: restore takes the newest valid one, and a
+//! checkpoint cut by a power loss falls back to the one before.
+//!
+//! `Options::total` and friends are not used for this, as an options save
+//! rewrites a whole 1 KB options page.
+
+use crate::history::Error;
+use embedded_storage::Storage;
+
+type Result<T> = core::result::Result<T, Error>;
+
+/// Running totals, in the units of `App::flow` summed per measurement
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Totals {
+    /// Since the meter was commissioned
+    pub total: f32,
+    pub hour: f32,
+    pub day: f32,
+    pub month: f32,
+    /// Current load-profile interval
+    pub profile: f32,
+    /// `total` at the last totals reset; the resettable total is `total - rest`
+    pub rest: f32,
+}
+
+/// Sequence u32, time u32, six f32 totals, CRC u16
+const RECORD_SIZE: usize = 34;
+const RECORD_CRC: usize = RECORD_SIZE - 2;
+/// 25LC1024 page
+const PAGE_SIZE: usize = 256;
+
+/// `SLOTS` checkpoint slots at `OFFSET` from the statistics page
+pub struct TotalsStore<const OFFSET: usize, const SLOTS: u32> {
+    /// Sequence number of the next checkpoint
+    next: u32,
+    /// Time of the newest checkpoint
+    last_time: u32,
+}
+
+impl<const OFFSET: usize, const SLOTS: u32> TotalsStore<OFFSET, SLOTS> {
+    const OFFSET_OF_STAT_PAGE: usize = 4096;
+    /// First slot, aligned to an EEPROM page
+    const OFFSET: usize = (Self::OFFSET_OF_STAT_PAGE + OFFSET).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    /// Slots plus up to a page lost to the alignment
+    pub const SIZE_ON_FLASH: usize = (SLOTS as usize + 1) * PAGE_SIZE;
+    /// Seconds between checkpoints
+    pub const CHECKPOINT_INTERVAL: u32 = 300;
+
+    /// Open the store, continuing after the newest valid checkpoint
+    pub fn new<S: Storage>(storage: &mut S) -> Result<Self> {
+        let mut store = Self::new_empty();
+        for slot in 0..SLOTS {
+            if let Some((sequence, time, _)) = store.read_slot(storage, slot)? {
+                if sequence >= store.next {
+                    store.next = sequence + 1;
+                    store.last_time = time;
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    pub const fn new_empty() -> Self {
+        Self {
+            next: 0,
+            last_time: 0,
+        }
+    }
+
+    /// Checkpoints written since the store was created
+    pub fn sequence(&self) -> u32 {
+        self.next
+    }
+
+    /// A checkpoint is due `CHECKPOINT_INTERVAL` after the last one,
+    /// or at once if the clock went back past it
+    pub fn due(&self, now: u32) -> bool {
+        self.next == 0 || now < self.last_time || now - self.last_time >= Self::CHECKPOINT_INTERVAL
+    }
+
+    /// Write `totals` to the next slot
+    pub fn checkpoint<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        now: u32,
+        totals: &Totals,
+    ) -> Result<()> {
+        let mut record = [0_u8; RECORD_SIZE];
+        record[..4].copy_from_slice(&self.next.to_le_bytes());
+        record[4..8].copy_from_slice(&now.to_le_bytes());
+        let values = [
+            totals.total,
+            totals.hour,
+            totals.day,
+            totals.month,
+            totals.profile,
+            totals.rest,
+        ];
+        for (chunk, value) in record[8..RECORD_CRC].chunks_mut(4).zip(values) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&record[..RECORD_CRC]);
+        record[RECORD_CRC..].copy_from_slice(&crc.to_le_bytes());
+        storage
+            .write(Self::slot_offset(self.next % SLOTS), &record)
+            .map_err(|_| Error::Storage)?;
+        self.next += 1;
+        self.last_time = now;
+        Ok(())
+    }
+
+    /// Totals of the newest checkpoint, `None` before the first one
+    pub fn restore<S: Storage>(&self, storage: &mut S) -> Result<Option<Totals>> {
+        if self.next == 0 {
+            return Ok(None);
+        }
+        let sequence = self.next - 1;
+        Ok(self
+            .read_slot(storage, sequence % SLOTS)?
+            .filter(|(stored, _, _)| *stored == sequence)
+            .map(|(_, _, totals)| totals))
+    }
+
+    fn read_slot<S: Storage>(
+        &self,
+        storage: &mut S,
+        slot: u32,
+    ) -> Result<Option<(u32, u32, Totals)>> {
+        let mut record = [0_u8; RECORD_SIZE];
+        storage
+            .read(Self::slot_offset(slot), &mut record)
+            .map_err(|_| Error::Storage)?;
+        let crc = crc16::State::<crc16::CCITT_FALSE>::calculate(&record[..RECORD_CRC]);
+        if crc != u16::from_le_bytes([record[RECORD_CRC], record[RECORD_CRC + 1]]) {
+            return Ok(None);
+        }
+        let word = |at: usize| [record[at], record[at + 1], record[at + 2], record[at + 3]];
+        Ok(Some((
+            u32::from_le_bytes(word(0)),
+            u32::from_le_bytes(word(4)),
+            Totals {
+                total: f32::from_le_bytes(word(8)),
+                hour: f32::from_le_bytes(word(12)),
+                day: f32::from_le_bytes(word(16)),
+                month: f32::from_le_bytes(word(20)),
+                profile: f32::from_le_bytes(word(24)),
+                rest: f32::from_le_bytes(word(28)),
+            },
+        )))
+    }
+
+    fn slot_offset(slot: u32) -> u32 {
+        (Self::OFFSET + slot as usize * PAGE_SIZE) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::App;
+    use crate::test_storage::MemStorage;
+
+    /// Unaligned offset, as the store follows the other logs
+    type Store = TotalsStore<100, 4>;
+
+    const T0: u32 = 1_700_000_000;
+    /// `AppRequest::Process` period
+    const TICK: u32 = 5;
+    const FLOW: f32 = 2.0;
+
+    /// Run the meter for `ticks` measurements the way `AppRequest::Process` does
+    fn run(app: &mut App, store: &mut Store, storage: &mut MemStorage, now: &mut u32, ticks: u32) {
+        for _ in 0..ticks {
+            *now += TICK;
+            app.flow = FLOW;
+            app.accumulate();
+            if store.due(*now) {
+                store.checkpoint(storage, *now, &app.totals()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_store() {
+        let mut storage = MemStorage::new();
+        let store = Store::new(&mut storage).unwrap();
+        assert_eq!(store.restore(&mut storage).unwrap(), None);
+        assert!(store.due(T0));
+    }
+
+    #[test]
+    fn test_reset_loses_at_most_one_interval() {
+        let mut storage = MemStorage::new();
+        let mut store = Store::new(&mut storage).unwrap();
+        let mut app = App::new();
+        let mut now = T0;
+        let max_lost = (Store::CHECKPOINT_INTERVAL / TICK) as f32 * FLOW;
+
+        for ticks in [1, 59, 60, 61, 150, 7] {
+            run(&mut app, &mut store, &mut storage, &mut now, ticks);
+            let before = app.totals();
+
+            // Reset: RAM is gone, init restores the newest checkpoint
+            store = Store::new(&mut storage).unwrap();
+            app = App::new();
+            app.restore_totals(&store.restore(&mut storage).unwrap().unwrap());
+            let after = app.totals();
+            assert!(after.total <= before.total);
+            assert!(
+                before.total - after.total <= max_lost,
+                "lost {} after {} ticks",
+                before.total - after.total,
+                ticks
+            );
+            assert!(before.month - after.month <= max_lost);
+        }
+    }
+
+    #[test]
+    fn test_reset_totals_survives_a_reset() {
+        let mut storage = MemStorage::new();
+        let mut store = Store::new(&mut storage).unwrap();
+        let mut app = App::new();
+        let mut now = T0;
+        run(&mut app, &mut store, &mut storage, &mut now, 10);
+
+        // `AppRequest::ResetTotals` checkpoints at once
+        app.reset_totals();
+        store.checkpoint(&mut storage, now, &app.totals()).unwrap();
+        assert_eq!(app.rest_total(), 0.0);
+        run(&mut app, &mut store, &mut storage, &mut now, 3);
+
+        let store = Store::new(&mut storage).unwrap();
+        let mut restored = App::new();
+        restored.restore_totals(&store.restore(&mut storage).unwrap().unwrap());
+        assert_eq!(restored.total_flow, 10.0 * FLOW);
+        assert_eq!(restored.rest_total(), 0.0);
+        assert_eq!(app.rest_total(), 3.0 * FLOW);
+    }
+
+    #[test]
+    fn test_power_cut_during_checkpoint() {
+        let mut image = MemStorage::new();
+        let mut store = Store::new(&mut image).unwrap();
+        let old = Totals {
+            total: 10.0,
+            hour: 1.0,
+            day: 2.0,
+            month: 3.0,
+            profile: 0.5,
+            rest: 4.0,
+        };
+        store.checkpoint(&mut image, T0, &old).unwrap();
+        for cut in 0..RECORD_SIZE {
+            let mut storage = image.clone();
+            let mut store = Store::new(&mut storage).unwrap();
+            storage.written = 0;
+            storage.power_cut_after = Some(cut);
+            let new = Totals { total: 20.0, ..old };
+            assert!(store.checkpoint(&mut storage, T0 + 300, &new).is_err());
+            storage.power_cut_after = None;
+
+            // The torn checkpoint is skipped, the previous one is restored
+            let store = Store::new(&mut storage).unwrap();
+            assert_eq!(
+                store.restore(&mut storage).unwrap(),
+                Some(old),
+                "cut at {}",
+                cut
+            );
+        }
+    }
+
+    #[test]
+    fn test_checkpoints_rotate_over_pages() {
+        let mut storage = MemStorage::new();
+        let mut store = Store::new(&mut storage).unwrap();
+        let totals = Totals::default();
+        for n in 0..40 {
+            store
+                .checkpoint(&mut storage, T0 + n * 300, &totals)
+                .unwrap();
+        }
+        // Each slot has its own page and they wear evenly
+        assert_eq!(storage.page_writes.len(), 4);
+        assert!(storage.page_writes.values().all(|&writes| writes == 10));
+        assert!(storage
+            .page_writes
+            .keys()
+            .all(|&page| page * PAGE_SIZE >= 4096 + 100));
+
+        let store = Store::new(&mut storage).unwrap();
+        assert_eq!(store.sequence(), 40);
+        assert!(!store.due(T0 + 39 * 300 + 299));
+        assert!(store.due(T0 + 39 * 300 + 300));
+        // Clock set back: checkpoint at once
+        assert!(store.due(T0));
+    }
+}
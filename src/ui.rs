@@ -11,8 +11,9 @@
 //!   - `MenuList` = ring buffer of screens with Up/Down navigation
 //!   - `MenuController` = 4 MenuLists + current_menu pointer + key dispatch
 
-use crate::apps::AppRequest;
+use crate::apps::{AppRequest, FIRMWARE_VERSION};
 use crate::gui::{CharacterDisplay, HistoryType, UiEvent};
+use crate::history::Status;
 use crate::App;
 use alloc::string::String;
 use core::fmt::Write;
@@ -23,14 +24,17 @@ use core::fmt::Write;
 #[cfg_attr(not(test), derive(defmt::Format))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ScreenId {
-    // Main menu (14 items matching C++)
+    // Main menu (14 items matching C++, plus the resettable total, the load
+    // profile and the event log)
     HourConsumption,
     DayConsumption,
     TotalVolume,
+    RestVolume,
     Uptime,
     HourHistory,
     DayHistory,
     MonthHistory,
+    ProfileHistory,
     DateTime,
     Version,
     Bootloader,
@@ -38,6 +42,7 @@ pub enum ScreenId {
     SlaveAddress,
     Muster,
     Negative,
+    EventLog,
     // User menu (2 items)
     Channel1,
     Channel2,
@@ -64,12 +69,15 @@ const COMM_TYPES: [&str; 4] = ["ВЫКЛ", "M-BUS", "ModBus", "Выход 4-20mA
 const SENSOR_TYPES: [&str; 5] = ["ДУ40", "ДУ50", "ДУ65", "ДУ80", "ДУ100"];
 const ON_OFF: [&str; 2] = ["ВЫКЛ", "ВКЛ"];
 
+/// Screens a menu holds, the main menu has 17
+const MENU_CAPACITY: usize = 20;
+
 // ─── MenuList ────────────────────────────────────────────────────────
 /// Ring buffer of screen IDs. Up/Down navigates.
 /// If current screen doesn't consume the key, List does navigation.
 /// Ported from C++ UI::List + RingList.
 pub struct MenuList {
-    items: [ScreenId; 16],
+    items: [ScreenId; MENU_CAPACITY],
     count: usize,
     index: usize,
 }
@@ -83,14 +91,14 @@ impl Default for MenuList {
 impl MenuList {
     pub fn new() -> Self {
         Self {
-            items: [ScreenId::HourConsumption; 16],
+            items: [ScreenId::HourConsumption; MENU_CAPACITY],
             count: 0,
             index: 0,
         }
     }
 
     pub fn add(&mut self, screen: ScreenId) {
-        if self.count < 16 {
+        if self.count < MENU_CAPACITY {
             self.items[self.count] = screen;
             self.count += 1;
         }
@@ -183,6 +191,17 @@ pub enum DateTimeEditItem {
     Year,
 }
 
+/// What the hour/day history screens show for the selected period
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryView {
+    #[default]
+    Flow,
+    /// Peak instantaneous flow and its time
+    Max,
+    /// Minimum instantaneous flow and its time
+    Min,
+}
+
 /// Pattern for ClickableLabel (version screen secret pattern)
 #[derive(Debug, Clone, Copy, Default)]
 pub struct PatternState {
@@ -206,6 +225,7 @@ pub struct MenuController {
     pub sensor_type: EditBoxState,
     pub slave_address: EditNumberState,
     pub datetime_item: DateTimeEditItem,
+    pub history_view: HistoryView,
     pub pattern: PatternState,
     /// Idle counter for auto-hide (C++ IDLE_TIMEOUT)
     pub idle_counter: u8,
@@ -224,10 +244,12 @@ impl MenuController {
         main_menu.add(ScreenId::HourConsumption);
         main_menu.add(ScreenId::DayConsumption);
         main_menu.add(ScreenId::TotalVolume);
+        main_menu.add(ScreenId::RestVolume);
         main_menu.add(ScreenId::Uptime);
         main_menu.add(ScreenId::HourHistory);
         main_menu.add(ScreenId::DayHistory);
         main_menu.add(ScreenId::MonthHistory);
+        main_menu.add(ScreenId::ProfileHistory);
         main_menu.add(ScreenId::DateTime);
         main_menu.add(ScreenId::Version);
         main_menu.add(ScreenId::Bootloader);
@@ -235,6 +257,7 @@ impl MenuController {
         main_menu.add(ScreenId::SlaveAddress);
         main_menu.add(ScreenId::Muster);
         main_menu.add(ScreenId::Negative);
+        main_menu.add(ScreenId::EventLog);
 
         // Build user menu — matches C++ init_user()
         let mut user_menu = MenuList::new();
@@ -267,6 +290,7 @@ impl MenuController {
                 editable: false,
             },
             datetime_item: DateTimeEditItem::default(),
+            history_view: HistoryView::default(),
             pattern: PatternState::default(),
             idle_counter: 0,
         }
@@ -313,12 +337,13 @@ impl MenuController {
         self.current_menu = menu;
         // On new menu selection, reset to first item
         // C++ shows current()->show() but doesn't reset index
-        None
+        self.screen_entered()
     }
 
     /// Deselect — exit menu (C++ Menu::deselect)
     pub fn deselect(&mut self) -> Option<AppRequest> {
         self.current_menu = MenuId::None;
+        self.history_view = HistoryView::Flow;
         self.main_menu.reset();
         self.user_menu.reset();
         self.configuration_menu.reset();
@@ -330,16 +355,30 @@ impl MenuController {
         self.current_list().current()
     }
 
+    /// Load what the current screen shows once it becomes current
+    fn screen_entered(&self) -> Option<AppRequest> {
+        match self.current_screen() {
+            // Newest event, including those logged since the last visit
+            ScreenId::EventLog => Some(AppRequest::SetEvent(0)),
+            _ => None,
+        }
+    }
+
     // ─── Title line ──────────────────────────────────────────────────
     pub fn title(&self, screen: ScreenId) -> &'static str {
         match screen {
             ScreenId::HourConsumption => "Расход     Qм3/ч",
             ScreenId::DayConsumption => "Расход   Qм3/сут",
             ScreenId::TotalVolume => "Объем      Vм3  ",
+            ScreenId::RestVolume => "Объем сбр.  Vм3 ",
             ScreenId::Uptime => "Время работы",
-            ScreenId::HourHistory => "Расход за",
-            ScreenId::DayHistory => "Расход за",
+            ScreenId::HourHistory | ScreenId::DayHistory => match self.history_view {
+                HistoryView::Flow => "Расход за",
+                HistoryView::Max => "Макс. расход",
+                HistoryView::Min => "Мин. расход",
+            },
             ScreenId::MonthHistory => "Расход за",
+            ScreenId::ProfileHistory => "Профиль нагрузки",
             ScreenId::DateTime => "Дата/Время",
             ScreenId::Version => "Версия ПО",
             ScreenId::Bootloader => "Обновить ПО",
@@ -347,6 +386,7 @@ impl MenuController {
             ScreenId::SlaveAddress => "Адрес",
             ScreenId::Muster => "Поверка",
             ScreenId::Negative => "Реверс",
+            ScreenId::EventLog => "Журнал событий",
             ScreenId::Channel1 => "01         луч 1",
             ScreenId::Channel2 => "02         луч 2",
             ScreenId::SensorType => "Датчик",
@@ -368,24 +408,78 @@ impl MenuController {
             ScreenId::TotalVolume => {
                 write!(s, "{:.3}", app.month_flow).ok();
             }
+            ScreenId::RestVolume => {
+                write!(s, "{:.3}", app.rest_total()).ok();
+            }
             ScreenId::Uptime => {
                 write!(s, "{:.0}m", app.num).ok();
             }
-            ScreenId::HourHistory | ScreenId::DayHistory | ScreenId::MonthHistory => {
+            ScreenId::HourHistory | ScreenId::DayHistory
+                if self.history_view != HistoryView::Flow =>
+            {
+                // Extreme of the selected period and the time of day it was measured
+                let extreme = app
+                    .history_state
+                    .stats
+                    .map(|stats| match self.history_view {
+                        HistoryView::Min => (stats.min, stats.min_at),
+                        _ => (stats.max, stats.max_at),
+                    });
+                match extreme {
+                    Some((flow, at)) => {
+                        write!(
+                            s,
+                            "{:.3} в {:02}:{:02}",
+                            flow,
+                            at % 86400 / 3600,
+                            at % 3600 / 60
+                        )
+                        .ok();
+                    }
+                    None => s.push_str("--"),
+                }
+            }
+            ScreenId::HourHistory
+            | ScreenId::DayHistory
+            | ScreenId::MonthHistory
+            | ScreenId::ProfileHistory => {
                 // History screens show date/time + value — handled in render
-                if let Some(flow) = app.history_state.flow {
-                    write!(s, "{:.3}", flow).ok();
-                } else {
-                    s.push_str("None");
+                match app.history_state.flow {
+                    Some(_) if app.history_state.status == Status::GapFilled => s.push_str("--"),
+                    Some(flow) => {
+                        write!(s, "{:.3}", flow).ok();
+                    }
+                    None => s.push_str("None"),
                 }
             }
+            ScreenId::EventLog => match app.event_state.event {
+                // Event code, then day.month hour:minute (UTC)
+                Some(event) => match time::OffsetDateTime::from_unix_timestamp(event.time as i64) {
+                    Ok(dt) => {
+                        write!(
+                            s,
+                            "E{:02} {:02}.{:02} {:02}:{:02}",
+                            event.code.to_u16(),
+                            dt.day(),
+                            dt.month() as u8,
+                            dt.hour(),
+                            dt.minute()
+                        )
+                        .ok();
+                    }
+                    Err(_) => {
+                        write!(s, "E{:02}", event.code.to_u16()).ok();
+                    }
+                },
+                None => s.push_str("Нет событий"),
+            },
             ScreenId::DateTime => {
                 // DateTime screen has its own rendering
                 let dt = &app.datetime;
                 write!(s, "{:02}:{:02}:{:02}", dt.hour(), dt.minute(), dt.second()).ok();
             }
             ScreenId::Version => {
-                write!(s, "0.1.{}", app.num % 1000).ok();
+                s.push_str(FIRMWARE_VERSION);
             }
             ScreenId::Bootloader => {
                 // Button — no value line
@@ -473,14 +567,14 @@ impl MenuController {
                     ScreenId::SlaveAddress => comm_cursor != 0,
                     _ => true,
                 });
-                None
+                self.screen_entered()
             }
             UiEvent::Down => {
                 self.current_list_mut().prev_enabled(|s: ScreenId| match s {
                     ScreenId::SlaveAddress => comm_cursor != 0,
                     _ => true,
                 });
-                None
+                self.screen_entered()
             }
             _ => None,
         }
@@ -492,13 +586,14 @@ impl MenuController {
         &mut self,
         screen: ScreenId,
         event: UiEvent,
-        _app: &App,
+        app: &App,
     ) -> Option<AppRequest> {
         match screen {
             // ── LiveMeter screens: no keys consumed (navigation handled by List) ──
             ScreenId::HourConsumption
             | ScreenId::DayConsumption
             | ScreenId::TotalVolume
+            | ScreenId::RestVolume
             | ScreenId::Channel1
             | ScreenId::Channel2
             | ScreenId::SerialNumber => None,
@@ -555,6 +650,19 @@ impl MenuController {
             ScreenId::HourHistory => self.history_key_event(event, HistoryType::Hour),
             ScreenId::DayHistory => self.history_key_event(event, HistoryType::Day),
             ScreenId::MonthHistory => self.history_key_event(event, HistoryType::Month),
+            ScreenId::ProfileHistory => self.history_key_event(event, HistoryType::Profile),
+
+            // ── Event log: Right = older, Left = newer, Enter = newest ──
+            ScreenId::EventLog => {
+                let state = &app.event_state;
+                let index = match event {
+                    UiEvent::Right if state.index + 1 < state.count => state.index + 1,
+                    UiEvent::Left if state.index > 0 => state.index - 1,
+                    UiEvent::Enter => 0,
+                    _ => return None,
+                };
+                Some(AppRequest::SetEvent(index))
+            }
 
             // ── Calibration: just a label ──
             ScreenId::Calibration => None,
@@ -741,8 +849,22 @@ impl MenuController {
     }
 
     // ─── History key handler ──
-    fn history_key_event(&mut self, event: UiEvent, _htype: HistoryType) -> Option<AppRequest> {
+    fn history_key_event(&mut self, event: UiEvent, htype: HistoryType) -> Option<AppRequest> {
         match event {
+            // Left/Right switch between the period's flow and its extremes
+            UiEvent::Left | UiEvent::Right
+                if matches!(htype, HistoryType::Hour | HistoryType::Day) =>
+            {
+                self.history_view = match (self.history_view, event) {
+                    (HistoryView::Flow, UiEvent::Right) => HistoryView::Max,
+                    (HistoryView::Max, UiEvent::Right) => HistoryView::Min,
+                    (HistoryView::Min, UiEvent::Right) => HistoryView::Flow,
+                    (HistoryView::Flow, _) => HistoryView::Min,
+                    (HistoryView::Max, _) => HistoryView::Flow,
+                    (HistoryView::Min, _) => HistoryView::Max,
+                };
+                None
+            }
             UiEvent::Enter => {
                 // Enter date editing mode
                 // TODO: implement date navigation
@@ -887,8 +1009,8 @@ mod tests {
 
         // Navigate to version screen
         let always_enabled = |_s: ScreenId| true;
-        for _ in 0..8 {
-            ctrl.main_menu.next_enabled(|_s: ScreenId| true); // index 8 = Version
+        for _ in 0..10 {
+            ctrl.main_menu.next_enabled(|_s: ScreenId| true); // index 10 = Version
         }
         assert_eq!(ctrl.current_screen(), ScreenId::Version);
 
@@ -917,7 +1039,7 @@ mod tests {
 
         // Navigate to version
         let always_enabled = |_s: ScreenId| true;
-        for _ in 0..8 {
+        for _ in 0..9 {
             ctrl.main_menu.next_enabled(|_s: ScreenId| true);
         }
 
@@ -935,9 +1057,9 @@ mod tests {
         let app = test_app();
         ctrl.select(MenuId::Main);
 
-        // Navigate to comm type screen (index 10)
+        // Navigate to comm type screen (index 12)
         let always_enabled = |_s: ScreenId| true;
-        for _ in 0..10 {
+        for _ in 0..12 {
             ctrl.main_menu.next_enabled(|_s: ScreenId| true);
         }
         assert_eq!(ctrl.current_screen(), ScreenId::CommType);
@@ -966,8 +1088,8 @@ mod tests {
         // Enable slave address by setting comm_type to M-BUS
         ctrl.comm_type.cursor = 1; // M-BUS
 
-        // Navigate to slave address (index 11)
-        for _ in 0..11 {
+        // Navigate to slave address (index 13)
+        for _ in 0..13 {
             ctrl.main_menu.next_enabled(|_s: ScreenId| true);
         }
         assert_eq!(ctrl.current_screen(), ScreenId::SlaveAddress);
@@ -992,9 +1114,9 @@ mod tests {
         let app = test_app();
         ctrl.select(MenuId::Main);
 
-        // Navigate to bootloader (index 9)
+        // Navigate to bootloader (index 11)
         let always_enabled = |_s: ScreenId| true;
-        for _ in 0..9 {
+        for _ in 0..11 {
             ctrl.main_menu.next_enabled(|_s: ScreenId| true);
         }
 
@@ -1044,4 +1166,97 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_history_flow_extremes() {
+        let mut ctrl = MenuController::new();
+        let mut app = test_app();
+        ctrl.select(MenuId::Main);
+        for _ in 0..5 {
+            ctrl.main_menu.next_enabled(|_s: ScreenId| true);
+        }
+        assert_eq!(ctrl.current_screen(), ScreenId::HourHistory);
+        app.history_state.flow = Some(12.5);
+        app.history_state.stats = Some(crate::history::FlowStats {
+            min: 0.25,
+            min_at: 1_710_510_300, // 13:45 UTC
+            max: 4.5,
+            max_at: 1_710_511_200, // 14:00 UTC
+        });
+
+        assert_eq!(ctrl.format_value(ScreenId::HourHistory, &app), "12.500");
+        ctrl.key_event(UiEvent::Right, &app);
+        assert_eq!(ctrl.title(ScreenId::HourHistory), "Макс. расход");
+        assert_eq!(
+            ctrl.format_value(ScreenId::HourHistory, &app),
+            "4.500 в 14:00"
+        );
+        ctrl.key_event(UiEvent::Right, &app);
+        assert_eq!(ctrl.title(ScreenId::HourHistory), "Мин. расход");
+        assert_eq!(
+            ctrl.format_value(ScreenId::HourHistory, &app),
+            "0.250 в 13:45"
+        );
+        ctrl.key_event(UiEvent::Left, &app);
+        assert_eq!(ctrl.history_view, HistoryView::Max);
+
+        // Gap-filled or old records carry no extremes
+        app.history_state.stats = None;
+        assert_eq!(ctrl.format_value(ScreenId::HourHistory, &app), "--");
+        // The month ring and the load profile keep no extremes
+        assert_eq!(ctrl.format_value(ScreenId::MonthHistory, &app), "12.500");
+        assert_eq!(ctrl.format_value(ScreenId::ProfileHistory, &app), "12.500");
+
+        ctrl.deselect();
+        assert_eq!(ctrl.history_view, HistoryView::Flow);
+    }
+
+    #[test]
+    fn test_rest_volume_screen() {
+        let ctrl = MenuController::new();
+        let mut app = test_app();
+        app.total_flow = 10.0;
+        app.reset_totals();
+        app.total_flow = 12.5;
+        assert_eq!(ctrl.format_value(ScreenId::RestVolume, &app), "2.500");
+    }
+
+    #[test]
+    fn test_event_log_screen() {
+        use crate::events::{Event, EventCode};
+
+        let mut ctrl = MenuController::new();
+        let mut app = test_app();
+        ctrl.select(MenuId::Main);
+        // Entering the screen, here back from the first one, loads the newest event
+        assert_eq!(
+            ctrl.key_event(UiEvent::Down, &app),
+            Some(AppRequest::SetEvent(0))
+        );
+        assert_eq!(ctrl.current_screen(), ScreenId::EventLog);
+        assert_eq!(ctrl.format_value(ScreenId::EventLog, &app), "Нет событий");
+        assert_eq!(ctrl.key_event(UiEvent::Right, &app), None);
+
+        app.event_state.count = 2;
+        app.event_state.event = Some(Event {
+            time: 1_710_510_300, // 2024-03-15 13:45 UTC
+            code: EventCode::TdcTimeout,
+            param: 0,
+        });
+        assert_eq!(
+            ctrl.format_value(ScreenId::EventLog, &app),
+            "E09 15.03 13:45"
+        );
+        assert_eq!(
+            ctrl.key_event(UiEvent::Right, &app),
+            Some(AppRequest::SetEvent(1))
+        );
+        app.event_state.index = 1;
+        // Oldest event: Right does nothing, Left goes back
+        assert_eq!(ctrl.key_event(UiEvent::Right, &app), None);
+        assert_eq!(
+            ctrl.key_event(UiEvent::Left, &app),
+            Some(AppRequest::SetEvent(0))
+        );
+    }
 }
@@ -8,6 +8,7 @@ mod ui_history_tests {
         DayKind, HistoryWidget as BaseHistoryWidget, HourKind, MonthKind,
     };
     use crate::gui::{CharacterDisplay, DateTimeItems, HistoryType, UiEvent, Widget};
+    use crate::history::Status;
     use crate::{Actions, App};
 
     type HistoryWidget = BaseHistoryWidget<HourKind>;
@@ -340,6 +341,17 @@ mod ui_history_tests {
         assert!(widget.value.state.contains("None"));
     }
 
+    #[test]
+    fn test_history_widget_update_gap_filled() {
+        let mut widget = HistoryWidget::new();
+        let mut app = MockAppState::new().with_flow(0.0).to_app();
+        app.history_state.status = Status::GapFilled;
+
+        widget.update(&app);
+
+        assert_eq!(widget.value.state.trim(), "--");
+    }
+
     #[test]
     fn test_history_widget_update_date_format() {
         let mut widget = HistoryWidget::new();